@@ -25,14 +25,16 @@ fn do_command<I: Iterator<Item = String>>(mut args: I) -> Result<()> {
         cmd.arg(arg);
     }
 
-    let child = cmd
+    let mut child = cmd
         .stdout(process::Stdio::piped())
         .stderr(process::Stdio::null())
         .spawn()
         .expect("spawning did not succeed");
 
-    let stdout = child.stdout.expect("stdout must be there");
-    each_line(BufReader::new(stdout.with_timeout(Duration::new(5, 0))))
+    let stdout = child.stdout.take().expect("stdout must be there");
+    let rslt = each_line(BufReader::new(stdout.with_timeout(Duration::new(5, 0))));
+    child.wait()?;
+    rslt
 }
 
 fn main() {
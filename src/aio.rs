@@ -0,0 +1,244 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An adapter for running the wrappers on `async-io`, the reactor behind `smol`.
+
+use async_io::{Async, IoSafe, Timer};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::fmt;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::utils;
+use super::{TimeoutReader, TimeoutStream, TimeoutWriter};
+
+/// The `AsyncTimeout` struct gives an `async_io::Async` handle the same timeout
+/// semantics as the blocking wrappers in this crate.
+///
+/// Each `poll_read` or `poll_write` that cannot complete right away starts a timer
+/// for the corresponding timeout; if the operation is still pending when the timer
+/// fires, it fails with an `io::ErrorKind::TimedOut` variant as the value of
+/// `io::Error`. Just like the blocking wrappers, the timeout bounds each individual
+/// operation, not a whole sequence of them.
+///
+/// An `AsyncTimeout` is usually created by converting one of the blocking wrappers,
+/// which carries over the timeouts configured on it. Like `Async::new`, the
+/// conversion puts the underlying descriptor into nonblocking mode.
+pub struct AsyncTimeout<H> {
+    io: Async<H>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_timer: Option<Timer>,
+    write_timer: Option<Timer>,
+}
+
+impl<H: AsFd> AsyncTimeout<H> {
+    /// Create a new `AsyncTimeout` with optional read and write timeouts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::aio::AsyncTimeout;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let stream = AsyncTimeout::new(stream, Duration::new(5, 0), Duration::new(1, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<R, W>(handle: H, read_timeout: R, write_timeout: W) -> Result<AsyncTimeout<H>>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        let scaled = |timeout: Duration| utils::ms_to_duration(utils::timeout_ms(timeout));
+        AsyncTimeout::with_scaled_timeouts(
+            handle,
+            read_timeout.into().map(scaled),
+            write_timeout.into().map(scaled),
+        )
+    }
+
+    /// Create a new `AsyncTimeout` from timeouts that the scale factor has already
+    /// been applied to.
+    fn with_scaled_timeouts(
+        handle: H,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> Result<AsyncTimeout<H>> {
+        Ok(AsyncTimeout {
+            io: Async::new(handle)?,
+            read_timeout,
+            write_timeout,
+            read_timer: None,
+            write_timer: None,
+        })
+    }
+
+    /// Gets a reference to the underlying `Async` handle.
+    pub fn get_ref(&self) -> &Async<H> {
+        &self.io
+    }
+
+    /// Unwraps this `AsyncTimeout`, returning the underlying handle in blocking mode.
+    pub fn into_inner(self) -> Result<H> {
+        self.io.into_inner()
+    }
+}
+
+impl<H: AsFd> fmt::Debug for AsyncTimeout<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncTimeout")
+            .field("fd", &self.io.as_fd().as_raw_fd())
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .finish()
+    }
+}
+
+/// Poll `timer` for an operation that is still pending, starting it on first use.
+fn poll_timeout(
+    timer: &mut Option<Timer>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<Error> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Poll::Pending,
+    };
+
+    let expired = Pin::new(timer.get_or_insert_with(|| Timer::after(timeout)))
+        .poll(cx)
+        .is_ready();
+    if expired {
+        *timer = None;
+        Poll::Ready(Error::new(
+            ErrorKind::TimedOut,
+            "timed out waiting for fd to be ready",
+        ))
+    } else {
+        Poll::Pending
+    }
+}
+
+/// Reset `timer` once an operation has finished, so the next one starts afresh.
+fn finish<T>(timer: &mut Option<Timer>, rslt: Poll<Result<T>>) -> Poll<Result<T>> {
+    if rslt.is_ready() {
+        *timer = None;
+    }
+    rslt
+}
+
+impl<H: IoSafe + Read> AsyncRead for AsyncTimeout<H> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        if let Poll::Ready(rslt) = Pin::new(&mut this.io).poll_read(cx, buf) {
+            return finish(&mut this.read_timer, Poll::Ready(rslt));
+        }
+        poll_timeout(&mut this.read_timer, this.read_timeout, cx).map(Err)
+    }
+}
+
+impl<H: IoSafe + Write> AsyncWrite for AsyncTimeout<H> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        if let Poll::Ready(rslt) = Pin::new(&mut this.io).poll_write(cx, buf) {
+            return finish(&mut this.write_timer, Poll::Ready(rslt));
+        }
+        poll_timeout(&mut this.write_timer, this.write_timeout, cx).map(Err)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if let Poll::Ready(rslt) = Pin::new(&mut this.io).poll_flush(cx) {
+            return finish(&mut this.write_timer, Poll::Ready(rslt));
+        }
+        poll_timeout(&mut this.write_timer, this.write_timeout, cx).map(Err)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_close(cx)
+    }
+}
+
+impl<H> TimeoutReader<H>
+where
+    H: Read + AsFd,
+{
+    /// Convert this `TimeoutReader` into an `AsyncTimeout` with the same read timeout.
+    pub fn into_async(self) -> Result<AsyncTimeout<H>> {
+        let timeout = self.timeout();
+        AsyncTimeout::with_scaled_timeouts(self.into_inner(), timeout, None)
+    }
+}
+
+impl<H> TimeoutWriter<H>
+where
+    H: Write + AsFd,
+{
+    /// Convert this `TimeoutWriter` into an `AsyncTimeout` with the same write timeout.
+    pub fn into_async(self) -> Result<AsyncTimeout<H>> {
+        let timeout = self.timeout();
+        AsyncTimeout::with_scaled_timeouts(self.into_inner(), None, timeout)
+    }
+}
+
+impl<H> TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    /// Convert this `TimeoutStream` into an `AsyncTimeout` with the same read and
+    /// write timeouts.
+    pub fn into_async(self) -> Result<AsyncTimeout<H>> {
+        let read_timeout = self.read_timeout()?;
+        let write_timeout = self.write_timeout()?;
+        AsyncTimeout::with_scaled_timeouts(self.into_inner(), read_timeout, write_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_io::AsyncRead;
+    use std::future;
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    use super::super::TimeoutStream;
+    use super::*;
+
+    fn read_once<H: IoSafe + Read>(s: &mut AsyncTimeout<H>, buf: &mut [u8]) -> Result<usize> {
+        async_io::block_on(future::poll_fn(|cx| Pin::new(&mut *s).poll_read(cx, buf)))
+    }
+
+    #[test]
+    fn async_read_keeps_timeout() {
+        let (mut left, right) = UnixStream::pair().unwrap();
+        let right = TimeoutStream::new(right, Duration::from_millis(100), None);
+        let mut right = right.into_async().unwrap();
+
+        let mut buf = [0u8; 4];
+        let err = read_once(&mut right, &mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        left.write_all(b"ok").unwrap();
+        assert_eq!(2, read_once(&mut right, &mut buf).unwrap());
+        assert_eq!(b"ok", &buf[..2]);
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Timed helpers for passing ancillary data, such as file descriptors, over Unix
+//! domain sockets.
+//!
+//! Like the `Read` and `Write` wrappers in this crate, each helper waits until the
+//! socket is ready, up until an optional timeout, before actually performing the
+//! `recvmsg` or `sendmsg` call. If the wait times out, the helper returns an
+//! `io::ErrorKind::TimedOut` variant as the value of `io::Error`.
+
+use nix::poll::PollFlags;
+use nix::sys::socket::{self, ControlMessageOwned, MsgFlags};
+use std::io::{IoSlice, IoSliceMut, Result};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use super::utils;
+
+pub use nix::sys::socket::ControlMessage;
+
+/// The most file descriptors a single message can carry on Linux (`SCM_MAX_FD`).
+const MAX_FDS: usize = 253;
+
+/// Receive a message along with any file descriptors passed with it, waiting at most
+/// `timeout` for the message to arrive.
+///
+/// Returns the number of bytes received into `buf` along with the received file
+/// descriptors, which are owned by the caller. On Linux and Android the descriptors
+/// are created close-on-exec.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::ancillary;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// # fn foo(socket: UnixStream) -> std::io::Result<()> {
+/// let mut buf = [0u8; 64];
+/// let (n, fds) = ancillary::recv_with_fds(&socket, &mut buf, Duration::new(5, 0))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn recv_with_fds<F, T>(socket: &F, buf: &mut [u8], timeout: T) -> Result<(usize, Vec<OwnedFd>)>
+where
+    F: AsFd,
+    T: Into<Option<Duration>>,
+{
+    let timeout = timeout.into().map(utils::timeout_ms);
+    utils::wait_until_ready(timeout, socket, PollFlags::POLLIN)?;
+
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut cmsg = nix::cmsg_space!([RawFd; MAX_FDS]);
+    let msg = socket::recvmsg::<()>(
+        socket.as_fd().as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        recv_flags(),
+    )?;
+
+    let mut fds = Vec::new();
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // SAFETY: the kernel installed these descriptors in our table as part of
+            // this `recvmsg` call and nothing else has a handle on them yet.
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+
+    Ok((msg.bytes, fds))
+}
+
+/// Send `buf` along with the file descriptors in `fds`, waiting at most `timeout` for
+/// the socket to become writable.
+///
+/// The descriptors are duplicated into the receiving process; the caller keeps
+/// ownership of its own copies. Returns the number of bytes of `buf` that were sent.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::ancillary;
+/// use std::fs::File;
+/// use std::os::fd::AsFd;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// # fn foo(socket: UnixStream) -> std::io::Result<()> {
+/// let file = File::open("file.txt")?;
+/// ancillary::send_with_fds(&socket, b"file", &[file.as_fd()], Duration::new(5, 0))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn send_with_fds<F, T>(
+    socket: &F,
+    buf: &[u8],
+    fds: &[BorrowedFd<'_>],
+    timeout: T,
+) -> Result<usize>
+where
+    F: AsFd,
+    T: Into<Option<Duration>>,
+{
+    let raw: Vec<RawFd> = fds.iter().map(AsRawFd::as_raw_fd).collect();
+    sendmsg_within(
+        socket,
+        &[IoSlice::new(buf)],
+        &[ControlMessage::ScmRights(&raw)],
+        timeout,
+    )
+}
+
+/// Send the data in `iov` along with arbitrary control messages, waiting at most
+/// `timeout` for the socket to become writable.
+///
+/// This is the general form of `send_with_fds`, allowing, for example,
+/// `ControlMessage::ScmCredentials` to be sent on Linux. Returns the number of bytes
+/// of `iov` that were sent.
+pub fn sendmsg_within<F, T>(
+    socket: &F,
+    iov: &[IoSlice<'_>],
+    cmsgs: &[ControlMessage<'_>],
+    timeout: T,
+) -> Result<usize>
+where
+    F: AsFd,
+    T: Into<Option<Duration>>,
+{
+    let timeout = timeout.into().map(utils::timeout_ms);
+    utils::wait_until_ready(timeout, socket, PollFlags::POLLOUT)?;
+
+    Ok(socket::sendmsg::<()>(
+        socket.as_fd().as_raw_fd(),
+        iov,
+        cmsgs,
+        MsgFlags::empty(),
+        None,
+    )?)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn recv_flags() -> MsgFlags {
+    MsgFlags::MSG_CMSG_CLOEXEC
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn recv_flags() -> MsgFlags {
+    MsgFlags::empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn receive_passed_fd() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let (mut pipe_rdr, mut pipe_wtr) = UnixStream::pair().unwrap();
+
+        let n = send_with_fds(&left, b"fd", &[pipe_wtr.as_fd()], Duration::new(5, 0)).unwrap();
+        assert_eq!(2, n);
+
+        let mut buf = [0u8; 8];
+        let (n, mut fds) = recv_with_fds(&right, &mut buf, Duration::new(5, 0)).unwrap();
+        assert_eq!(b"fd", &buf[..n]);
+        assert_eq!(1, fds.len());
+
+        pipe_wtr.write_all(b"!").unwrap();
+        let mut passed = File::from(fds.pop().unwrap());
+        passed.write_all(b"?").unwrap();
+        let mut received = [0u8; 2];
+        pipe_rdr.read_exact(&mut received).unwrap();
+        assert_eq!(b"!?", &received);
+    }
+
+    #[test]
+    fn receive_times_out() {
+        let (_left, right) = UnixStream::pair().unwrap();
+
+        let mut buf = [0u8; 8];
+        let err = recv_with_fds(&right, &mut buf, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn send_times_out_when_peer_is_full() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        left.set_nonblocking(true).unwrap();
+        let chunk = [0u8; 4096];
+        while (&left).write(&chunk).is_ok() {}
+        left.set_nonblocking(false).unwrap();
+
+        let err = sendmsg_within(
+            &left,
+            &[IoSlice::new(b"more")],
+            &[],
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+}
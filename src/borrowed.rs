@@ -0,0 +1,187 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Timeouts for descriptors the caller does not own.
+
+use std::fmt;
+use std::io::{Read, Result, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::Duration;
+
+use super::{TimeoutReader, TimeoutWriter};
+
+/// A handle that reads from a borrowed descriptor through a function of the caller's.
+///
+/// This is the handle behind a `TimeoutReaderRef`; see `TimeoutReaderRef::from_fn`.
+pub struct ReadFn<'fd, F> {
+    fd: BorrowedFd<'fd>,
+    read: F,
+}
+
+impl<'fd, F> Read for ReadFn<'fd, F>
+where
+    F: FnMut(&mut [u8]) -> Result<usize>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (self.read)(buf)
+    }
+}
+
+impl<'fd, F> AsFd for ReadFn<'fd, F> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd
+    }
+}
+
+impl<'fd, F> fmt::Debug for ReadFn<'fd, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadFn")
+            .field("fd", &self.fd.as_raw_fd())
+            .finish()
+    }
+}
+
+/// A handle that writes to a borrowed descriptor through a function of the caller's.
+///
+/// This is the handle behind a `TimeoutWriterRef`; see `TimeoutWriterRef::from_fn`.
+/// Flushing does nothing, since `write` hands its data straight to the caller's
+/// function.
+pub struct WriteFn<'fd, F> {
+    fd: BorrowedFd<'fd>,
+    write: F,
+}
+
+impl<'fd, F> Write for WriteFn<'fd, F>
+where
+    F: FnMut(&[u8]) -> Result<usize>,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (self.write)(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'fd, F> AsFd for WriteFn<'fd, F> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd
+    }
+}
+
+impl<'fd, F> fmt::Debug for WriteFn<'fd, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteFn")
+            .field("fd", &self.fd.as_raw_fd())
+            .finish()
+    }
+}
+
+/// The `TimeoutReaderRef` type adds a timeout to reads made through a function of the
+/// caller's, on a descriptor it only borrows.
+///
+/// Handles owned by a C library, or by another part of the program, often come as
+/// nothing more than a descriptor and a function that reads from them. A
+/// `TimeoutReaderRef` is a `TimeoutReader` that waits until the borrowed descriptor is
+/// readable and then calls the function to do the reading. It never closes the
+/// descriptor.
+///
+/// If a read times out, it returns an `io::ErrorKind::TimedOut` variant as the value
+/// of `io::Error`.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReaderRef;
+/// use std::io::Read;
+/// use std::os::fd::AsFd;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let (sock, _peer) = UnixStream::pair()?;
+/// let mut rdr = TimeoutReaderRef::from_fn(sock.as_fd(), Duration::new(5, 0), |buf: &mut [u8]| {
+///     // Stands in for a read function from a C library.
+///     (&sock).read(buf)
+/// });
+///
+/// let mut buf = [0u8; 64];
+/// let n = rdr.read(&mut buf)?;
+/// # Ok(())
+/// # }
+/// ```
+pub type TimeoutReaderRef<'fd, F> = TimeoutReader<ReadFn<'fd, F>>;
+
+impl<'fd, F> TimeoutReaderRef<'fd, F>
+where
+    F: FnMut(&mut [u8]) -> Result<usize>,
+{
+    /// Create a new `TimeoutReaderRef` that waits up to `timeout` for `fd` to become
+    /// readable, then reads with `read`.
+    pub fn from_fn<T: Into<Option<Duration>>>(
+        fd: BorrowedFd<'fd>,
+        timeout: T,
+        read: F,
+    ) -> TimeoutReaderRef<'fd, F> {
+        TimeoutReader::new(ReadFn { fd, read }, timeout)
+    }
+}
+
+/// The `TimeoutWriterRef` type adds a timeout to writes made through a function of
+/// the caller's, on a descriptor it only borrows.
+///
+/// This is the writing counterpart of `TimeoutReaderRef`, a `TimeoutWriter` around a
+/// `WriteFn`.
+pub type TimeoutWriterRef<'fd, F> = TimeoutWriter<WriteFn<'fd, F>>;
+
+impl<'fd, F> TimeoutWriterRef<'fd, F>
+where
+    F: FnMut(&[u8]) -> Result<usize>,
+{
+    /// Create a new `TimeoutWriterRef` that waits up to `timeout` for `fd` to become
+    /// writable, then writes with `write`.
+    pub fn from_fn<T: Into<Option<Duration>>>(
+        fd: BorrowedFd<'fd>,
+        timeout: T,
+        write: F,
+    ) -> TimeoutWriterRef<'fd, F> {
+        TimeoutWriter::new(WriteFn { fd, write }, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::{AsFd, AsRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn borrowed_fd_times_out_and_reads() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let raw = left.as_raw_fd();
+        let mut rdr = TimeoutReaderRef::from_fn(
+            left.as_fd(),
+            Duration::from_millis(20),
+            |buf: &mut [u8]| Ok(nix::unistd::read(raw, buf)?),
+        );
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+
+        let mut wtr =
+            TimeoutWriterRef::from_fn(right.as_fd(), Duration::new(5, 0), |buf: &[u8]| {
+                (&right).write(buf)
+            });
+        wtr.write_all(b"ping").unwrap();
+        rdr.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+    }
+}
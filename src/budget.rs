@@ -0,0 +1,327 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::utils;
+
+/// How far off the deadline of a budget too long for an `Instant` to hold is put.
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time according to this clock.
+    fn now(&self) -> Instant;
+}
+
+/// A `Clock` that reads the system's monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The `TimeBudget` struct is an allotment of time shared by any number of wrappers.
+///
+/// A single request often involves several operations on several handles that must
+/// finish within an overall deadline, which per-operation timeouts cannot express.
+/// Wrappers bound to the same `TimeBudget` never wait past the moment it runs out:
+/// each wait is limited to the shorter of the wrapper's own timeout and the time
+/// left in the budget, and once the budget is exhausted every operation fails with
+/// an `io::ErrorKind::TimedOut` variant as the value of `io::Error`.
+///
+/// The budget starts counting down as soon as it is created, and counts all elapsed
+/// time, not just the time spent waiting. That includes the time the reads and
+/// writes themselves take: a `read` that blocks inside the kernel, as reads from NFS
+/// or FUSE files can even though `poll` reports them ready, leaves that much less
+/// for the operations after it. A single operation cannot be interrupted once it has
+/// started, though, so it can finish after the budget has run out; the next one then
+/// fails straight away.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{TimeBudget, TimeoutReader, TimeoutWriter};
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let upstream = TcpStream::connect("127.0.0.1:8080")?;
+/// let downstream = TcpStream::connect("127.0.0.1:8081")?;
+///
+/// let budget = TimeBudget::new(Duration::new(5, 0));
+/// let mut rdr = TimeoutReader::new(upstream, Duration::new(1, 0));
+/// let mut wtr = TimeoutWriter::new(downstream, Duration::new(1, 0));
+/// rdr.set_budget(Some(budget.clone()));
+/// wtr.set_budget(Some(budget));
+/// # Ok(())
+/// # }
+/// ```
+pub struct TimeBudget {
+    deadline: Instant,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl TimeBudget {
+    /// Create a new `TimeBudget` that runs out once `budget` has elapsed.
+    pub fn new(budget: Duration) -> Arc<TimeBudget> {
+        TimeBudget::until(deadline_after(Instant::now(), budget))
+    }
+
+    /// Create a new `TimeBudget` that runs out at `deadline`.
+    pub fn until(deadline: Instant) -> Arc<TimeBudget> {
+        Arc::new(TimeBudget {
+            deadline,
+            clock: None,
+        })
+    }
+
+    /// Create a new `TimeBudget` that runs out once `budget` has elapsed according to
+    /// `clock`, such as the `testing::ManualClock` of the `testing` feature.
+    ///
+    /// Waits are still real: a wait is cut short when the budget looks exhausted
+    /// according to `clock` at the start of the wait, not when the clock is advanced
+    /// during it.
+    pub fn with_clock(budget: Duration, clock: Arc<dyn Clock>) -> Arc<TimeBudget> {
+        Arc::new(TimeBudget {
+            deadline: deadline_after(clock.now(), budget),
+            clock: Some(clock),
+        })
+    }
+
+    fn now(&self) -> Instant {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Returns the moment the budget runs out.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns the time left in the budget, which is zero once it is exhausted.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(self.now())
+    }
+
+    /// Returns `true` once the budget has run out.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining() == Duration::new(0, 0)
+    }
+}
+
+/// Returns the moment `budget` after `now`, or a moment far enough off that it never
+/// comes if that is too far for an `Instant` to hold.
+fn deadline_after(now: Instant, budget: Duration) -> Instant {
+    now.checked_add(budget)
+        .or_else(|| now.checked_add(FAR_FUTURE))
+        .unwrap_or(now)
+}
+
+/// Run `op` on `wrapper` with its budget, reached through `slot`, tightened to
+/// `timeout` from now, returning how much of that time was left when `op` finished.
+///
+/// This is the whole of the `*_within` helpers on the wrappers. A budget the wrapper
+/// is already bound to still applies if it runs out sooner, and is restored once `op`
+/// is done.
+pub(crate) fn run_within<T, F>(
+    wrapper: &mut T,
+    slot: fn(&mut T) -> &mut Option<Arc<TimeBudget>>,
+    timeout: Duration,
+    op: F,
+) -> Result<Duration>
+where
+    F: FnOnce(&mut T) -> Result<()>,
+{
+    let timeout = utils::ms_to_duration(utils::timeout_ms(timeout));
+    let budget = match *slot(wrapper) {
+        Some(ref outer) if outer.remaining() <= timeout => Arc::clone(outer),
+        _ => TimeBudget::new(timeout),
+    };
+    let saved = slot(wrapper).replace(Arc::clone(&budget));
+    let restore = Restore {
+        wrapper,
+        slot,
+        saved,
+    };
+    let rslt = op(restore.wrapper);
+    drop(restore);
+    rslt.map(|()| budget.remaining())
+}
+
+/// Puts back the budget `run_within` replaced, even if the operation panics.
+struct Restore<'a, T> {
+    wrapper: &'a mut T,
+    slot: fn(&mut T) -> &mut Option<Arc<TimeBudget>>,
+    saved: Option<Arc<TimeBudget>>,
+}
+
+impl<'a, T> Drop for Restore<'a, T> {
+    fn drop(&mut self) {
+        *(self.slot)(self.wrapper) = self.saved.take();
+    }
+}
+
+impl fmt::Debug for TimeBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeBudget")
+            .field("deadline", &self.deadline)
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    use super::super::{PartialTransfer, TimeoutReader, TimeoutWriter};
+    use super::*;
+
+    #[test]
+    fn read_exact_within_returns_time_left() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(left, Duration::new(5, 0));
+        let mut buf = [0u8; 4];
+
+        right.write_all(b"ab").unwrap();
+        let start = Instant::now();
+        let err = rdr
+            .read_exact_within(&mut buf, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::new(2, 0));
+        assert!(rdr.budget().is_none());
+        assert_eq!(2, PartialTransfer::of(&err).unwrap().transferred());
+        assert_eq!(b"ab", &buf[..2]);
+
+        right.write_all(b"cdef").unwrap();
+        let left = rdr
+            .read_exact_within(&mut buf, Duration::new(5, 0))
+            .unwrap();
+        assert_eq!(b"cdef", &buf);
+        assert!(left > Duration::new(4, 0) && left <= Duration::new(5, 0));
+    }
+
+    #[test]
+    fn slow_reads_are_charged_to_the_budget() {
+        use std::os::fd::{AsFd, BorrowedFd};
+        use std::thread;
+
+        // Always ready to `poll`, but slow to actually read, like a file on NFS.
+        struct SlowRead(UnixStream);
+
+        impl Read for SlowRead {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                thread::sleep(Duration::from_millis(60));
+                let len = buf.len().min(1);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        impl AsFd for SlowRead {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(SlowRead(left), Duration::new(5, 0));
+        right.write_all(b"abc").unwrap();
+
+        let mut buf = [0u8; 3];
+        let err = rdr
+            .read_exact_within(&mut buf, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(2, PartialTransfer::of(&err).unwrap().transferred());
+    }
+
+    #[test]
+    fn budget_limits_waits_across_wrappers() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let budget = TimeBudget::new(Duration::from_millis(150));
+
+        let mut rdr = TimeoutReader::new(left, Duration::new(5, 0));
+        let mut wtr = TimeoutWriter::new(right, None);
+        rdr.set_budget(Some(budget.clone()));
+        wtr.set_budget(Some(budget.clone()));
+
+        let start = Instant::now();
+        let mut buf = [0u8; 4];
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::new(2, 0));
+        assert!(budget.is_exhausted());
+
+        let err = wtr.write(b"late").unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn budget_follows_manual_clock() {
+        use super::super::testing::ManualClock;
+
+        let (left, _right) = UnixStream::pair().unwrap();
+        let clock = Arc::new(ManualClock::new());
+        let budget = TimeBudget::with_clock(Duration::new(60, 0), clock.clone());
+        let mut wtr = TimeoutWriter::new(left, Duration::new(5, 0));
+        wtr.set_budget(Some(budget.clone()));
+
+        wtr.write_all(b"early").unwrap();
+        clock.advance(Duration::new(59, 0));
+        assert_eq!(Duration::new(1, 0), budget.remaining());
+        wtr.write_all(b"still").unwrap();
+
+        clock.advance(Duration::new(1, 0));
+        let err = wtr.write(b"late").unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn huge_budget_never_runs_out() {
+        use super::super::testing::ManualClock;
+
+        let budget = TimeBudget::new(Duration::MAX);
+        assert!(budget.remaining() > Duration::from_secs(365 * 24 * 60 * 60));
+
+        let clock = Arc::new(ManualClock::new());
+        let budget = TimeBudget::with_clock(Duration::MAX, clock.clone());
+        clock.advance(Duration::from_secs(365 * 24 * 60 * 60));
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn budget_restored_after_panic() {
+        use super::super::Timeout;
+        use std::panic::{self, AssertUnwindSafe};
+
+        let (left, _right) = UnixStream::pair().unwrap();
+        let outer = TimeBudget::new(Duration::new(60, 0));
+        let mut rdr = TimeoutReader::new(left, Duration::new(5, 0));
+        rdr.set_budget(Some(outer.clone()));
+
+        let rslt = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_within(&mut rdr, Timeout::budget_slot, Duration::new(1, 0), |_| {
+                panic!("operation failed")
+            })
+        }));
+        assert!(rslt.is_err());
+        assert!(Arc::ptr_eq(&outer, rdr.budget().unwrap()));
+    }
+}
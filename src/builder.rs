@@ -0,0 +1,612 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::fd::AsFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::budget::TimeBudget;
+use super::config::RetryPolicy;
+use super::metrics::MetricsSink;
+use super::observe::WaitObserver;
+#[cfg(feature = "reactor")]
+use super::reactor::Reactor;
+use super::select::PollFlags;
+use super::throughput::{StallDetector, ThroughputMeter};
+#[cfg(feature = "chaos")]
+use super::wait::Chaos;
+use super::wait::{Waiter, Watchdog};
+use super::{TimeoutReader, TimeoutStream, TimeoutWriter};
+
+/// The `TimeoutBuilder` struct configures wrappers beyond their timeouts.
+///
+/// The constructors on each wrapper cover the common case of a handle and its
+/// timeouts. A `TimeoutBuilder` collects the timeouts together with the less common
+/// settings, such as a `TimeBudget` or a `WaitObserver`, and then wraps any number
+/// of handles with them.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutBuilder;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let stream = TimeoutBuilder::new()
+///     .read_timeout(Duration::new(5, 0))
+///     .write_timeout(Duration::new(1, 0))
+///     .stream(stream);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct TimeoutBuilder {
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    flush_timeout: Option<Duration>,
+    waiter: Waiter,
+}
+
+impl TimeoutBuilder {
+    /// Create a new `TimeoutBuilder` without any timeouts.
+    pub fn new() -> TimeoutBuilder {
+        TimeoutBuilder::default()
+    }
+
+    /// Sets the timeout for reads, used by readers and streams.
+    pub fn read_timeout<T: Into<Option<Duration>>>(mut self, timeout: T) -> TimeoutBuilder {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Sets the timeout for writes, used by writers and streams.
+    pub fn write_timeout<T: Into<Option<Duration>>>(mut self, timeout: T) -> TimeoutBuilder {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// Sets a separate timeout for flushing writers, which otherwise use the write
+    /// timeout.
+    pub fn flush_timeout<T: Into<Option<Duration>>>(mut self, timeout: T) -> TimeoutBuilder {
+        self.flush_timeout = timeout.into();
+        self
+    }
+
+    /// Names the wrappers, to identify them in log records when the `log` feature is
+    /// enabled.
+    pub fn name<S: Into<String>>(mut self, name: S) -> TimeoutBuilder {
+        self.waiter.name = Some(Arc::from(name.into()));
+        self
+    }
+
+    /// Binds the wrappers to a shared `TimeBudget`.
+    pub fn budget(mut self, budget: Arc<TimeBudget>) -> TimeoutBuilder {
+        self.waiter.budget = Some(budget);
+        self
+    }
+
+    /// Installs a `WaitObserver` that is called around each wait the wrappers
+    /// perform. Every wrapper built afterwards shares the same observer.
+    pub fn observer<O>(mut self, observer: O) -> TimeoutBuilder
+    where
+        O: WaitObserver + 'static,
+    {
+        self.waiter.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Installs a `MetricsSink` that receives an observation for every `read` and
+    /// `write` the wrappers perform. Every wrapper built afterwards shares the same
+    /// sink.
+    pub fn metrics_sink<M>(mut self, sink: M) -> TimeoutBuilder
+    where
+        M: MetricsSink + 'static,
+    {
+        self.waiter.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Shuts down each wrapper's handle once it has gone `idle` without a successful
+    /// operation, calling `on_idle` just before.
+    ///
+    /// The idle period runs across calls, starting when the wrapper is built and
+    /// restarting with every successful `read`, `write`, or `flush`. A wait that would
+    /// outlast the idle period is cut short; the wrapper then calls `on_idle`, shuts
+    /// the socket down so the peer sees the connection end, and fails the operation
+    /// with an `io::ErrorKind::TimedOut` error, as does every later operation. There is
+    /// no background thread, so a wrapper that is not being used notices that it has
+    /// gone idle on its next operation. Handles that are not sockets are not shut down.
+    ///
+    /// Each wrapper built afterwards has its own idle period, shared with its clones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutBuilder;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let stream = TimeoutBuilder::new()
+    ///     .read_timeout(Duration::new(5, 0))
+    ///     .idle_watchdog(Duration::new(300, 0), || eprintln!("closing idle connection"))
+    ///     .stream(stream);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn idle_watchdog<F>(mut self, idle: Duration, on_idle: F) -> TimeoutBuilder
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.waiter.watchdog = Some(Arc::new(Watchdog::new(idle, Arc::new(on_idle))));
+        self
+    }
+
+    /// Fails operations on each wrapper once fewer than `bytes_per_second` bytes per
+    /// second have moved through it, in both directions together, over the last
+    /// `grace` period.
+    ///
+    /// A peer that sends a byte just before each timeout would otherwise keep a
+    /// transfer alive forever. The rate is averaged over a rolling window as long as
+    /// `grace`, and nothing is judged until the wrapper has existed for that long.
+    /// Waits are cut short when the transfer would count as stalled, so a stall is
+    /// noticed even while waiting. Once stalled, an operation that would have to wait
+    /// fails with an `io::ErrorKind::TimedOut` error carrying a `Stalled`, while one
+    /// that can go ahead at once still does, so the transfer recovers as soon as the
+    /// peer sends enough again to bring the rate back up.
+    ///
+    /// Each wrapper built afterwards measures its own rate, shared with its clones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_second` or `grace` is zero.
+    pub fn min_throughput(mut self, bytes_per_second: u64, grace: Duration) -> TimeoutBuilder {
+        assert!(bytes_per_second > 0, "rate must be greater than zero");
+        assert!(
+            grace > Duration::ZERO,
+            "grace period must be greater than zero"
+        );
+        self.waiter.stall = Some(Arc::new(StallDetector::new(bytes_per_second, grace)));
+        self
+    }
+
+    /// Measures the rate at which data moves through each wrapper, so that its
+    /// `bytes_per_second` method can report it.
+    ///
+    /// The rolling rate is averaged over the trailing `window`, and the lifetime rate
+    /// since the wrapper was built. Reads and writes both count. Each wrapper built
+    /// afterwards has its own measurements, shared with its clones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutBuilder;
+    /// use std::io;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mirror = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut mirror = TimeoutBuilder::new()
+    ///     .read_timeout(Duration::new(5, 0))
+    ///     .measure_throughput(Duration::new(10, 0))
+    ///     .reader(mirror);
+    ///
+    /// let mut chunk = vec![0u8; 1 << 20];
+    /// io::Read::read_exact(&mut mirror, &mut chunk)?;
+    /// if mirror.bytes_per_second().is_some_and(|rate| rate.rolling < 100_000.0) {
+    ///     println!("mirror is slow, trying another");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn measure_throughput(mut self, window: Duration) -> TimeoutBuilder {
+        self.waiter.meter = Some(Arc::new(ThroughputMeter::new(window)));
+        self
+    }
+
+    /// Caps the total time each wrapper may spend waiting for its handle, over all of
+    /// its operations, at `cap`.
+    ///
+    /// The timeouts bound each wait on its own, but a peer that keeps answering just
+    /// before they expire can still hold a connection blocked for far longer over its
+    /// lifetime. With a cap, the waits of a wrapper and its clones are added up, as in
+    /// `IoStats::wait_time`. A wait is cut short when it would take the total past
+    /// `cap`, and from then on every operation that needs to wait fails straight away
+    /// with an `io::ErrorKind::TimedOut` error.
+    ///
+    /// Each wrapper built afterwards starts with nothing spent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutBuilder;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let stream = TimeoutBuilder::new()
+    ///     .read_timeout(Duration::new(5, 0))
+    ///     .max_total_wait(Duration::new(60, 0))
+    ///     .stream(stream);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_total_wait(mut self, cap: Duration) -> TimeoutBuilder {
+        self.waiter.wait_cap = Some(cap);
+        self
+    }
+
+    /// Waits again, up to the number of times and with the pauses `policy` sets,
+    /// when a wait runs into the wrappers' timeout, instead of failing straight away.
+    ///
+    /// See `RetryPolicy` for which waits are retried.
+    pub fn retry(mut self, policy: RetryPolicy) -> TimeoutBuilder {
+        self.waiter.retry = policy;
+        self
+    }
+
+    /// Shuts down each wrapper's handle as soon as an operation on it times out.
+    ///
+    /// Many protocols cannot recover from a timed out exchange, since the reply may
+    /// still arrive and be mistaken for the answer to the next request. With this
+    /// option, a socket is shut down in both directions before the operation returns
+    /// its `io::ErrorKind::TimedOut` error, so it cannot be reused by accident and the
+    /// peer sees the connection end even if the caller forgets to drop the wrapper.
+    /// Handles that are not sockets are not shut down.
+    pub fn close_on_timeout(mut self, close: bool) -> TimeoutBuilder {
+        self.waiter.close_on_timeout = close;
+        self
+    }
+
+    /// Makes reads stop waiting when priority data arrives, as well as ordinary data.
+    ///
+    /// TCP urgent data and some character devices, such as GPIO lines exported through
+    /// sysfs, signal with `POLLPRI`, which reads do not wait for by default. Once a
+    /// read returns, `priority_pending` tells whether priority data is waiting.
+    ///
+    /// The read itself goes ahead as usual, so TCP urgent data should be received
+    /// inline, with `SO_OOBINLINE` set; otherwise the read still blocks until ordinary
+    /// data arrives.
+    pub fn priority_events(mut self, priority: bool) -> TimeoutBuilder {
+        self.waiter.priority = priority;
+        self
+    }
+
+    /// Sets the poll events that reads wait for, in place of `POLLIN`.
+    ///
+    /// This is for handles that signal readiness in unusual ways, or to add events
+    /// such as Linux's `POLLRDHUP`, so that a read also stops waiting when the peer
+    /// shuts down its side of a connection. The read goes ahead once any of the events arrive, so
+    /// leaving out `POLLIN` can make reads block. `priority_events` still adds
+    /// `POLLPRI` to these events.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate nix;
+    /// extern crate timeout_readwrite;
+    ///
+    /// use nix::libc;
+    /// use timeout_readwrite::TimeoutBuilder;
+    /// use timeout_readwrite::select::PollFlags;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let rdhup = PollFlags::from_bits_retain(libc::POLLRDHUP);
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let stream = TimeoutBuilder::new()
+    ///     .read_timeout(Duration::new(5, 0))
+    ///     .read_events(PollFlags::POLLIN | rdhup)
+    ///     .stream(stream);
+    /// # Ok(())
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn read_events(mut self, events: PollFlags) -> TimeoutBuilder {
+        self.waiter.read_events = Some(events);
+        self
+    }
+
+    /// Sets the poll events that writes and flushes wait for, in place of `POLLOUT`.
+    ///
+    /// The write goes ahead once any of the events arrive, so leaving out `POLLOUT`
+    /// can make writes block.
+    pub fn write_events(mut self, events: PollFlags) -> TimeoutBuilder {
+        self.waiter.write_events = Some(events);
+        self
+    }
+
+    /// Makes each operation on the wrappers fail with a spurious
+    /// `io::ErrorKind::TimedOut` error with the given `probability`, for soak testing
+    /// how an application copes with timeouts.
+    ///
+    /// This is only available with the `chaos` feature, so production builds cannot
+    /// inject faults by accident.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0 and 1.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, probability: f64) -> TimeoutBuilder {
+        self.waiter.chaos = Some(Arc::new(Chaos::new(probability)));
+        self
+    }
+
+    /// Waits for the wrappers' handles through `reactor` instead of calling `poll` on
+    /// each of them.
+    ///
+    /// This is only available with the `reactor` feature.
+    #[cfg(feature = "reactor")]
+    pub fn reactor(mut self, reactor: &Reactor) -> TimeoutBuilder {
+        self.waiter.reactor = Some(reactor.shared());
+        self
+    }
+
+    /// Wrap `handle` in a `TimeoutReader` using the read timeout.
+    pub fn reader<H>(&self, handle: H) -> TimeoutReader<H>
+    where
+        H: Read + AsFd,
+    {
+        let mut rdr = TimeoutReader::new(handle, self.read_timeout);
+        rdr.set_waiter(self.waiter.for_new_wrapper());
+        rdr
+    }
+
+    /// Wrap `handle` in a `TimeoutWriter` using the write and flush timeouts.
+    pub fn writer<H>(&self, handle: H) -> TimeoutWriter<H>
+    where
+        H: Write + AsFd,
+    {
+        let mut wtr = TimeoutWriter::new(handle, self.write_timeout);
+        wtr.set_initial_flush_timeout(self.flush_timeout);
+        wtr.set_waiter(self.waiter.for_new_wrapper());
+        wtr
+    }
+
+    /// Wrap `handle` in a `TimeoutStream` using both timeouts.
+    pub fn stream<H>(&self, handle: H) -> TimeoutStream<H>
+    where
+        H: Read + Write + AsFd,
+    {
+        let mut stream = TimeoutStream::new(handle, self.read_timeout, self.write_timeout);
+        stream.set_waiter(self.waiter.for_new_wrapper());
+        stream
+    }
+}
+
+impl fmt::Debug for TimeoutBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutBuilder")
+            .field("name", &self.waiter.name)
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .field("flush_timeout", &self.flush_timeout)
+            .field("budget", &self.waiter.budget)
+            .field("observer", &self.waiter.observer.is_some())
+            .field("metrics_sink", &self.waiter.sink.is_some())
+            .field("close_on_timeout", &self.waiter.close_on_timeout)
+            .field("priority_events", &self.waiter.priority)
+            .field("read_events", &self.waiter.read_events)
+            .field("write_events", &self.waiter.write_events)
+            .field(
+                "idle_watchdog",
+                &self.waiter.watchdog.as_ref().map(|w| w.idle()),
+            )
+            .field(
+                "min_throughput",
+                &self.waiter.stall.as_ref().map(|s| (s.minimum(), s.grace())),
+            )
+            .field(
+                "measure_throughput",
+                &self.waiter.meter.as_ref().map(|m| m.window()),
+            )
+            .field("max_total_wait", &self.waiter.wait_cap)
+            .field("retry", &self.waiter.retry)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::super::observe::{Direction, WaitObserver};
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<(&'static str, Direction)>>,
+    }
+
+    impl WaitObserver for Arc<Recorder> {
+        fn on_wait_start(&self, direction: Direction, _timeout: Duration) {
+            self.events.lock().unwrap().push(("start", direction));
+        }
+
+        fn on_wait_end(&self, direction: Direction, _elapsed: Duration) {
+            self.events.lock().unwrap().push(("end", direction));
+        }
+
+        fn on_timeout(&self, direction: Direction, _elapsed: Duration) {
+            self.events.lock().unwrap().push(("timeout", direction));
+        }
+    }
+
+    #[test]
+    fn observer_sees_each_wait() {
+        let recorder = Arc::new(Recorder::default());
+        let (left, right) = UnixStream::pair().unwrap();
+        let builder = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(100))
+            .write_timeout(Duration::new(5, 0))
+            .observer(recorder.clone());
+        let mut left = builder.stream(left);
+        let mut right = builder.reader(right);
+
+        let mut buf = [0u8; 2];
+        let err = right.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        left.write_all(b"ok").unwrap();
+        right.read_exact(&mut buf).unwrap();
+
+        assert_eq!(
+            vec![
+                ("start", Direction::Read),
+                ("timeout", Direction::Read),
+                ("start", Direction::Write),
+                ("end", Direction::Write),
+                ("start", Direction::Read),
+                ("end", Direction::Read),
+            ],
+            *recorder.events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn watchdog_shuts_down_idle_stream() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let fired = Arc::new(AtomicBool::new(false));
+        let seen = fired.clone();
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .read_timeout(Duration::new(5, 0))
+            .idle_watchdog(Duration::from_millis(100), move || {
+                seen.store(true, Ordering::Relaxed)
+            })
+            .stream(left);
+
+        left.write_all(b"hi").unwrap();
+        let mut buf = [0u8; 2];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(fired.load(Ordering::Relaxed));
+
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(0, right.read(&mut buf).unwrap());
+        assert_eq!(ErrorKind::TimedOut, left.write(b"x").unwrap_err().kind());
+    }
+
+    #[test]
+    fn total_wait_is_capped() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(60))
+            .max_total_wait(Duration::from_millis(100))
+            .reader(left);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, left.read(&mut buf).unwrap_err().kind());
+        assert_eq!(ErrorKind::TimedOut, left.read(&mut buf).unwrap_err().kind());
+        let waited = left.stats().wait_time;
+        assert!(waited >= Duration::from_millis(100) && waited < Duration::from_millis(160));
+
+        // Even with data waiting, the wrapper has no wait time left to spend.
+        right.write_all(b"late").unwrap();
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!("total wait time exhausted", err.to_string());
+    }
+
+    #[test]
+    fn huge_total_wait_cap_is_allowed() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(50))
+            .max_total_wait(Duration::MAX)
+            .reader(left);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, left.read(&mut buf).unwrap_err().kind());
+        right.write_all(b"data").unwrap();
+        assert_eq!(4, left.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn priority_data_is_reported() {
+        use nix::sys::socket::{self, sockopt, MsgFlags};
+        use std::net::{TcpListener, TcpStream};
+        use std::os::fd::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        socket::setsockopt(&server, sockopt::OobInline, &true).unwrap();
+        let mut server = TimeoutBuilder::new()
+            .read_timeout(Duration::new(5, 0))
+            .priority_events(true)
+            .stream(server);
+        assert!(!server.priority_pending().unwrap());
+
+        socket::send(client.as_raw_fd(), b"!", MsgFlags::MSG_OOB).unwrap();
+        let mut buf = [0u8; 1];
+        while !server.priority_pending().unwrap() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(1, server.read(&mut buf).unwrap());
+        assert_eq!(b"!", &buf);
+    }
+
+    #[test]
+    fn read_events_replace_default() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(50))
+            .read_events(PollFlags::POLLPRI)
+            .stream(left);
+
+        right.write_all(b"x").unwrap();
+        let mut buf = [0u8; 1];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn close_on_timeout_shuts_down_socket() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(50))
+            .close_on_timeout(true)
+            .stream(left);
+
+        let mut buf = [0u8; 1];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(0, right.read(&mut buf).unwrap());
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn chaos_injects_timeouts() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut always = TimeoutBuilder::new()
+            .chaos(1.0)
+            .writer(left.try_clone().unwrap());
+        let mut never = TimeoutBuilder::new().chaos(0.0).writer(left);
+
+        let err = always.write(b"lost").unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(1, always.stats().timeouts);
+
+        never.write_all(b"sent").unwrap();
+        let mut buf = [0u8; 4];
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(b"sent", &buf);
+    }
+}
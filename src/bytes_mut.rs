@@ -0,0 +1,144 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Timed reads straight into a `bytes::BytesMut`.
+
+use bytes::BytesMut;
+use std::io::{Read, Result};
+use std::time::Duration;
+
+use super::budget;
+use super::Timeout;
+
+/// The capacity reserved in a buffer that has no room left.
+const RESERVE: usize = 8 * 1024;
+
+/// Read once from `rdr` into the spare capacity of `buf`, reserving some first if
+/// there is none, and extend `buf` by the bytes read.
+fn read_spare<R: Read>(rdr: &mut R, buf: &mut BytesMut) -> Result<usize> {
+    if buf.len() == buf.capacity() {
+        buf.reserve(RESERVE);
+    }
+    // `Read` implementations are free to look at the buffer they are given, so the
+    // spare capacity is zeroed before the handle sees it.
+    let len = buf.len();
+    buf.resize(buf.capacity(), 0);
+    let rslt = rdr.read(&mut buf[len..]);
+    buf.truncate(len + *rslt.as_ref().unwrap_or(&0));
+    rslt
+}
+
+impl<H, D> Timeout<H, D>
+where
+    Timeout<H, D>: Read,
+{
+    /// Read onto the end of `buf` within `timeout`, returning how many bytes were read.
+    ///
+    /// This is a single `read` into the spare capacity of `buf`, so it returns as soon
+    /// as any data arrives, and returns 0 at end of file. If `buf` is full, 8 KiB of
+    /// capacity is reserved first; reserve more beforehand to read larger chunks. The
+    /// read goes through the handle's own `Read` implementation, so handles that
+    /// buffer or decode data, such as TLS streams, work as usual.
+    ///
+    /// The read waits at most the wrapper's own read timeout, and gives up once
+    /// `timeout` has elapsed, or once a `TimeBudget` the wrapper is bound to runs out
+    /// if that is sooner.
+    ///
+    /// This is only available with the `bytes` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate bytes;
+    /// extern crate timeout_readwrite;
+    ///
+    /// use bytes::BytesMut;
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+    /// let mut buf = BytesMut::with_capacity(4096);
+    /// rdr.read_into_bytes(&mut buf, Duration::new(1, 0))?;
+    /// # Ok(())
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn read_into_bytes(&mut self, buf: &mut BytesMut, timeout: Duration) -> Result<usize> {
+        let mut n = 0;
+        budget::run_within(self, Timeout::budget_slot, timeout, |wrapper| {
+            n = read_spare(wrapper, buf)?;
+            Ok(())
+        })?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::{AsFd, BorrowedFd};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use {TimeoutReader, TimeoutStream};
+
+    #[test]
+    fn reads_into_spare_capacity() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::new(5, 0), None);
+        let mut buf = BytesMut::with_capacity(9);
+        buf.extend_from_slice(b"head:");
+
+        let start = Instant::now();
+        let err = stream
+            .read_into_bytes(&mut buf, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::new(1, 0));
+        assert_eq!(&b"head:"[..], &buf[..]);
+
+        right.write_all(b"tail and more").unwrap();
+        let n = stream
+            .read_into_bytes(&mut buf, Duration::new(1, 0))
+            .unwrap();
+        assert_eq!(4, n);
+        assert_eq!(&b"head:tail"[..], &buf[..]);
+        assert_eq!(4, stream.stats().bytes_read);
+    }
+
+    #[test]
+    fn reads_through_the_handle() {
+        struct Shouting(UnixStream);
+
+        impl Read for Shouting {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                let n = self.0.read(buf)?;
+                buf[..n].make_ascii_uppercase();
+                Ok(n)
+            }
+        }
+
+        impl AsFd for Shouting {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(Shouting(left), Duration::new(5, 0));
+        right.write_all(b"quiet").unwrap();
+
+        let mut buf = BytesMut::new();
+        rdr.read_into_bytes(&mut buf, Duration::new(1, 0)).unwrap();
+        assert_eq!(&b"QUIET"[..], &buf[..]);
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A channel that can be waited on together with a file descriptor.
+
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::select::{self, PollFlags};
+
+struct Shared<T> {
+    /// The queued messages, and the number of live senders.
+    queue: Mutex<(VecDeque<T>, usize)>,
+    /// Readable while messages are queued, or once every sender is gone.
+    event: EventFd,
+}
+
+/// Create a new channel whose receiver can be waited on with `wait`.
+///
+/// The channel is unbounded, like `std::sync::mpsc::channel`. Alongside the queue it
+/// keeps an `eventfd` counting the queued messages, so the receiver has a descriptor
+/// that `poll` reports readable whenever a message is pending.
+///
+/// This is only available on Linux and Android, with the `channel` feature.
+pub fn channel<T>() -> Result<(Sender<T>, Receiver<T>)> {
+    let flags = EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_SEMAPHORE;
+    let shared = Arc::new(Shared {
+        queue: Mutex::new((VecDeque::new(), 1)),
+        event: EventFd::from_flags(flags).map_err(Error::from)?,
+    });
+    Ok((
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    ))
+}
+
+/// The sending half of a `channel`, which can be cloned to send from several places.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queue `msg` for the receiver, waking it if it is waiting.
+    pub fn send(&self, msg: T) -> Result<()> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        self.shared.event.write(1).map_err(Error::from)?;
+        queue.0.push_back(msg);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.queue.lock().unwrap().1 += 1;
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.1 -= 1;
+        if queue.1 == 0 {
+            // Leave the receiver readable from now on, so it finds out.
+            let _ = self.shared.event.write(1);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// The receiving half of a `channel`.
+///
+/// Its descriptor is readable while messages are pending, and once every sender has
+/// been dropped.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Take the next message without blocking.
+    ///
+    /// This fails with `TryRecvError::Empty` if no message is pending, and with
+    /// `TryRecvError::Disconnected` once no message is pending and every sender has
+    /// been dropped.
+    pub fn try_recv(&self) -> std::result::Result<T, TryRecvError> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        match queue.0.pop_front() {
+            Some(msg) => {
+                // Every queued message holds one count, so this cannot block.
+                let _ = self.shared.event.read();
+                Ok(msg)
+            }
+            None if queue.1 == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> AsFd for Receiver<T> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.shared.event.as_fd()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// What ended a `wait`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// The descriptor is ready to be read.
+    Data,
+    /// A message is pending on the channel, or every sender has been dropped.
+    Message,
+    /// Neither happened before the timeout elapsed.
+    TimedOut,
+}
+
+/// Wait up to `timeout` until either `fd` is readable or a message is pending on
+/// `rx`, whichever comes first.
+///
+/// This suits a reader thread that also takes commands from a coordinator: one call
+/// sleeps until there is something to do, and says what it is. When both are ready
+/// at once, `Wakeup::Message` is returned, so that commands are not held up by a
+/// steady stream of data. If `timeout` is `None`, this waits indefinitely.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::channel::{self, Wakeup};
+/// use std::io::Read;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// enum Command {
+///     Stop,
+/// }
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let mut stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let (commands, rx) = channel::channel::<Command>()?;
+/// // Hand `commands` to the coordinator...
+///
+/// let mut buf = [0u8; 1024];
+/// loop {
+///     match channel::wait(&stream, &rx, Some(Duration::new(30, 0)))? {
+///         Wakeup::Data => {
+///             if stream.read(&mut buf)? == 0 {
+///                 break;
+///             }
+///         }
+///         Wakeup::Message => match rx.try_recv() {
+///             Ok(Command::Stop) | Err(_) => break,
+///         },
+///         Wakeup::TimedOut => println!("nothing for 30 seconds"),
+///     }
+/// }
+/// # drop(commands);
+/// # Ok(())
+/// # }
+/// ```
+pub fn wait<T>(fd: &impl AsFd, rx: &Receiver<T>, timeout: Option<Duration>) -> Result<Wakeup> {
+    let sources = [
+        (rx.as_fd(), PollFlags::POLLIN),
+        (fd.as_fd(), PollFlags::POLLIN),
+    ];
+    match select::wait_ready(timeout, &sources) {
+        Ok(ready) => match ready.first() {
+            Some(&(0, _)) => Ok(Wakeup::Message),
+            _ => Ok(Wakeup::Data),
+        },
+        Err(ref e) if e.kind() == ErrorKind::TimedOut => Ok(Wakeup::TimedOut),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::TryRecvError;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn wakes_for_data_or_message() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let (tx, rx) = channel().unwrap();
+        let timeout = Some(Duration::from_millis(20));
+
+        assert_eq!(Wakeup::TimedOut, wait(&left, &rx, timeout).unwrap());
+
+        right.write_all(b"data").unwrap();
+        assert_eq!(Wakeup::Data, wait(&left, &rx, timeout).unwrap());
+
+        tx.send("stop").unwrap();
+        assert_eq!(Wakeup::Message, wait(&left, &rx, timeout).unwrap());
+        assert_eq!(Ok("stop"), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        assert_eq!(Wakeup::Data, wait(&left, &rx, timeout).unwrap());
+
+        drop(tx);
+        assert_eq!(Wakeup::Message, wait(&left, &rx, timeout).unwrap());
+        assert_eq!(Err(TryRecvError::Disconnected), rx.try_recv());
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::Result;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, ExitStatus};
+use std::time::Duration;
+
+use super::{TimeoutReader, TimeoutWriter};
+
+/// The `TimeoutChild` struct wraps a spawned `std::process::Child` and exposes its
+/// standard I/O handles already wrapped with timeouts.
+///
+/// Any of standard in, standard out, or standard error that were configured as
+/// `Stdio::piped()` when the child was spawned are wrapped in a `TimeoutWriter` or
+/// `TimeoutReader`, respectively. Handles that were not piped are reported as `None`,
+/// exactly like the fields on `Child`.
+#[derive(Debug)]
+pub struct TimeoutChild {
+    child: Child,
+    stdin: Option<TimeoutWriter<ChildStdin>>,
+    stdout: Option<TimeoutReader<ChildStdout>>,
+    stderr: Option<TimeoutReader<ChildStderr>>,
+}
+
+impl TimeoutChild {
+    /// Create a new `TimeoutChild` from a spawned child process.
+    ///
+    /// The `read_timeout` applies to both standard out and standard error, while the
+    /// `write_timeout` applies to standard in.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutChild;
+    /// use std::io::Read;
+    /// use std::process::{Command, Stdio};
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let child = Command::new("ls").stdout(Stdio::piped()).spawn()?;
+    /// let mut child = TimeoutChild::new(child, Duration::new(5, 0), None);
+    ///
+    /// let mut output = String::new();
+    /// child.stdout().expect("stdout is piped").read_to_string(&mut output)?;
+    /// child.wait()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<R, W>(mut child: Child, read_timeout: R, write_timeout: W) -> TimeoutChild
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        let read_timeout = read_timeout.into();
+        let write_timeout = write_timeout.into();
+
+        TimeoutChild {
+            stdin: child
+                .stdin
+                .take()
+                .map(|h| TimeoutWriter::new(h, write_timeout)),
+            stdout: child
+                .stdout
+                .take()
+                .map(|h| TimeoutReader::new(h, read_timeout)),
+            stderr: child
+                .stderr
+                .take()
+                .map(|h| TimeoutReader::new(h, read_timeout)),
+            child,
+        }
+    }
+
+    /// Returns the timed standard in of the child, if it was piped.
+    pub fn stdin(&mut self) -> Option<&mut TimeoutWriter<ChildStdin>> {
+        self.stdin.as_mut()
+    }
+
+    /// Returns the timed standard out of the child, if it was piped.
+    pub fn stdout(&mut self) -> Option<&mut TimeoutReader<ChildStdout>> {
+        self.stdout.as_mut()
+    }
+
+    /// Returns the timed standard error of the child, if it was piped.
+    pub fn stderr(&mut self) -> Option<&mut TimeoutReader<ChildStderr>> {
+        self.stderr.as_mut()
+    }
+
+    /// Takes ownership of the timed standard in, leaving `None` in its place.
+    ///
+    /// Dropping the returned writer closes the child's standard in.
+    pub fn take_stdin(&mut self) -> Option<TimeoutWriter<ChildStdin>> {
+        self.stdin.take()
+    }
+
+    /// Takes ownership of the timed standard out, leaving `None` in its place.
+    pub fn take_stdout(&mut self) -> Option<TimeoutReader<ChildStdout>> {
+        self.stdout.take()
+    }
+
+    /// Takes ownership of the timed standard error, leaving `None` in its place.
+    pub fn take_stderr(&mut self) -> Option<TimeoutReader<ChildStderr>> {
+        self.stderr.take()
+    }
+
+    /// Returns the OS-assigned process identifier of the child.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Forces the child process to exit. See `Child::kill`.
+    pub fn kill(&mut self) -> Result<()> {
+        self.child.kill()
+    }
+
+    /// Waits for the child to exit completely. See `Child::wait`.
+    ///
+    /// Like `Child::wait`, standard in is closed before waiting so that a child
+    /// reading its input until end of file does not deadlock.
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        drop(self.stdin.take());
+        self.child.wait()
+    }
+
+    /// Attempts to collect the exit status of the child if it has already exited.
+    /// See `Child::try_wait`.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Returns a reference to the underlying `Child`.
+    ///
+    /// The standard I/O handles have been moved into the `TimeoutChild`, so the
+    /// `stdin`, `stdout`, and `stderr` fields of the returned `Child` are `None`.
+    pub fn get_ref(&self) -> &Child {
+        &self.child
+    }
+
+    /// Returns a mutable reference to the underlying `Child`.
+    pub fn get_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+impl From<Child> for TimeoutChild {
+    /// Wrap a `Child` without any timeouts on its standard I/O handles.
+    fn from(child: Child) -> TimeoutChild {
+        TimeoutChild::new(child, None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn echo_through_cat() {
+        let child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut child = TimeoutChild::new(child, Duration::new(5, 0), Duration::new(5, 0));
+        assert!(child.stderr().is_none());
+
+        child.stdin().unwrap().write_all(b"hello").unwrap();
+        drop(child.take_stdin());
+
+        let mut output = String::new();
+        child.stdout().unwrap().read_to_string(&mut output).unwrap();
+        assert_eq!("hello", output);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn silent_child_times_out() {
+        let child = Command::new("sleep")
+            .arg("5")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut child = TimeoutChild::new(child, Duration::from_millis(100), None);
+
+        let mut buf = [0u8; 16];
+        let err = child.stdout().unwrap().read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
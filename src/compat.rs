@@ -0,0 +1,149 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+
+/// The `RawFdHandle` struct lets handles that only implement `AsRawFd` be used with
+/// the wrappers in this crate, which require `AsFd`.
+///
+/// Earlier releases of this crate accepted any `AsRawFd` handle. The wrappers now
+/// follow Rust's I/O safety rules and borrow descriptors through `AsFd`, which most
+/// handles in the standard library and the wider ecosystem implement. For handles
+/// that have not caught up yet, `RawFdHandle` forwards `Read`, `Write`, and `Seek` to
+/// the handle and provides the missing `AsFd` implementation.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{RawFdHandle, TimeoutReader};
+/// use std::io::{self, Read};
+/// use std::os::fd::{AsRawFd, RawFd};
+/// use std::time::Duration;
+///
+/// struct LegacyPort(std::fs::File);
+///
+/// impl Read for LegacyPort {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         self.0.read(buf)
+///     }
+/// }
+///
+/// impl AsRawFd for LegacyPort {
+///     fn as_raw_fd(&self) -> RawFd {
+///         self.0.as_raw_fd()
+///     }
+/// }
+///
+/// # fn foo(port: LegacyPort) -> std::io::Result<()> {
+/// // SAFETY: `LegacyPort` owns its file, so the descriptor stays open for as long
+/// // as the port does.
+/// let port = unsafe { RawFdHandle::new(port) };
+/// let mut rdr = TimeoutReader::new(port, Duration::new(5, 0));
+/// # Ok(())
+/// # }
+/// ```
+pub struct RawFdHandle<H>
+where
+    H: AsRawFd,
+{
+    handle: H,
+}
+
+impl<H> RawFdHandle<H>
+where
+    H: AsRawFd,
+{
+    /// Create a new `RawFdHandle`.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor returned by `handle.as_raw_fd()` must be open, and must remain
+    /// open and refer to the same file for as long as `handle` is alive. This holds
+    /// for any handle that owns its descriptor.
+    pub unsafe fn new(handle: H) -> RawFdHandle<H> {
+        RawFdHandle { handle }
+    }
+
+    /// Gets a reference to the underlying handle.
+    pub fn get_ref(&self) -> &H {
+        &self.handle
+    }
+
+    /// Gets a mutable reference to the underlying handle.
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.handle
+    }
+
+    /// Unwraps this `RawFdHandle`, returning the underlying handle.
+    pub fn into_inner(self) -> H {
+        self.handle
+    }
+}
+
+impl<H> fmt::Debug for RawFdHandle<H>
+where
+    H: AsRawFd,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawFdHandle")
+            .field("fd", &self.handle.as_raw_fd())
+            .finish()
+    }
+}
+
+impl<H> AsFd for RawFdHandle<H>
+where
+    H: AsRawFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: upheld by the caller of `RawFdHandle::new`.
+        unsafe { BorrowedFd::borrow_raw(self.handle.as_raw_fd()) }
+    }
+}
+
+impl<H> AsRawFd for RawFdHandle<H>
+where
+    H: AsRawFd,
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.handle.as_raw_fd()
+    }
+}
+
+impl<H> Read for RawFdHandle<H>
+where
+    H: AsRawFd + Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.handle.read(buf)
+    }
+}
+
+impl<H> Write for RawFdHandle<H>
+where
+    H: AsRawFd + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.handle.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.handle.flush()
+    }
+}
+
+impl<H> Seek for RawFdHandle<H>
+where
+    H: AsRawFd + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.handle.seek(pos)
+    }
+}
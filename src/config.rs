@@ -0,0 +1,416 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use super::utils;
+use super::{TimeoutBuilder, TimeoutReader, TimeoutStream, TimeoutWriter};
+
+/// The environment variable consulted by the `new_with_default` constructors.
+pub const DEFAULT_TIMEOUT_VAR: &str = "TIMEOUT_READWRITE_DEFAULT_MS";
+
+/// Returns the default timeout set through the `TIMEOUT_READWRITE_DEFAULT_MS`
+/// environment variable, as a whole number of milliseconds.
+///
+/// Returns `None` if the variable is not set or does not hold a valid number, so a
+/// typo in the environment never makes the wrappers stricter than the code asked
+/// for.
+pub fn env_default_timeout() -> Option<Duration> {
+    env::var(DEFAULT_TIMEOUT_VAR)
+        .ok()
+        .and_then(|ms| parse_default_timeout(&ms))
+}
+
+/// Parse the value of `TIMEOUT_READWRITE_DEFAULT_MS`.
+fn parse_default_timeout(ms: &str) -> Option<Duration> {
+    ms.trim().parse().ok().map(Duration::from_millis)
+}
+
+/// The environment variable holding the timeout scale factor, a positive number such
+/// as `2.5`.
+pub const TIMEOUT_SCALE_VAR: &str = "TIMEOUT_READWRITE_SCALE";
+
+/// Returns the factor that every configured timeout is multiplied by.
+///
+/// The factor is read from the `TIMEOUT_READWRITE_SCALE` environment variable the
+/// first time it is needed, and is `1.0` if the variable is not set or does not hold
+/// a positive number. It can be changed with `set_timeout_scale`.
+pub fn timeout_scale() -> f64 {
+    utils::timeout_scale()
+}
+
+/// Multiply every timeout configured from now on by `scale`, for the whole process.
+///
+/// Timeouts tuned for a fast machine tend to fail spuriously on a loaded CI runner.
+/// Rather than loosening each of them, a test suite can scale them all at once, either
+/// with this function or with the `TIMEOUT_READWRITE_SCALE` environment variable.
+///
+/// The factor applies to the timeouts given to the wrappers when they are created or
+/// reconfigured, including defaults and overrides, and to the timeouts passed to the
+/// one-off operations in this crate. The timeouts the wrappers report are the scaled
+/// ones. Wrappers created earlier keep their timeouts, and a `TimeBudget` is not
+/// scaled, since its deadline is a point in time.
+///
+/// # Panics
+///
+/// Panics if `scale` is not a positive, finite number.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// timeout_readwrite::set_timeout_scale(3.0);
+/// let (left, _right) = UnixStream::pair().unwrap();
+/// let rdr = TimeoutReader::new(left, Duration::from_millis(100));
+/// assert_eq!(Some(Duration::from_millis(300)), rdr.timeout());
+/// ```
+pub fn set_timeout_scale(scale: f64) {
+    utils::set_timeout_scale(scale)
+}
+
+/// The `TimeoutOverride` struct tightens the timeouts of every wrapper created on
+/// the current thread for as long as it is alive.
+///
+/// While the override is in place, a new wrapper uses the shorter of its own timeout
+/// and the override, and a wrapper created without a timeout uses the override.
+/// Wrappers that already exist are unaffected. This is mostly useful in tests, to
+/// keep code that creates its own wrappers from waiting for the full production
+/// timeouts.
+///
+/// Overrides nest: an inner override can tighten the timeout further, but never
+/// loosen it. Dropping the guard restores the previous override.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{TimeoutOverride, TimeoutReader};
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// let (left, _right) = UnixStream::pair().unwrap();
+/// let _guard = TimeoutOverride::set(Duration::from_millis(100));
+/// let rdr = TimeoutReader::new(left, Duration::new(30, 0));
+/// assert_eq!(Some(Duration::from_millis(100)), rdr.timeout());
+/// ```
+#[must_use = "the override is removed as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct TimeoutOverride {
+    previous: Option<Duration>,
+    // The override belongs to the thread that set it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl TimeoutOverride {
+    /// Limit the timeouts of wrappers created on this thread to `timeout` until the
+    /// returned guard is dropped.
+    pub fn set(timeout: Duration) -> TimeoutOverride {
+        let limit = match utils::timeout_override() {
+            Some(previous) if previous < timeout => previous,
+            _ => timeout,
+        };
+        TimeoutOverride {
+            previous: utils::replace_timeout_override(Some(limit)),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns the override in effect on this thread, if any.
+    pub fn current() -> Option<Duration> {
+        utils::timeout_override()
+    }
+}
+
+impl Drop for TimeoutOverride {
+    fn drop(&mut self) {
+        utils::replace_timeout_override(self.previous);
+    }
+}
+
+/// The `RetryPolicy` struct tells wrappers to wait again, rather than fail, when a
+/// wait runs into their timeout.
+///
+/// A wait that times out is tried again up to `retries` more times, each after a
+/// pause of `backoff`, and the operation only fails with an `io::ErrorKind::TimedOut`
+/// error once the last try has timed out too. This suits links that go quiet now and
+/// then without being dead. Waits cut short by a `TimeBudget`, a total wait cap, a
+/// stall, or the idle watchdog are not retried, and a pause never outlasts the
+/// budget. Reads that a terminal times out through `VTIME` are not retried either.
+///
+/// The default policy makes no retries.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{RetryPolicy, TimeoutBuilder};
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let stream = TimeoutBuilder::new()
+///     .read_timeout(Duration::new(5, 0))
+///     .retry(RetryPolicy {
+///         retries: 2,
+///         backoff: Duration::from_millis(100),
+///     })
+///     .stream(stream);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct RetryPolicy {
+    /// How many times a timed-out wait is tried again.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub retries: u32,
+    /// The pause before each retry.
+    #[cfg_attr(feature = "serde", serde(default, with = "humantime_serde"))]
+    pub backoff: Duration,
+}
+
+/// The `TimeoutConfig` struct collects the timeouts for a wrapper, and the policy for
+/// retrying them, in one value that can be stored, passed around, and used to
+/// construct wrappers.
+///
+/// With the `serde` feature enabled, it can be serialized and deserialized, with the
+/// timeouts written as human-readable durations such as `"750ms"` or `"1m 30s"`.
+/// Missing timeouts deserialize as `None`, which means no timeout at all, and a
+/// missing `retry` table as the default `RetryPolicy`, which makes no retries.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{RetryPolicy, TimeoutConfig};
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let config = TimeoutConfig {
+///     read_timeout: Some(Duration::from_millis(750)),
+///     write_timeout: Some(Duration::new(5, 0)),
+///     flush_timeout: None,
+///     retry: RetryPolicy {
+///         retries: 2,
+///         backoff: Duration::from_millis(100),
+///     },
+/// };
+///
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let mut stream = config.stream(stream);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct TimeoutConfig {
+    /// The timeout for each read, or `None` to wait indefinitely.
+    #[cfg_attr(feature = "serde", serde(default, with = "humantime_serde"))]
+    pub read_timeout: Option<Duration>,
+    /// The timeout for each write, or `None` to wait indefinitely.
+    #[cfg_attr(feature = "serde", serde(default, with = "humantime_serde"))]
+    pub write_timeout: Option<Duration>,
+    /// The timeout for each flush of a writer, or `None` to use the write timeout.
+    #[cfg_attr(feature = "serde", serde(default, with = "humantime_serde"))]
+    pub flush_timeout: Option<Duration>,
+    /// How waits that time out are retried.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub retry: RetryPolicy,
+}
+
+impl TimeoutConfig {
+    /// Returns a `TimeoutBuilder` with these timeouts and retry policy, for wrappers
+    /// that need more settings than the config holds.
+    pub fn builder(&self) -> TimeoutBuilder {
+        TimeoutBuilder::new()
+            .read_timeout(self.read_timeout)
+            .write_timeout(self.write_timeout)
+            .flush_timeout(self.flush_timeout)
+            .retry(self.retry)
+    }
+
+    /// Wrap `handle` in a `TimeoutReader` using the read timeout and retry policy.
+    pub fn reader<H>(&self, handle: H) -> TimeoutReader<H>
+    where
+        H: Read + AsFd,
+    {
+        self.builder().reader(handle)
+    }
+
+    /// Wrap `handle` in a `TimeoutWriter` using the write and flush timeouts and the
+    /// retry policy.
+    pub fn writer<H>(&self, handle: H) -> TimeoutWriter<H>
+    where
+        H: Write + AsFd,
+    {
+        self.builder().writer(handle)
+    }
+
+    /// Wrap `handle` in a `TimeoutStream` using both timeouts and the retry policy.
+    pub fn stream<H>(&self, handle: H) -> TimeoutStream<H>
+    where
+        H: Read + Write + AsFd,
+    {
+        self.builder().stream(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+    #[cfg(feature = "serde")]
+    use toml;
+
+    use super::super::utils;
+    use super::*;
+
+    #[test]
+    fn scale_multiplies_timeouts() {
+        let timeout = Duration::from_millis(100);
+        assert_eq!(100, utils::scaled_ms(timeout, 1.0));
+        assert_eq!(250, utils::scaled_ms(timeout, 2.5));
+        assert_eq!(1, utils::scaled_ms(timeout, 0.001));
+        assert_eq!(0, utils::scaled_ms(Duration::new(0, 0), 2.5));
+        assert_eq!(i32::MAX, utils::scaled_ms(Duration::new(u64::MAX, 0), 10.0));
+    }
+
+    #[test]
+    fn default_timeout_from_environment() {
+        assert_eq!(
+            Some(Duration::from_millis(250)),
+            parse_default_timeout(" 250\n")
+        );
+        assert_eq!(None, parse_default_timeout("soon"));
+        assert_eq!(None, parse_default_timeout("-5"));
+
+        // The environment is shared with the tests running alongside, so leave it be
+        // and only check that an explicit timeout takes precedence.
+        let (left, _right) = UnixStream::pair().unwrap();
+        let wtr = TimeoutWriter::new_with_default(left.try_clone().unwrap(), Duration::new(1, 0));
+        assert_eq!(Some(Duration::new(1, 0)), wtr.timeout());
+        let stream = TimeoutStream::new_with_default(left, Duration::new(2, 0), None);
+        assert_eq!(Some(Duration::new(2, 0)), stream.read_timeout().unwrap());
+    }
+
+    #[test]
+    fn override_tightens_new_wrappers() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        {
+            let _outer = TimeoutOverride::set(Duration::from_millis(200));
+            {
+                let _inner = TimeoutOverride::set(Duration::new(10, 0));
+                assert_eq!(Some(Duration::from_millis(200)), TimeoutOverride::current());
+            }
+            let stream = TimeoutStream::new(left, None, Duration::from_millis(50));
+            assert_eq!(
+                Some(Duration::from_millis(200)),
+                stream.read_timeout().unwrap()
+            );
+            assert_eq!(
+                Some(Duration::from_millis(50)),
+                stream.write_timeout().unwrap()
+            );
+        }
+        assert_eq!(None, TimeoutOverride::current());
+    }
+
+    #[test]
+    fn writer_uses_flush_timeout() {
+        use std::io::{ErrorKind, Write};
+        use std::time::Instant;
+
+        use super::super::testing;
+
+        let config = TimeoutConfig {
+            write_timeout: Some(Duration::new(5, 0)),
+            flush_timeout: Some(Duration::from_millis(50)),
+            ..TimeoutConfig::default()
+        };
+        let (mock, mut control) = testing::mock_pollable().unwrap();
+        let mut wtr = config.writer(mock);
+        assert_eq!(Some(Duration::from_millis(50)), wtr.flush_timeout());
+
+        control.block_writes().unwrap();
+        let start = Instant::now();
+        assert_eq!(ErrorKind::TimedOut, wtr.flush().unwrap_err().kind());
+        assert!(start.elapsed() < Duration::new(5, 0));
+
+        wtr.set_flush_timeout(None).unwrap();
+        assert_eq!(Some(Duration::new(5, 0)), wtr.flush_timeout());
+    }
+
+    #[test]
+    fn wrappers_retry_timed_out_waits() {
+        use std::io::{ErrorKind, Read, Write};
+        use std::thread;
+        use std::time::Instant;
+
+        use super::super::TimeBudget;
+
+        let config = TimeoutConfig {
+            read_timeout: Some(Duration::from_millis(50)),
+            retry: RetryPolicy {
+                retries: 2,
+                backoff: Duration::from_millis(10),
+            },
+            ..TimeoutConfig::default()
+        };
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = config.reader(left);
+
+        // Three waits and two pauses go by before the read gives up.
+        let start = Instant::now();
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        assert!(start.elapsed() >= Duration::from_millis(170));
+
+        // Data that turns up during a retry is read.
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(80));
+            right.write_all(b"late").unwrap();
+            right
+        });
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+        let _right = writer.join().unwrap();
+
+        // A budget that runs out is final.
+        rdr.set_budget(Some(TimeBudget::new(Duration::from_millis(30))));
+        let start = Instant::now();
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_human_readable_durations() {
+        let config: TimeoutConfig = toml::from_str("read_timeout = \"750ms\"").unwrap();
+        assert_eq!(Some(Duration::from_millis(750)), config.read_timeout);
+        assert_eq!(None, config.write_timeout);
+        assert_eq!(RetryPolicy::default(), config.retry);
+
+        let text = toml::to_string(&config).unwrap();
+        assert_eq!(config, toml::from_str(&text).unwrap());
+
+        let config: TimeoutConfig =
+            toml::from_str("[retry]\nretries = 3\nbackoff = \"250ms\"").unwrap();
+        assert_eq!(3, config.retry.retries);
+        assert_eq!(Duration::from_millis(250), config.retry.backoff);
+        let text = toml::to_string(&config).unwrap();
+        assert_eq!(config, toml::from_str(&text).unwrap());
+    }
+}
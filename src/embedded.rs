@@ -0,0 +1,156 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `embedded-io` trait implementations for the wrappers.
+//!
+//! The wrappers report errors as `std::io::Error`, which `embedded-io` maps onto its
+//! own `ErrorKind`; in particular, a timed out operation is reported as
+//! `embedded_io::ErrorKind::TimedOut`. `ReadReady` and `WriteReady` check readiness of
+//! the underlying descriptor without blocking.
+
+use embedded_io::{ErrorType, ReadReady, WriteReady};
+use nix::poll::PollFlags;
+use std::io::{Error, Read, Result, Write};
+use std::os::fd::AsFd;
+
+use super::utils::is_ready;
+use super::{TimeoutReader, TimeoutStream, TimeoutWriter};
+
+impl<H> ErrorType for TimeoutReader<H>
+where
+    H: Read + AsFd,
+{
+    type Error = Error;
+}
+
+impl<H> embedded_io::Read for TimeoutReader<H>
+where
+    H: Read + AsFd,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Read::read(self, buf)
+    }
+}
+
+impl<H> ReadReady for TimeoutReader<H>
+where
+    H: Read + AsFd,
+{
+    fn read_ready(&mut self) -> Result<bool> {
+        is_ready(self, PollFlags::POLLIN)
+    }
+}
+
+impl<H> ErrorType for TimeoutWriter<H>
+where
+    H: Write + AsFd,
+{
+    type Error = Error;
+}
+
+impl<H> embedded_io::Write for TimeoutWriter<H>
+where
+    H: Write + AsFd,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Write::flush(self)
+    }
+}
+
+impl<H> WriteReady for TimeoutWriter<H>
+where
+    H: Write + AsFd,
+{
+    fn write_ready(&mut self) -> Result<bool> {
+        is_ready(self, PollFlags::POLLOUT)
+    }
+}
+
+impl<H> ErrorType for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    type Error = Error;
+}
+
+impl<H> embedded_io::Read for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Read::read(self, buf)
+    }
+}
+
+impl<H> ReadReady for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn read_ready(&mut self) -> Result<bool> {
+        is_ready(self, PollFlags::POLLIN)
+    }
+}
+
+impl<H> embedded_io::Write for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Write::flush(self)
+    }
+}
+
+impl<H> WriteReady for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn write_ready(&mut self) -> Result<bool> {
+        is_ready(self, PollFlags::POLLOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_io::{Error as EmbeddedError, ErrorKind, Read, ReadReady, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutStream;
+
+    fn exchange<R: Read + ReadReady, W: Write>(rdr: &mut R, wtr: &mut W) -> ErrorKind {
+        assert!(!rdr.read_ready().unwrap());
+        wtr.write_all(b"hi").unwrap();
+        assert!(rdr.read_ready().unwrap());
+
+        let mut buf = [0u8; 2];
+        rdr.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hi", &buf);
+
+        match rdr.read(&mut buf) {
+            Err(e) => e.kind(),
+            Ok(n) => panic!("unexpectedly read {} bytes", n),
+        }
+    }
+
+    #[test]
+    fn generic_embedded_io_usage() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, None, Duration::new(5, 0));
+        let mut right = TimeoutStream::new(right, Duration::from_millis(100), None);
+
+        assert_eq!(ErrorKind::TimedOut, exchange(&mut right, &mut left));
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Byte-stuffed framing, SLIP or COBS, over timed readers and writers.
+//!
+//! This is only available with the `framing` feature.
+
+use memchr::memchr;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use super::budget;
+use super::{TimeoutReader, TimeoutWriter};
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+/// How frames are delimited and stuffed on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Serial Line Internet Protocol, RFC 1055. Frames end with `0xC0`, and are also
+    /// sent with a leading `0xC0` to flush out any line noise.
+    Slip,
+    /// Consistent Overhead Byte Stuffing. Frames end with `0x00`, which never appears
+    /// inside them.
+    Cobs,
+}
+
+impl Codec {
+    fn delimiter(self) -> u8 {
+        match self {
+            Codec::Slip => SLIP_END,
+            Codec::Cobs => 0,
+        }
+    }
+
+    /// Returns the encoding of `frame`, including its delimiters.
+    pub fn encode(self, frame: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Slip => {
+                let mut out = Vec::with_capacity(frame.len() + 2);
+                out.push(SLIP_END);
+                for &b in frame {
+                    match b {
+                        SLIP_END => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                        SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                        b => out.push(b),
+                    }
+                }
+                out.push(SLIP_END);
+                out
+            }
+            Codec::Cobs => {
+                let mut out = Vec::with_capacity(frame.len() + frame.len() / 254 + 2);
+                let mut code_at = out.len();
+                out.push(0);
+                for &b in frame {
+                    if b != 0 {
+                        out.push(b);
+                    }
+                    if b == 0 || out.len() - code_at == 255 {
+                        out[code_at] = (out.len() - code_at) as u8;
+                        code_at = out.len();
+                        out.push(0);
+                    }
+                }
+                out[code_at] = (out.len() - code_at) as u8;
+                out.push(0);
+                out
+            }
+        }
+    }
+
+    /// Decode one `encoded` frame, without its delimiters.
+    pub fn decode(self, encoded: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(encoded.len());
+        match self {
+            Codec::Slip => {
+                let mut bytes = encoded.iter();
+                while let Some(&b) = bytes.next() {
+                    if b != SLIP_ESC {
+                        out.push(b);
+                        continue;
+                    }
+                    out.push(match bytes.next() {
+                        Some(&SLIP_ESC_END) => SLIP_END,
+                        Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                        _ => return Err(invalid("bad SLIP escape sequence")),
+                    });
+                }
+            }
+            Codec::Cobs => {
+                let mut rest = encoded;
+                while let Some((&code, tail)) = rest.split_first() {
+                    let len = match (code as usize).checked_sub(1) {
+                        Some(len) if len <= tail.len() => len,
+                        _ => return Err(invalid("bad COBS encoding")),
+                    };
+                    out.extend_from_slice(&tail[..len]);
+                    rest = &tail[len..];
+                    if code != 255 && !rest.is_empty() {
+                        out.push(0);
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// The `FrameReader` struct decodes frames from a `TimeoutReader`, giving each frame
+/// its own time limit.
+///
+/// Empty frames, such as the leading delimiter of a SLIP frame, are skipped. If a
+/// frame runs out of time, an `io::ErrorKind::TimedOut` error is returned and the
+/// part of the frame received so far is kept, so the next call to `read_frame`
+/// carries on with it. Frames that grow past the maximum size are dropped with an
+/// `io::ErrorKind::InvalidData` error as soon as they do, as are frames that do not
+/// decode; the rest of an oversized frame is skipped by the calls that follow, however
+/// late it arrives.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::framing::{Codec, FrameReader};
+/// use std::fs::File;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let port = File::open("/dev/ttyUSB0")?;
+/// let mut frames = FrameReader::new(TimeoutReader::new(port, None), Codec::Cobs, 512);
+/// let frame = frames.read_frame(Duration::from_millis(200))?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FrameReader<H>
+where
+    H: Read + AsFd,
+{
+    rdr: BufReader<TimeoutReader<H>>,
+    codec: Codec,
+    max_frame: usize,
+    pending: Vec<u8>,
+    /// Whether the rest of an oversized frame is still to be skipped.
+    discarding: bool,
+}
+
+impl<H> FrameReader<H>
+where
+    H: Read + AsFd,
+{
+    /// Create a new `FrameReader` decoding frames of up to `max_frame` bytes with
+    /// `codec` from `rdr`.
+    pub fn new(rdr: TimeoutReader<H>, codec: Codec, max_frame: usize) -> FrameReader<H> {
+        FrameReader {
+            rdr: BufReader::new(rdr),
+            codec,
+            max_frame,
+            pending: Vec::new(),
+            discarding: false,
+        }
+    }
+
+    /// Read and decode the next frame, taking at most `timeout` for it.
+    pub fn read_frame(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        // The most a frame of `max_frame` bytes can take up once encoded.
+        let max_encoded = match self.codec {
+            Codec::Slip => self.max_frame * 2,
+            Codec::Cobs => self.max_frame + self.max_frame / 254 + 1,
+        };
+        let delimiter = self.codec.delimiter();
+        let pending = &mut self.pending;
+        let discarding = &mut self.discarding;
+        budget::run_within(
+            &mut self.rdr,
+            |rdr| rdr.get_mut().budget_slot(),
+            timeout,
+            |rdr| read_encoded(rdr, delimiter, max_encoded, pending, discarding),
+        )?;
+
+        let encoded = self.pending.split_off(0);
+        let frame = self.codec.decode(&encoded)?;
+        if frame.len() > self.max_frame {
+            return Err(invalid("frame longer than the maximum size"));
+        }
+        Ok(frame)
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &TimeoutReader<H> {
+        self.rdr.get_ref()
+    }
+
+    /// Unwraps this `FrameReader`, returning the underlying reader.
+    ///
+    /// Any data that was read ahead, including a partial frame, is lost.
+    pub fn into_inner(self) -> TimeoutReader<H> {
+        self.rdr.into_inner()
+    }
+}
+
+/// Read a whole non-empty encoded frame onto the end of `pending`, dropping its
+/// delimiter, or fail once it grows past `max` bytes.
+///
+/// While `discarding` is set, input is skipped up to the next delimiter first. It is
+/// set when a frame is dropped for being too long before its end has been read.
+fn read_encoded<R: BufRead>(
+    rdr: &mut R,
+    delimiter: u8,
+    max: usize,
+    pending: &mut Vec<u8>,
+    discarding: &mut bool,
+) -> Result<()> {
+    loop {
+        let available = rdr.fill_buf()?;
+        if available.is_empty() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "end of file before a whole frame",
+            ));
+        }
+        let (chunk, used, done) = match memchr(delimiter, available) {
+            Some(end) => (&available[..end], end + 1, true),
+            None => (available, available.len(), false),
+        };
+        if *discarding {
+            rdr.consume(used);
+            *discarding = !done;
+            continue;
+        }
+        let overlong = pending.len() + chunk.len() > max;
+        if !overlong {
+            pending.extend_from_slice(chunk);
+        }
+        rdr.consume(used);
+
+        if overlong {
+            // Drop the frame now, and the rest of it as it arrives.
+            pending.clear();
+            *discarding = !done;
+            return Err(invalid("frame longer than the maximum size"));
+        }
+        if done && !pending.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// The `FrameWriter` struct encodes frames onto a `TimeoutWriter`, giving each frame
+/// its own time limit.
+///
+/// If a frame runs out of time, an `io::ErrorKind::TimedOut` error carrying a
+/// `PartialTransfer` is returned. The peer then has part of a frame, which its
+/// decoder drops once the next frame's delimiter arrives.
+#[derive(Debug)]
+pub struct FrameWriter<H>
+where
+    H: Write + AsFd,
+{
+    wtr: TimeoutWriter<H>,
+    codec: Codec,
+    max_frame: usize,
+}
+
+impl<H> FrameWriter<H>
+where
+    H: Write + AsFd,
+{
+    /// Create a new `FrameWriter` encoding frames of up to `max_frame` bytes with
+    /// `codec` onto `wtr`.
+    pub fn new(wtr: TimeoutWriter<H>, codec: Codec, max_frame: usize) -> FrameWriter<H> {
+        FrameWriter {
+            wtr,
+            codec,
+            max_frame,
+        }
+    }
+
+    /// Encode and write `frame`, taking at most `timeout` for it.
+    ///
+    /// Frames longer than the maximum size fail with an `io::ErrorKind::InvalidInput`
+    /// error, without anything being written.
+    pub fn write_frame(&mut self, frame: &[u8], timeout: Duration) -> Result<()> {
+        if frame.len() > self.max_frame {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "frame longer than the maximum size",
+            ));
+        }
+        self.wtr
+            .write_all_within(&self.codec.encode(frame), timeout)
+            .map(|_| ())
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &TimeoutWriter<H> {
+        &self.wtr
+    }
+
+    /// Unwraps this `FrameWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> TimeoutWriter<H> {
+        self.wtr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn codecs_round_trip() {
+        let long: Vec<u8> = (0..600).map(|i| (i % 7) as u8).collect();
+        let frames: [&[u8]; 5] = [b"", b"\x00", b"a\x00b\xc0c\xdbd", &[1u8; 254], &long];
+        for &codec in &[Codec::Slip, Codec::Cobs] {
+            for frame in frames.iter() {
+                let encoded = codec.encode(frame);
+                let delimiter = codec.delimiter();
+                assert_eq!(Some(&delimiter), encoded.last());
+                assert!(!encoded[1..encoded.len() - 1].contains(&delimiter));
+                let inner = match codec {
+                    Codec::Slip => &encoded[1..encoded.len() - 1],
+                    Codec::Cobs => &encoded[..encoded.len() - 1],
+                };
+                assert_eq!(*frame, &codec.decode(inner).unwrap()[..]);
+            }
+        }
+        assert_eq!(vec![3, 1, 2, 2, 3, 0], Codec::Cobs.encode(&[1, 2, 0, 3]));
+
+        let malformed: [(Codec, &[u8]); 5] = [
+            (Codec::Cobs, b"\x00"),
+            (Codec::Cobs, b"\x02a\x00b"),
+            (Codec::Cobs, b"\x05abc"),
+            (Codec::Slip, b"a\xdb"),
+            (Codec::Slip, b"a\xdbb"),
+        ];
+        for &(codec, encoded) in malformed.iter() {
+            let err = codec.decode(encoded).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn frames_over_timed_socket() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut right_raw = right.try_clone().unwrap();
+        let mut rdr = FrameReader::new(TimeoutReader::new(left, None), Codec::Slip, 16);
+        let mut wtr = FrameWriter::new(TimeoutWriter::new(right, None), Codec::Slip, 16);
+        let timeout = Duration::from_millis(50);
+
+        wtr.write_frame(b"hello", timeout).unwrap();
+        assert_eq!(b"hello", &rdr.read_frame(timeout).unwrap()[..]);
+
+        // A frame split by a stall is kept across the timeout.
+        right_raw.write_all(b"\xc0wor").unwrap();
+        let err = rdr.read_frame(timeout).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        right_raw.write_all(b"ld\xc0").unwrap();
+        assert_eq!(b"world", &rdr.read_frame(timeout).unwrap()[..]);
+
+        let err = wtr.write_frame(&[0u8; 17], timeout).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        right_raw.write_all(&[b'x'; 40]).unwrap();
+        right_raw.write_all(b"\xc0ok\xc0").unwrap();
+        let err = rdr.read_frame(timeout).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(b"ok", &rdr.read_frame(timeout).unwrap()[..]);
+    }
+
+    #[test]
+    fn oversized_frame_tail_skipped_after_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = FrameReader::new(TimeoutReader::new(left, None), Codec::Slip, 4);
+        let timeout = Duration::from_millis(50);
+
+        right.write_all(b"\xc0AAAAAAAAAA").unwrap();
+        let err = rdr.read_frame(timeout).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        let err = rdr.read_frame(timeout).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right.write_all(b"BAD\xc0ok\xc0").unwrap();
+        assert_eq!(b"ok", &rdr.read_frame(timeout).unwrap()[..]);
+    }
+}
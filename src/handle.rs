@@ -0,0 +1,159 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{Read, Result, Write};
+use std::net::TcpStream;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+
+/// Readable handles with a file descriptor, as a single trait that can be boxed.
+///
+/// `Read + AsFd` cannot be written as one trait object, so handles of different types
+/// cannot share a `TimeoutReader` type without this. Every `Read + AsFd` type
+/// implements `ReadFd`, and a `Box<dyn ReadFd>` is both `Read` and `AsFd`, so a
+/// `TimeoutReader<Box<dyn ReadFd>>` can wrap a pipe, a socket, or a pseudo-terminal
+/// alike. Add `+ Send` to the trait object to move it between threads.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{ReadFd, TimeoutReader};
+/// use std::fs::File;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let sources: Vec<Box<dyn ReadFd>> = vec![
+///     Box::new(File::open("/dev/ttyUSB0")?),
+///     Box::new(TcpStream::connect("127.0.0.1:8080")?),
+/// ];
+/// let readers: Vec<TimeoutReader<Box<dyn ReadFd>>> = sources
+///     .into_iter()
+///     .map(|source| TimeoutReader::new(source, Duration::new(5, 0)))
+///     .collect();
+/// # Ok(())
+/// # }
+/// ```
+pub trait ReadFd: Read + AsFd {}
+
+impl<T: Read + AsFd + ?Sized> ReadFd for T {}
+
+/// Writable handles with a file descriptor, as a single trait that can be boxed.
+///
+/// This is the writing counterpart of `ReadFd`, for `TimeoutWriter<Box<dyn WriteFd>>`.
+pub trait WriteFd: Write + AsFd {}
+
+impl<T: Write + AsFd + ?Sized> WriteFd for T {}
+
+/// Duplex handles with a file descriptor, as a single trait that can be boxed.
+///
+/// This is the counterpart of `ReadFd` for `TimeoutStream<Box<dyn ReadWriteFd>>`.
+pub trait ReadWriteFd: Read + Write + AsFd {}
+
+impl<T: Read + Write + AsFd + ?Sized> ReadWriteFd for T {}
+
+/// Handles that can be duplicated into a new, independently owned handle referring
+/// to the same underlying file or socket.
+///
+/// This is the `try_clone` method that `File`, `TcpStream`, `UnixStream`, and
+/// `OwnedFd` each provide, gathered into a trait so the wrappers can offer it too.
+pub trait TryCloneHandle: Sized {
+    /// Creates a new independently owned handle to the same file or socket.
+    fn try_clone_handle(&self) -> Result<Self>;
+}
+
+impl TryCloneHandle for File {
+    fn try_clone_handle(&self) -> Result<File> {
+        self.try_clone()
+    }
+}
+
+impl TryCloneHandle for TcpStream {
+    fn try_clone_handle(&self) -> Result<TcpStream> {
+        self.try_clone()
+    }
+}
+
+impl TryCloneHandle for UnixStream {
+    fn try_clone_handle(&self) -> Result<UnixStream> {
+        self.try_clone()
+    }
+}
+
+impl TryCloneHandle for OwnedFd {
+    fn try_clone_handle(&self) -> Result<OwnedFd> {
+        self.try_clone()
+    }
+}
+
+/// Handles that buffer data in user space, ahead of their file descriptor.
+///
+/// A wrapper normally waits for the descriptor to become readable before reading,
+/// but data that a handle has already buffered can be read without waiting, even
+/// though the descriptor has nothing more to offer. Implementing this trait lets
+/// `TimeoutReader` check the buffer first, and forward `BufRead` to the handle, and
+/// lets `read` on any wrapper check it too, once turned on with
+/// `Timeout::set_check_buffered`.
+pub trait BufferedHandle {
+    /// Returns `true` if data can be read from the handle without touching its
+    /// file descriptor.
+    fn has_buffered_data(&self) -> bool;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::{TimeoutReader, TimeoutStream};
+    use super::*;
+
+    #[test]
+    fn boxed_handles_share_one_wrapper_type() {
+        let (rdr, mut wtr) = nix::unistd::pipe().unwrap();
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let sources: Vec<Box<dyn ReadFd + Send>> = vec![Box::new(File::from(rdr)), Box::new(left)];
+        let mut readers: Vec<TimeoutReader<Box<dyn ReadFd + Send>>> = sources
+            .into_iter()
+            .map(|source| TimeoutReader::new(source, Duration::from_millis(20)))
+            .collect();
+
+        let mut buf = [0u8; 4];
+        for rdr in readers.iter_mut() {
+            assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        }
+        nix::unistd::write(&mut wtr, b"pipe").unwrap();
+        right.write_all(b"sock").unwrap();
+        readers[0].read_exact(&mut buf).unwrap();
+        assert_eq!(b"pipe", &buf);
+        readers[1].read_exact(&mut buf).unwrap();
+        assert_eq!(b"sock", &buf);
+    }
+
+    #[test]
+    fn try_clone_keeps_timeouts() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, Duration::from_millis(100), None);
+        let mut other = left.try_clone().unwrap();
+        assert_ne!(left.as_raw_fd(), other.as_raw_fd());
+        assert_eq!(left.read_timeout().unwrap(), other.read_timeout().unwrap());
+
+        let mut buf = [0u8; 4];
+        let err = other.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        other.write_all(b"ping").unwrap();
+        right.read_exact(&mut buf).unwrap();
+        right.write_all(b"pong").unwrap();
+        left.read_exact(&mut buf).unwrap();
+        assert_eq!(b"pong", &buf);
+    }
+}
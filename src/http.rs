@@ -0,0 +1,417 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading the parts of HTTP/1.x messages under a time limit.
+
+use memchr::memmem;
+use std::fmt;
+use std::io::{BufRead, BufReader, Chain, Cursor, Error, ErrorKind, Read, Result, Write};
+use std::mem;
+use std::os::fd::AsFd;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::budget::{self, TimeBudget};
+use super::partial;
+use super::utils;
+use super::TimeoutStream;
+
+/// The longest chunk size line or trailer line accepted, in bytes.
+const MAX_CHUNK_LINE: u64 = 4096;
+
+/// Read the start line and headers of an HTTP/1.x message, up to and including the
+/// blank line that ends them, from `stream` onto the end of `head` within `timeout`.
+///
+/// Once `head` holds the header block, ending in `\r\n\r\n`, returns whatever was
+/// read beyond it, which is the start of the body or of a pipelined request. The
+/// header block may be at most `max_len` bytes long; a longer one fails with an
+/// `io::ErrorKind::InvalidData` error. End of file before the blank line is an
+/// `io::ErrorKind::UnexpectedEof` error.
+///
+/// On any error, the bytes read so far are left in `head`. If time runs out, the
+/// error carries a `PartialTransfer` with the number of bytes this call read, and
+/// calling again with the same `head` carries on where it left off.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{http, TimeoutStream};
+/// use std::net::TcpListener;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:8080")?;
+/// let (client, _) = listener.accept()?;
+/// let mut client = TimeoutStream::new(client, Duration::new(5, 0), Duration::new(5, 0));
+///
+/// let mut head = Vec::new();
+/// let body_start = http::read_header_block(&mut client, &mut head, 16 * 1024, Duration::new(10, 0))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_header_block<H>(
+    stream: &mut TimeoutStream<H>,
+    head: &mut Vec<u8>,
+    max_len: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>>
+where
+    H: Read + Write + AsFd,
+{
+    let mut rest = Vec::new();
+    budget::run_within(
+        stream,
+        |stream| stream.budget_slot(),
+        timeout,
+        |stream| {
+            rest = read_until_blank_line(stream, max_len, head)?;
+            Ok(())
+        },
+    )?;
+    Ok(rest)
+}
+
+/// Read onto `head` until it holds `\r\n\r\n`, then split off and return what follows.
+fn read_until_blank_line<R: Read>(
+    rdr: &mut R,
+    max_len: usize,
+    head: &mut Vec<u8>,
+) -> Result<Vec<u8>> {
+    let finder = memmem::Finder::new(b"\r\n\r\n");
+    let start = head.len();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = match rdr.read(&mut chunk) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "end of file before the end of the headers",
+                ))
+            }
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(partial::timed_out(head.len() - start, e)),
+        };
+
+        // The blank line may straddle what was already read and what just arrived.
+        let from = head.len().saturating_sub(3);
+        head.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = finder.find(&head[from..]) {
+            let end = from + pos + 4;
+            if end <= max_len {
+                return Ok(head.split_off(end));
+            }
+        }
+        if head.len() >= max_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "header block longer than the limit",
+            ));
+        }
+    }
+}
+
+/// The `ChunkedReader` struct decodes an HTTP/1.1 body sent with
+/// `Transfer-Encoding: chunked` from a `TimeoutStream`, giving each chunk its own time
+/// limit.
+///
+/// The time limit for a chunk starts when its size line is read, and covers the
+/// whole of its data, so an upstream that stalls part way through a chunk, or
+/// between chunks, fails the next read with an `io::ErrorKind::TimedOut` error
+/// instead of holding the reader up. The stream's own read timeout still limits
+/// each wait. Reads return the decoded body, and end of file once the last chunk
+/// and any trailers have been read; chunk extensions and trailers are discarded.
+/// Malformed chunk framing fails with an `io::ErrorKind::InvalidData` error.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{http, TimeoutStream};
+/// use timeout_readwrite::http::ChunkedReader;
+/// use std::io::Read;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let upstream = TcpStream::connect("127.0.0.1:8080")?;
+/// let mut upstream = TimeoutStream::new(upstream, Duration::new(5, 0), Duration::new(5, 0));
+/// let mut head = Vec::new();
+/// let body_start = http::read_header_block(&mut upstream, &mut head, 16 * 1024, Duration::new(10, 0))?;
+///
+/// // Once the headers say the body is chunked:
+/// let mut body = ChunkedReader::new(upstream, body_start, Duration::new(5, 0));
+/// let mut data = Vec::new();
+/// body.read_to_end(&mut data)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChunkedReader<H>
+where
+    H: Read + Write + AsFd,
+{
+    rdr: BufReader<Chain<Cursor<Vec<u8>>, TimeoutStream<H>>>,
+    per_chunk: Duration,
+    budget: Option<Arc<TimeBudget>>,
+    /// The number of data bytes left in the current chunk.
+    remaining: u64,
+    /// Whether the `\r\n` after a chunk's data is still to be read.
+    after_data: bool,
+    /// The part of a framing line read before a timeout cut it short.
+    line: Vec<u8>,
+    done: bool,
+}
+
+impl<H> ChunkedReader<H>
+where
+    H: Read + Write + AsFd,
+{
+    /// Create a new `ChunkedReader` decoding the body from `stream`, giving each chunk
+    /// at most `per_chunk`.
+    ///
+    /// `start` holds any part of the body already read from the stream, such as the
+    /// bytes `read_header_block` read beyond the headers.
+    pub fn new(stream: TimeoutStream<H>, start: Vec<u8>, per_chunk: Duration) -> ChunkedReader<H> {
+        ChunkedReader {
+            rdr: BufReader::new(Cursor::new(start).chain(stream)),
+            per_chunk,
+            budget: None,
+            remaining: 0,
+            after_data: false,
+            line: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Unwraps this `ChunkedReader`, returning the underlying stream and any bytes
+    /// read from it beyond the body, such as the start of the next response.
+    pub fn into_inner(self) -> (TimeoutStream<H>, Vec<u8>) {
+        // The buffer was filled from the start of the body before the rest of it.
+        let mut rest = self.rdr.buffer().to_vec();
+        let (cursor, stream) = self.rdr.into_inner().into_inner();
+        let pos = cursor.position() as usize;
+        let start = cursor.into_inner();
+        rest.extend_from_slice(&start[pos.min(start.len())..]);
+        (stream, rest)
+    }
+
+    /// Run `op` on the reader with the current chunk's budget applied to the stream,
+    /// unless a budget the stream is already bound to runs out sooner.
+    fn within_chunk<T, F>(&mut self, op: F) -> Result<T>
+    where
+        F: FnOnce(&mut BufReader<Chain<Cursor<Vec<u8>>, TimeoutStream<H>>>) -> Result<T>,
+    {
+        let slot = self.rdr.get_mut().get_mut().1.budget_slot();
+        let chunk = match (slot.as_ref(), self.budget.as_ref()) {
+            (Some(outer), Some(chunk)) if chunk.deadline() < outer.deadline() => Some(chunk),
+            (Some(outer), _) => Some(outer),
+            (None, chunk) => chunk,
+        };
+        let outer = mem::replace(slot, chunk.cloned());
+        let rslt = op(&mut self.rdr);
+        *self.rdr.get_mut().get_mut().1.budget_slot() = outer;
+        rslt
+    }
+
+    /// Read one line of chunk framing, without its line ending.
+    ///
+    /// If the read fails, such as when it times out, the part of the line read so far
+    /// is kept for the next call.
+    fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = mem::take(&mut self.line);
+        let limit = MAX_CHUNK_LINE - line.len() as u64;
+        let rslt = self.within_chunk(|rdr| rdr.by_ref().take(limit).read_until(b'\n', &mut line));
+        if let Err(e) = rslt {
+            self.line = line;
+            return Err(e);
+        }
+        if line.last() != Some(&b'\n') {
+            return Err(match line.len() as u64 {
+                MAX_CHUNK_LINE => invalid_chunk("chunk line too long"),
+                _ => Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "end of file inside a chunked body",
+                ),
+            });
+        }
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(line)
+    }
+
+    /// Start the next chunk, reading its size line.
+    fn next_chunk(&mut self) -> Result<()> {
+        self.budget = Some(TimeBudget::new(utils::ms_to_duration(utils::timeout_ms(
+            self.per_chunk,
+        ))));
+        if self.after_data {
+            if !self.read_line()?.is_empty() {
+                return Err(invalid_chunk("chunk data longer than its size"));
+            }
+            self.after_data = false;
+        }
+
+        let line = self.read_line()?;
+        let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+        let size = str::from_utf8(size)
+            .ok()
+            .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| invalid_chunk("bad chunk size"))?;
+        if size > 0 {
+            self.remaining = size;
+            return Ok(());
+        }
+
+        // The last chunk: skip any trailers up to the blank line.
+        while !self.read_line()?.is_empty() {}
+        self.done = true;
+        Ok(())
+    }
+}
+
+fn invalid_chunk(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl<H> Read for ChunkedReader<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.remaining == 0 && !self.done {
+            self.next_chunk()?;
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.within_chunk(|rdr| rdr.read(&mut buf[..len]))?;
+        if n == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "end of file inside a chunked body",
+            ));
+        }
+        self.remaining -= n as u64;
+        self.after_data = self.remaining == 0;
+        Ok(n)
+    }
+}
+
+impl<H> fmt::Debug for ChunkedReader<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChunkedReader")
+            .field("stream", self.rdr.get_ref().get_ref().1)
+            .field("per_chunk", &self.per_chunk)
+            .field("remaining", &self.remaining)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn header_block_split_from_body() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, None, None);
+        let timeout = Duration::from_millis(50);
+
+        let mut head = Vec::new();
+        right.write_all(b"POST / HTTP/1.1\r\nHost: x\r").unwrap();
+        let err = read_header_block(&mut stream, &mut head, 64, timeout).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(b"POST / HTTP/1.1\r\nHost: x\r", &head[..]);
+
+        // The blank line is found even when it straddles the timeout.
+        right.write_all(b"\n\r\nbody").unwrap();
+        let rest = read_header_block(&mut stream, &mut head, 64, timeout).unwrap();
+        assert_eq!(b"POST / HTTP/1.1\r\nHost: x\r\n\r\n", &head[..]);
+        assert_eq!(b"body", &rest[..]);
+
+        head.clear();
+        right.write_all(&[b'a'; 100]).unwrap();
+        let err = read_header_block(&mut stream, &mut head, 64, timeout).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(100, head.len());
+    }
+
+    #[test]
+    fn chunked_body_decoded_with_budget_per_chunk() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let stream = TimeoutStream::new(left, None, None);
+        let mut body = ChunkedReader::new(stream, b"5\r\nhel".to_vec(), Duration::from_millis(50));
+
+        right
+            .write_all(b"lo\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\nnext")
+            .unwrap();
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).unwrap();
+        assert_eq!(b"hello world", &data[..]);
+        let (stream, rest) = body.into_inner();
+        assert_eq!(b"next", &rest[..]);
+
+        let mut body = ChunkedReader::new(stream, Vec::new(), Duration::from_millis(50));
+        right.write_all(b"a\r\nstall").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(5, body.read(&mut buf).unwrap());
+        let err = body.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn chunk_size_line_kept_across_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let stream = TimeoutStream::new(left, None, None);
+        let mut body = ChunkedReader::new(stream, Vec::new(), Duration::from_millis(50));
+
+        right.write_all(b"1").unwrap();
+        let mut buf = [0u8; 16];
+        let err = body.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right
+            .write_all(b"0\r\n0123456789abcdef\r\n0\r\n\r\n")
+            .unwrap();
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).unwrap();
+        assert_eq!(b"0123456789abcdef", &data[..]);
+    }
+
+    #[test]
+    fn chunked_leftovers_kept_in_order() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        let stream = TimeoutStream::new(left, None, None);
+        // More than the reader buffers at once follows the body, so some of it is
+        // still in `start` when the body ends.
+        let next: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut start = b"1\r\na\r\n0\r\n\r\n".to_vec();
+        start.extend_from_slice(&next);
+        let mut body = ChunkedReader::new(stream, start, Duration::from_millis(50));
+
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).unwrap();
+        assert_eq!(b"a", &data[..]);
+        let (_stream, rest) = body.into_inner();
+        assert_eq!(next, rest);
+    }
+}
@@ -7,6 +7,7 @@
 // except according to those terms.
 
 #![cfg(unix)]
+#![cfg_attr(feature = "nightly", feature(read_buf, core_io_borrowed_buf))]
 
 //! Provides `TimeoutReader` and `TimeoutWriter` structs to time out reads and
 //! writes, respectively. `TimeoutReader` implements `Read` and `TimeoutWriter`
@@ -76,16 +77,170 @@
 //!   Err(ref e) => { println!("failed reading with {}", e); },
 //! }
 //! ```
+//!
+//! # Zero timeouts
+//!
+//! A wrapper created with a timeout of `Duration::ZERO` never waits: each operation
+//! checks whether the handle is ready, fails straight away with an
+//! `io::ErrorKind::TimedOut` error if it is not, and otherwise performs the operation,
+//! which then does not block. Scaling a zero timeout leaves it at zero. As with
+//! `std::net`, the `set_*_timeout` methods reject a zero duration instead.
 
 #[cfg(test)]
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "async-io")]
+extern crate async_io;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "embedded-io")]
+extern crate embedded_io;
+#[cfg(feature = "async-io")]
+extern crate futures_io;
+#[cfg(feature = "serde")]
+extern crate humantime_serde;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+extern crate memchr;
+#[cfg(feature = "mio")]
+extern crate mio;
 extern crate nix;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serialport")]
+extern crate serialport;
+#[cfg(test)]
+extern crate toml;
 
+mod sockopt;
+mod tty;
 mod utils;
+mod wait;
+
+pub mod timeout;
+pub use timeout::Timeout;
 
 pub mod reader;
 pub use reader::{TimeoutReadExt, TimeoutReader};
 
 pub mod writer;
 pub use writer::{TimeoutWriteExt, TimeoutWriter};
+
+pub mod handle;
+pub use handle::{BufferedHandle, ReadFd, ReadWriteFd, TryCloneHandle, WriteFd};
+
+pub mod compat;
+pub use compat::RawFdHandle;
+
+pub mod borrowed;
+pub use borrowed::{ReadFn, TimeoutReaderRef, TimeoutWriterRef, WriteFn};
+
+pub mod budget;
+pub use budget::TimeBudget;
+
+pub mod partial;
+pub use partial::{PartialTransfer, Transfer, Utf8Tail};
+
+pub mod lines;
+pub use lines::TimedLines;
+
+pub mod observe;
+
+pub mod metrics;
+
+pub mod stats;
+pub use stats::IoStats;
+
+pub mod throughput;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub mod builder;
+pub use builder::TimeoutBuilder;
+
+pub mod stream;
+pub use stream::{TimeoutStrategy, TimeoutStream, TimeoutStreamExt};
+
+pub mod shared;
+pub use shared::SharedTimeoutStream;
+
+pub mod throttle;
+pub use throttle::ThrottledWriter;
+
+pub mod tee;
+pub use tee::{OnSinkTimeout, TeeWriter};
+
+pub mod tap;
+pub use tap::{Tap, TapEvent};
+
+pub mod progress;
+pub use progress::{Progress, ProgressUpdate};
+
+pub mod multi;
+pub use multi::MultiReader;
+
+#[macro_use]
+pub mod select;
+
+#[cfg(feature = "reactor")]
+pub mod reactor;
+
+#[cfg(feature = "channel")]
+pub mod channel;
+
+pub mod child;
+pub use child::TimeoutChild;
+
+pub mod pty;
+pub use pty::TimeoutPty;
+
+pub mod config;
+pub use config::{
+    env_default_timeout, set_timeout_scale, timeout_scale, RetryPolicy, TimeoutConfig,
+    TimeoutOverride,
+};
+
+pub mod stdio;
+pub use stdio::stdin_with_timeout;
+
+pub mod pipe;
+pub use pipe::{timeout_pipe, timeout_socketpair};
+
+pub mod net;
+pub use net::{connect_any_within, TimeoutAcceptExt, TimeoutConnectExt};
+
+pub mod udp;
+pub use udp::TimeoutUdpSocket;
+
+pub mod ancillary;
+
+pub mod modbus;
+
+pub mod nmea;
+
+pub mod http;
+
+pub mod reply;
+pub use reply::{Reply, ReplyReader};
+
+#[cfg(feature = "framing")]
+pub mod framing;
+
+#[cfg(feature = "async-io")]
+pub mod aio;
+
+#[cfg(feature = "bytes")]
+mod bytes_mut;
+
+#[cfg(feature = "embedded-io")]
+mod embedded;
+
+#[cfg(feature = "mio")]
+mod source;
+
+#[cfg(feature = "serialport")]
+pub mod serial;
+#[cfg(feature = "serialport")]
+pub use serial::{SerialHandle, TimeoutSerialPortExt};
@@ -0,0 +1,197 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
+use std::mem;
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use super::budget;
+use super::TimeoutReader;
+
+/// The `TimedLines` struct iterates over the lines of a `TimeoutReader`, giving each
+/// line its own time limit.
+///
+/// The reader's own timeout still limits each wait, while the per-line limit caps the
+/// whole of each line, so a peer that trickles in bytes without ever finishing a line
+/// is caught. Lines are returned without their trailing `\n` or `\r\n`, as with
+/// `BufRead::lines`.
+///
+/// When a line runs out of time, the iterator returns an `io::ErrorKind::TimedOut`
+/// error. By default it then ends, discarding the unfinished line. With
+/// `resume_after_timeout(true)`, the unfinished line is kept instead, and the next
+/// call to `next` carries on with it where it left off, so no data is lost.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use std::io::ErrorKind;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let rdr = TimeoutReader::new(stream, Duration::new(1, 0));
+///
+/// let lines = rdr.timed_lines(Duration::new(5, 0)).resume_after_timeout(true);
+/// for line in lines {
+///     match line {
+///         Ok(line) => println!("{}", line),
+///         Err(ref e) if e.kind() == ErrorKind::TimedOut => println!("still waiting"),
+///         Err(e) => return Err(e),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TimedLines<H>
+where
+    H: Read + AsFd,
+{
+    rdr: BufReader<TimeoutReader<H>>,
+    per_line: Duration,
+    resume: bool,
+    partial: Vec<u8>,
+    done: bool,
+}
+
+impl<H> TimedLines<H>
+where
+    H: Read + AsFd,
+{
+    /// Create a new `TimedLines` reading lines from `rdr`, each within `per_line`.
+    pub fn new(rdr: TimeoutReader<H>, per_line: Duration) -> TimedLines<H> {
+        TimedLines {
+            rdr: BufReader::new(rdr),
+            per_line,
+            resume: false,
+            partial: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Sets whether a line that runs out of time is kept and resumed on the next call,
+    /// rather than ending the iterator.
+    pub fn resume_after_timeout(mut self, resume: bool) -> TimedLines<H> {
+        self.resume = resume;
+        self
+    }
+
+    /// Returns the unfinished line kept after a timeout, if any.
+    pub fn partial(&self) -> &[u8] {
+        &self.partial
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &TimeoutReader<H> {
+        self.rdr.get_ref()
+    }
+
+    /// Unwraps this `TimedLines`, returning the underlying reader.
+    ///
+    /// Any data that was read ahead, including an unfinished line, is lost.
+    pub fn into_inner(self) -> TimeoutReader<H> {
+        self.rdr.into_inner()
+    }
+
+    fn read_line(&mut self) -> Result<usize> {
+        let mut n = 0;
+        let partial = &mut self.partial;
+        budget::run_within(
+            &mut self.rdr,
+            |rdr| rdr.get_mut().budget_slot(),
+            self.per_line,
+            |rdr| {
+                n = rdr.read_until(b'\n', partial)?;
+                Ok(())
+            },
+        )?;
+        Ok(n)
+    }
+}
+
+impl<H> Iterator for TimedLines<H>
+where
+    H: Read + AsFd,
+{
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        if self.done {
+            return None;
+        }
+        match self.read_line() {
+            Ok(0) if self.partial.is_empty() => {
+                self.done = true;
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if e.kind() != ErrorKind::TimedOut || !self.resume {
+                    self.done = true;
+                    self.partial.clear();
+                }
+                return Some(Err(e));
+            }
+        }
+
+        let mut line = mem::take(&mut self.partial);
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        Some(
+            String::from_utf8(line).map_err(|_| {
+                Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutReader;
+
+    #[test]
+    fn timed_out_line_ends_or_resumes() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let rdr = TimeoutReader::new(left, Duration::new(5, 0));
+        let mut lines = rdr.timed_lines(Duration::from_millis(50));
+
+        right.write_all(b"one\r\ntw").unwrap();
+        assert_eq!("one", lines.next().unwrap().unwrap());
+        let err = lines.next().unwrap().unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(lines.next().is_none());
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let rdr = TimeoutReader::new(left, Duration::new(5, 0));
+        let mut lines = rdr
+            .timed_lines(Duration::from_millis(50))
+            .resume_after_timeout(true);
+
+        right.write_all(b"tw").unwrap();
+        let err = lines.next().unwrap().unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(b"tw", lines.partial());
+
+        right.write_all(b"o\nthree").unwrap();
+        drop(right);
+        assert_eq!("two", lines.next().unwrap().unwrap());
+        assert_eq!("three", lines.next().unwrap().unwrap());
+        assert!(lines.next().is_none());
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Per-operation observations for feeding an existing metrics pipeline.
+//!
+//! `IoStats` keeps running totals; a `MetricsSink` installed through
+//! `TimeoutBuilder::metrics_sink` instead hears about every `read` and `write` as
+//! it finishes, so it can record latency histograms, outcome counters, and the like
+//! in whatever system the application already uses.
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use super::observe::Direction;
+
+/// How an operation ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The operation succeeded.
+    Success,
+    /// The operation failed with `io::ErrorKind::TimedOut`.
+    TimedOut,
+    /// The operation failed with any other error.
+    Failed(ErrorKind),
+}
+
+/// A single finished `read` or `write`.
+#[derive(Clone, Copy, Debug)]
+pub struct Observation<'a> {
+    /// The name given to the wrapper with `TimeoutBuilder::name`, if any.
+    pub name: Option<&'a str>,
+    /// Whether the operation was a `read` or a `write`.
+    pub direction: Direction,
+    /// How long the operation took, including any wait for the handle to become
+    /// ready.
+    pub latency: Duration,
+    /// How the operation ended.
+    pub outcome: Outcome,
+    /// The number of bytes transferred, which is zero unless the operation
+    /// succeeded.
+    pub bytes: usize,
+}
+
+/// Receives an `Observation` for every `read` and `write` performed by the wrappers
+/// it is installed on.
+///
+/// The sink is called on the thread performing the operation, so it should be
+/// quick; typically it just updates a few counters or histograms.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::metrics::{MetricsSink, Observation, Outcome};
+/// use timeout_readwrite::TimeoutBuilder;
+/// use std::net::TcpStream;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::time::Duration;
+///
+/// #[derive(Default)]
+/// struct Timeouts(AtomicU64);
+///
+/// impl MetricsSink for Timeouts {
+///     fn observe(&self, observation: &Observation) {
+///         if observation.outcome == Outcome::TimedOut {
+///             self.0.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let stream = TimeoutBuilder::new()
+///     .name("upstream")
+///     .read_timeout(Duration::new(5, 0))
+///     .metrics_sink(Timeouts::default())
+///     .stream(stream);
+/// # Ok(())
+/// # }
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// Called once for every finished operation.
+    fn observe(&self, observation: &Observation);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::super::TimeoutBuilder;
+    use super::*;
+
+    type Seen = (Option<String>, Direction, Outcome, usize);
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Seen>>);
+
+    impl MetricsSink for Arc<Recorder> {
+        fn observe(&self, o: &Observation) {
+            let name = o.name.map(String::from);
+            self.0
+                .lock()
+                .unwrap()
+                .push((name, o.direction, o.outcome, o.bytes));
+        }
+    }
+
+    #[test]
+    fn sink_observes_each_operation() {
+        let recorder = Arc::new(Recorder::default());
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutBuilder::new()
+            .name("peer")
+            .read_timeout(Duration::from_millis(50))
+            .metrics_sink(recorder.clone())
+            .stream(left);
+
+        let mut buf = [0u8; 4];
+        assert!(left.read(&mut buf).is_err());
+        left.write_all(b"ping").unwrap();
+        right.read_exact(&mut buf).unwrap();
+
+        let peer = Some(String::from("peer"));
+        assert_eq!(
+            vec![
+                (peer.clone(), Direction::Read, Outcome::TimedOut, 0),
+                (peer, Direction::Write, Outcome::Success, 4),
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+}
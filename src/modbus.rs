@@ -0,0 +1,198 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Framing for Modbus RTU, where frames are separated by silence on the line.
+
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+
+use super::partial;
+use super::utils;
+use super::TimeoutReader;
+
+/// The longest Modbus RTU frame, in bytes.
+pub const MAX_FRAME: usize = 256;
+
+/// Returns the silent interval that separates Modbus RTU frames at `baud_rate`.
+///
+/// This is 3.5 character times of 11 bits each, or the fixed 1.75 ms the
+/// specification recommends above 19200 baud.
+pub fn frame_gap(baud_rate: u32) -> Duration {
+    if baud_rate > 19200 {
+        Duration::from_micros(1750)
+    } else {
+        Duration::from_nanos(38_500_000_000 / u64::from(baud_rate.max(1)))
+    }
+}
+
+/// The `RtuFramer` struct reads whole Modbus RTU frames from a `TimeoutReader`, using
+/// the silent interval after each frame to tell where it ends.
+///
+/// Each call to `read_frame` waits for the first byte of a frame, then collects bytes
+/// until the line has been silent for the frame gap. The whole frame, from waiting
+/// for its first byte to seeing the gap after its last, has to fit within the time
+/// passed to `read_frame`; otherwise an `io::ErrorKind::TimedOut` error carrying a
+/// `PartialTransfer` is returned and whatever was collected is dropped. The reader's
+/// own timeout does not apply.
+///
+/// Gaps are detected with `poll`, which counts in whole milliseconds, so the gap is
+/// rounded up to the next millisecond. The framer only delimits frames; checking
+/// the CRC is left to the caller.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::modbus::RtuFramer;
+/// use std::fs::File;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let port = File::open("/dev/ttyUSB0")?;
+/// let mut framer = RtuFramer::new(TimeoutReader::new(port, None), 9600);
+/// let frame = framer.read_frame(Duration::from_millis(500))?;
+/// println!("frame from unit {}", frame[0]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RtuFramer<H>
+where
+    H: Read + AsFd,
+{
+    rdr: TimeoutReader<H>,
+    gap: c_int,
+}
+
+impl<H> RtuFramer<H>
+where
+    H: Read + AsFd,
+{
+    /// Create a new `RtuFramer` reading from a line running at `baud_rate`.
+    pub fn new(rdr: TimeoutReader<H>, baud_rate: u32) -> RtuFramer<H> {
+        RtuFramer::with_gap(rdr, frame_gap(baud_rate))
+    }
+
+    /// Create a new `RtuFramer` that ends a frame after `gap` of silence.
+    pub fn with_gap(rdr: TimeoutReader<H>, gap: Duration) -> RtuFramer<H> {
+        let ms = gap.as_nanos().div_ceil(1_000_000).max(1);
+        RtuFramer {
+            rdr,
+            gap: ms.min(c_int::MAX as u128) as c_int,
+        }
+    }
+
+    /// Returns the silent interval that ends a frame.
+    pub fn gap(&self) -> Duration {
+        utils::ms_to_duration(self.gap)
+    }
+
+    /// Read the next whole frame, taking at most `timeout` for all of it.
+    ///
+    /// Frames longer than `MAX_FRAME` fail with an `io::ErrorKind::InvalidData` error,
+    /// and end of file before a frame starts with an `io::ErrorKind::UnexpectedEof`
+    /// error. End of file part way through a frame ends the frame.
+    pub fn read_frame(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + utils::ms_to_duration(utils::timeout_ms(timeout));
+        let mut frame = Vec::new();
+        let mut buf = [0u8; MAX_FRAME + 1];
+        loop {
+            let left = utils::duration_to_ms(deadline.saturating_duration_since(Instant::now()));
+            let wait = if frame.is_empty() {
+                left
+            } else {
+                left.min(self.gap)
+            };
+            match utils::wait_until_ready(Some(wait), &self.rdr, PollFlags::POLLIN) {
+                Err(ref e)
+                    if e.kind() == ErrorKind::TimedOut && !frame.is_empty() && wait == self.gap =>
+                {
+                    return Ok(frame)
+                }
+                Err(e) => return Err(partial::timed_out(frame.len(), e)),
+                Ok(()) => {}
+            }
+
+            match self.rdr.try_read(&mut buf)? {
+                0 if frame.is_empty() => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "end of file before a frame started",
+                    ))
+                }
+                0 => return Ok(frame),
+                n => frame.extend_from_slice(&buf[..n]),
+            }
+            if frame.len() > MAX_FRAME {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "frame longer than the Modbus RTU maximum",
+                ));
+            }
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &TimeoutReader<H> {
+        &self.rdr
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut TimeoutReader<H> {
+        &mut self.rdr
+    }
+
+    /// Unwraps this `RtuFramer`, returning the underlying reader.
+    pub fn into_inner(self) -> TimeoutReader<H> {
+        self.rdr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::PartialTransfer;
+    use super::*;
+
+    #[test]
+    fn frames_split_on_gap() {
+        assert_eq!(Duration::from_nanos(4_010_416), frame_gap(9600));
+        assert_eq!(Duration::from_micros(1750), frame_gap(115_200));
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let gap = Duration::from_millis(30);
+        let mut framer = RtuFramer::with_gap(TimeoutReader::new(left, None), gap);
+        let writer = thread::spawn(move || {
+            right.write_all(&[0x01, 0x03]).unwrap();
+            thread::sleep(Duration::from_millis(5));
+            right.write_all(&[0x00, 0x6b]).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            right.write_all(&[0x02, 0x06]).unwrap();
+            right
+        });
+
+        let timeout = Duration::new(1, 0);
+        assert_eq!(
+            vec![0x01, 0x03, 0x00, 0x6b],
+            framer.read_frame(timeout).unwrap()
+        );
+        assert_eq!(vec![0x02, 0x06], framer.read_frame(timeout).unwrap());
+        let _right = writer.join().unwrap();
+
+        let err = framer.read_frame(Duration::from_millis(20)).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(Some(0), PartialTransfer::of(&err).map(|p| p.transferred()));
+    }
+}
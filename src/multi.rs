@@ -0,0 +1,224 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::libc::c_int;
+use nix::poll::{PollFd, PollFlags};
+use std::fmt;
+use std::io::{Read, Result};
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use super::utils;
+
+struct Source<H> {
+    handle: H,
+    finished: bool,
+}
+
+/// The `MultiReader` struct reads from whichever of several handles has data first,
+/// waiting on all of them at once.
+///
+/// Each call to `read_any` waits, up to the timeout, until at least one source is
+/// readable, then reads from one of them and reports which. When several sources are
+/// ready at once, they take turns, so a busy source cannot starve a quiet one.
+///
+/// Once a source reaches end of file it is reported once, with a length of zero, and
+/// then left out of later waits. `read_any` returns `None` when every source has
+/// finished.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::MultiReader;
+/// use std::fs::File;
+/// use std::os::fd::OwnedFd;
+/// use std::process;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let mut child = process::Command::new("make")
+///     .stdout(process::Stdio::piped())
+///     .stderr(process::Stdio::piped())
+///     .spawn()?;
+///
+/// let mut rdr = MultiReader::new(Duration::new(5, 0));
+/// rdr.push(File::from(OwnedFd::from(child.stdout.take().unwrap())));
+/// rdr.push(File::from(OwnedFd::from(child.stderr.take().unwrap())));
+///
+/// let mut buf = [0u8; 4096];
+/// while let Some((index, n)) = rdr.read_any(&mut buf)? {
+///     println!("source {} sent {} bytes", index, n);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultiReader<H> {
+    sources: Vec<Source<H>>,
+    timeout: Option<c_int>,
+    next: usize,
+}
+
+impl<H> fmt::Debug for MultiReader<H>
+where
+    H: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let handles: Vec<&H> = self.sources.iter().map(|s| &s.handle).collect();
+        f.debug_struct("MultiReader")
+            .field("sources", &handles)
+            .field("timeout", &self.timeout.map(utils::ms_to_duration))
+            .finish()
+    }
+}
+
+impl<H> MultiReader<H>
+where
+    H: Read + AsFd,
+{
+    /// Create a new `MultiReader` with no sources, waiting at most `timeout` for any
+    /// of them to become readable.
+    ///
+    /// If the timeout is `None`, `read_any` calls block indefinitely.
+    pub fn new<T: Into<Option<Duration>>>(timeout: T) -> MultiReader<H> {
+        MultiReader {
+            sources: Vec::new(),
+            timeout: utils::initial_timeout(timeout.into()),
+            next: 0,
+        }
+    }
+
+    /// Add a source, returning the index that `read_any` reports for it.
+    pub fn push(&mut self, handle: H) -> usize {
+        self.sources.push(Source {
+            handle,
+            finished: false,
+        });
+        self.sources.len() - 1
+    }
+
+    /// Returns the number of sources, including those that have finished.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Returns `true` if no sources have been added.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Returns the timeout for each call to `read_any`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(utils::ms_to_duration)
+    }
+
+    /// Sets the timeout for each call to `read_any`.
+    ///
+    /// An `Err` is returned if the zero `Duration` is passed to this method.
+    pub fn set_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Wait for any source to become readable, then read into `buf` from one that is.
+    ///
+    /// Returns the index of the source and the number of bytes read, which is zero
+    /// when that source has reached end of file. Returns `None` once every source has
+    /// finished, and an `io::ErrorKind::TimedOut` error if none became readable in
+    /// time.
+    pub fn read_any(&mut self, buf: &mut [u8]) -> Result<Option<(usize, usize)>> {
+        let ready = {
+            let (indices, mut fds): (Vec<usize>, Vec<PollFd>) = self
+                .sources
+                .iter()
+                .enumerate()
+                .filter(|&(_, s)| !s.finished)
+                .map(|(i, s)| (i, PollFd::new(s.handle.as_fd(), PollFlags::POLLIN)))
+                .unzip();
+            if fds.is_empty() {
+                return Ok(None);
+            }
+
+            utils::wait_any(self.timeout, &mut fds)?;
+            let ready: Vec<usize> = indices
+                .into_iter()
+                .zip(fds.iter())
+                .filter(|&(_, fd)| fd.any().unwrap_or(false))
+                .map(|(i, _)| i)
+                .collect();
+            let next = self.next;
+            ready
+                .iter()
+                .find(|&&i| i >= next)
+                .or(ready.first())
+                .cloned()
+        };
+
+        // poll reported readiness, so one of the sources must be ready.
+        let index = ready.unwrap_or(self.next % self.sources.len());
+        let source = &mut self.sources[index];
+        let n = source.handle.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            source.finished = true;
+        }
+        self.next = index + 1;
+        Ok(Some((index, n)))
+    }
+
+    /// Gets a reference to the source at `index`.
+    pub fn get_ref(&self, index: usize) -> Option<&H> {
+        self.sources.get(index).map(|s| &s.handle)
+    }
+
+    /// Gets a mutable reference to the source at `index`.
+    ///
+    /// Reading directly from the source may cause data to be lost or reordered.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut H> {
+        self.sources.get_mut(index).map(|s| &mut s.handle)
+    }
+
+    /// Unwraps this `MultiReader`, returning the sources in the order they were added.
+    pub fn into_inner(self) -> Vec<H> {
+        self.sources.into_iter().map(|s| s.handle).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn reads_from_whichever_source_is_ready() {
+        let (first, mut first_peer) = UnixStream::pair().unwrap();
+        let (second, mut second_peer) = UnixStream::pair().unwrap();
+        let mut rdr = MultiReader::new(Duration::from_millis(50));
+        assert_eq!(0, rdr.push(first));
+        assert_eq!(1, rdr.push(second));
+
+        let mut buf = [0u8; 16];
+        let err = rdr.read_any(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        second_peer.write_all(b"two").unwrap();
+        assert_eq!(Some((1, 3)), rdr.read_any(&mut buf).unwrap());
+
+        first_peer.write_all(b"one").unwrap();
+        second_peer.write_all(b"again").unwrap();
+        assert_eq!(Some((0, 3)), rdr.read_any(&mut buf).unwrap());
+        assert_eq!(Some((1, 5)), rdr.read_any(&mut buf).unwrap());
+
+        drop(first_peer);
+        drop(second_peer);
+        assert_eq!(Some((0, 0)), rdr.read_any(&mut buf).unwrap());
+        assert_eq!(Some((1, 0)), rdr.read_any(&mut buf).unwrap());
+        assert_eq!(None, rdr.read_any(&mut buf).unwrap());
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags};
+use nix::sys::socket::{self, sockopt, AddressFamily, SockFlag, SockType};
+use nix::sys::socket::{SockaddrLike, SockaddrStorage, UnixAddr};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::net::{self as unix, UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::utils;
+use super::TimeoutStream;
+
+/// Adds a timed `accept` to listening sockets.
+///
+/// The `accept` call on a listener will block forever until a connection comes in,
+/// which makes it hard for an accept loop to notice that it has been asked to shut
+/// down. `accept_timeout` waits until a connection is pending, up until an optional
+/// timeout, before actually performing the `accept` operation.
+///
+/// If the wait times out, `accept_timeout` returns an `io::ErrorKind::TimedOut`
+/// variant as the value of `io::Error`.
+pub trait TimeoutAcceptExt {
+    /// The type of the connected stream produced by `accept`.
+    type Stream;
+    /// The type of the peer address produced by `accept`.
+    type Addr;
+
+    /// Accept a new incoming connection, waiting at most `timeout` for one to arrive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutAcceptExt;
+    /// use std::io::ErrorKind;
+    /// use std::net::TcpListener;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let listener = TcpListener::bind("127.0.0.1:8080")?;
+    /// loop {
+    ///     match listener.accept_timeout(Duration::new(1, 0)) {
+    ///         Ok((stream, addr)) => { /* handle the connection */ }
+    ///         Err(ref e) if e.kind() == ErrorKind::TimedOut => { /* check for shutdown */ }
+    ///         Err(e) => return Err(e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    fn accept_timeout<T: Into<Option<Duration>>>(
+        &self,
+        timeout: T,
+    ) -> Result<(Self::Stream, Self::Addr)>;
+}
+
+impl TimeoutAcceptExt for TcpListener {
+    type Stream = TcpStream;
+    type Addr = SocketAddr;
+
+    fn accept_timeout<T: Into<Option<Duration>>>(
+        &self,
+        timeout: T,
+    ) -> Result<(TcpStream, SocketAddr)> {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, self, PollFlags::POLLIN)?;
+        self.accept()
+    }
+}
+
+impl TimeoutAcceptExt for UnixListener {
+    type Stream = UnixStream;
+    type Addr = unix::SocketAddr;
+
+    fn accept_timeout<T: Into<Option<Duration>>>(
+        &self,
+        timeout: T,
+    ) -> Result<(UnixStream, unix::SocketAddr)> {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, self, PollFlags::POLLIN)?;
+        self.accept()
+    }
+}
+
+/// Adds a timed `connect` to stream sockets, producing a `TimeoutStream`.
+///
+/// The socket is put into nonblocking mode and the connection is started. The
+/// connect helper then waits until the socket becomes writable, up until an optional
+/// timeout, and checks `SO_ERROR` to learn whether the connection succeeded. The
+/// socket is returned to blocking mode before it is handed back.
+///
+/// If the wait times out, the connection attempt is abandoned and
+/// `connect_with_timeout` returns an `io::ErrorKind::TimedOut` variant as the value
+/// of `io::Error`. A refused or otherwise failed connection is reported with the
+/// error from `SO_ERROR`.
+pub trait TimeoutConnectExt: Sized + Read + Write + AsFd {
+    /// The type of address this kind of socket connects to.
+    type Addr: ?Sized;
+
+    /// Connect to `addr`, waiting at most `timeout` for the connection to complete.
+    ///
+    /// The same `timeout` is installed as both the read and write timeout of the
+    /// returned `TimeoutStream`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutConnectExt;
+    /// use std::io::Write;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let addr = "127.0.0.1:8080".parse().unwrap();
+    /// let mut stream = TcpStream::connect_with_timeout(&addr, Duration::new(5, 0))?;
+    /// stream.write_all(b"hello")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn connect_with_timeout<T: Into<Option<Duration>>>(
+        addr: &Self::Addr,
+        timeout: T,
+    ) -> Result<TimeoutStream<Self>>;
+}
+
+impl TimeoutConnectExt for TcpStream {
+    type Addr = SocketAddr;
+
+    fn connect_with_timeout<T: Into<Option<Duration>>>(
+        addr: &SocketAddr,
+        timeout: T,
+    ) -> Result<TimeoutStream<TcpStream>> {
+        let timeout = timeout.into();
+        let family = match *addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
+
+        let stream = TcpStream::from(stream_socket(family)?);
+        stream.set_nonblocking(true)?;
+        connect_nonblocking(&stream, &SockaddrStorage::from(*addr), timeout)?;
+        stream.set_nonblocking(false)?;
+
+        Ok(TimeoutStream::new(stream, timeout, timeout))
+    }
+}
+
+impl TimeoutConnectExt for UnixStream {
+    type Addr = Path;
+
+    fn connect_with_timeout<T: Into<Option<Duration>>>(
+        path: &Path,
+        timeout: T,
+    ) -> Result<TimeoutStream<UnixStream>> {
+        let timeout = timeout.into();
+        let addr = UnixAddr::new(path)?;
+
+        let stream = UnixStream::from(stream_socket(AddressFamily::Unix)?);
+        stream.set_nonblocking(true)?;
+        connect_nonblocking(&stream, &addr, timeout)?;
+        stream.set_nonblocking(false)?;
+
+        Ok(TimeoutStream::new(stream, timeout, timeout))
+    }
+}
+
+/// Connect to the first of `addrs` that accepts a connection, with every attempt
+/// charged against one overall `budget`.
+///
+/// `addrs` is resolved once, and its addresses are tried in order. Each attempt gets
+/// an equal share of what is left of the budget, so an address that never answers
+/// cannot use up the time meant for the ones after it; an attempt that fails quickly
+/// leaves its unused share to the rest. Name resolution is charged against the budget
+/// too, although it cannot be interrupted.
+///
+/// The `budget` is installed as both the read and write timeout of the returned
+/// `TimeoutStream`, as with `connect_with_timeout`. If no address could be connected
+/// to, the error from the last attempt is returned, which is an
+/// `io::ErrorKind::TimedOut` error if the budget ran out.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::net::connect_any_within;
+/// use std::io::Write;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let mut stream = connect_any_within("localhost:8080", Duration::new(5, 0))?;
+/// stream.write_all(b"hello")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn connect_any_within<A: ToSocketAddrs>(
+    addrs: A,
+    budget: Duration,
+) -> Result<TimeoutStream<TcpStream>> {
+    // Clamp the budget the way the wrappers do, so a huge one cannot overflow.
+    let deadline = Instant::now() + utils::ms_to_duration(utils::duration_to_ms(budget));
+    let addrs: Vec<SocketAddr> = addrs.to_socket_addrs()?.collect();
+
+    let mut last = Error::new(
+        ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    );
+    for (index, addr) in addrs.iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "timed out connecting to any address",
+            ));
+        }
+        let share = remaining / (addrs.len() - index) as u32;
+        match TcpStream::connect_with_timeout(addr, share) {
+            Ok(stream) => return Ok(TimeoutStream::new(stream.into_inner(), budget, budget)),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Create a close-on-exec stream socket in the given address family.
+fn stream_socket(family: AddressFamily) -> Result<OwnedFd> {
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    {
+        Ok(socket::socket(
+            family,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?)
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+        let fd = socket::socket(family, SockType::Stream, SockFlag::empty(), None)?;
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok(fd)
+    }
+}
+
+/// Start a connection on a nonblocking socket and wait for it to complete.
+fn connect_nonblocking<F, A>(fd: &F, addr: &A, timeout: Option<Duration>) -> Result<()>
+where
+    F: AsFd,
+    A: SockaddrLike,
+{
+    match socket::connect(fd.as_fd().as_raw_fd(), addr) {
+        Ok(()) => return Ok(()),
+        Err(Errno::EINPROGRESS) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Unlike `wait_until_ready`, this waits for as long as it takes without a timeout,
+    // since the outcome is only known once the socket is writable.
+    let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLOUT)];
+    utils::wait_any(timeout.map(utils::timeout_ms), &mut fds)?;
+    connect_result(fd)
+}
+
+/// Report the outcome of a finished connection attempt, from `SO_ERROR`.
+pub(crate) fn connect_result<F: AsFd>(fd: &F) -> Result<()> {
+    match socket::getsockopt(fd, sockopt::SocketError)? {
+        0 => Ok(()),
+        errno => Err(Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn tcp_accept_times_out_without_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let err = listener
+            .accept_timeout(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn tcp_accept_pending_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let (_stream, addr) = listener.accept_timeout(Duration::new(5, 0)).unwrap();
+        assert_eq!(client.local_addr().unwrap(), addr);
+    }
+
+    /// A listener whose accept queue is already full, so that a new connection to it
+    /// stays in progress until `accept` makes room, along with the connection
+    /// filling it.
+    fn full_listener() -> (TcpListener, TcpStream) {
+        let fd = stream_socket(AddressFamily::Inet).unwrap();
+        let addr = SockaddrStorage::from("127.0.0.1:0".parse::<SocketAddr>().unwrap());
+        socket::bind(fd.as_raw_fd(), &addr).unwrap();
+        socket::listen(&fd, socket::Backlog::new(0).unwrap()).unwrap();
+        let listener = TcpListener::from(fd);
+        let first = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener, first)
+    }
+
+    #[test]
+    fn tcp_connect_without_timeout_waits() {
+        let (listener, _first) = full_listener();
+        let addr = listener.local_addr().unwrap();
+
+        let connector = thread::spawn(move || {
+            let stream = TcpStream::connect_with_timeout(&addr, None).unwrap();
+            stream.get_ref().peer_addr().unwrap()
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert!(!connector.is_finished());
+
+        listener.accept().unwrap();
+        assert_eq!(addr, connector.join().unwrap());
+    }
+
+    /// Start connecting a nonblocking socket to `addr`.
+    fn start_connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::from(stream_socket(AddressFamily::Inet).unwrap());
+        stream.set_nonblocking(true).unwrap();
+        match socket::connect(stream.as_raw_fd(), &SockaddrStorage::from(addr)) {
+            Ok(()) | Err(Errno::EINPROGRESS) => stream,
+            Err(e) => panic!("connect failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn wait_connected_reports_outcome() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = TimeoutStream::new(start_connect(addr), None, Duration::new(5, 0));
+        stream.wait_connected().unwrap();
+        assert_eq!(addr, stream.get_ref().peer_addr().unwrap());
+
+        drop(listener);
+        let stream = TimeoutStream::new(start_connect(addr), None, Duration::new(5, 0));
+        let err = stream.wait_connected().unwrap_err();
+        assert_eq!(ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn wait_connected_without_timeout_waits() {
+        let (listener, _first) = full_listener();
+        let addr = listener.local_addr().unwrap();
+
+        let stream = TimeoutStream::new(start_connect(addr), None, None);
+        let connector = thread::spawn(move || {
+            stream.wait_connected().unwrap();
+            stream.get_ref().peer_addr().unwrap()
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert!(!connector.is_finished());
+
+        listener.accept().unwrap();
+        assert_eq!(addr, connector.join().unwrap());
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("timeout-readwrite-{}-{}.sock", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn unix_accept_times_out_without_connection() {
+        let path = socket_path("unix-accept-timeout");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let err = listener
+            .accept_timeout(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unix_accept_pending_connection() {
+        let path = socket_path("unix-accept");
+        let listener = UnixListener::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+
+        let (_stream, addr) = listener.accept_timeout(Duration::new(5, 0)).unwrap();
+        assert!(addr.is_unnamed());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tcp_connect_with_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect_with_timeout(&addr, Duration::new(5, 0)).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        client.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hello", &buf);
+    }
+
+    #[test]
+    fn tcp_connect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let err = TcpStream::connect_with_timeout(&addr, Duration::new(5, 0)).unwrap_err();
+        assert_eq!(ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn connect_any_skips_refused_address() {
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused_addr = refused.local_addr().unwrap();
+        drop(refused);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let addrs = [refused_addr, addr];
+        let stream = connect_any_within(&addrs[..], Duration::new(5, 0)).unwrap();
+        assert_eq!(addr, stream.get_ref().peer_addr().unwrap());
+
+        let stream = connect_any_within(&addrs[..], Duration::MAX).unwrap();
+        assert_eq!(addr, stream.get_ref().peer_addr().unwrap());
+
+        let err = connect_any_within(&[refused_addr][..], Duration::new(5, 0)).unwrap_err();
+        assert_eq!(ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn unix_connect_with_timeout() {
+        let path = socket_path("unix-connect");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut client = UnixStream::connect_with_timeout(&path, Duration::new(5, 0)).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        server.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hello", &buf);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
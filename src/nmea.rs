@@ -0,0 +1,221 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading NMEA 0183 sentences, as sent by GPS receivers, with a time limit each.
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
+use std::os::fd::AsFd;
+use std::str;
+use std::time::Duration;
+
+use super::budget;
+use super::TimeoutReader;
+
+/// The longest sentence NMEA 0183 allows, from the leading `$` to the final `\n`.
+pub const MAX_SENTENCE: usize = 82;
+
+/// The `NmeaReader` struct reads NMEA sentences from a `TimeoutReader`, giving each
+/// sentence its own time limit.
+///
+/// Anything before the `$` or `!` that starts a sentence, such as the tail of a
+/// sentence cut off when the port was opened, is skipped. A receiver that stalls
+/// part way through a sentence, or stops sending altogether, surfaces as an
+/// `io::ErrorKind::TimedOut` error once the per-sentence limit has elapsed, rather
+/// than a hang. Sentences longer than the length cap fail with an
+/// `io::ErrorKind::InvalidData` error, so that line noise cannot grow the buffer
+/// without bound; the next call picks up at the following sentence.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::nmea::NmeaReader;
+/// use std::fs::File;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let port = File::open("/dev/ttyACM0")?;
+/// let mut gps = NmeaReader::new(TimeoutReader::new(port, None), Duration::new(2, 0))
+///     .verify_checksum(true);
+/// loop {
+///     let sentence = gps.read_sentence()?;
+///     if sentence.starts_with("$GPGGA") {
+///         println!("{}", sentence);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NmeaReader<H>
+where
+    H: Read + AsFd,
+{
+    rdr: BufReader<TimeoutReader<H>>,
+    per_sentence: Duration,
+    max_len: usize,
+    verify: bool,
+}
+
+impl<H> NmeaReader<H>
+where
+    H: Read + AsFd,
+{
+    /// Create a new `NmeaReader` reading sentences from `rdr`, each within
+    /// `per_sentence`.
+    pub fn new(rdr: TimeoutReader<H>, per_sentence: Duration) -> NmeaReader<H> {
+        NmeaReader {
+            rdr: BufReader::new(rdr),
+            per_sentence,
+            max_len: MAX_SENTENCE,
+            verify: false,
+        }
+    }
+
+    /// Sets the longest sentence accepted, counting the leading `$` and the final
+    /// `\r\n`. This is `MAX_SENTENCE` by default; some receivers send longer
+    /// proprietary sentences.
+    pub fn max_len(mut self, max_len: usize) -> NmeaReader<H> {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Sets whether sentences must end in a valid `*hh` checksum. Sentences without
+    /// one, or with a wrong one, then fail with an `io::ErrorKind::InvalidData` error.
+    /// This is off by default.
+    pub fn verify_checksum(mut self, verify: bool) -> NmeaReader<H> {
+        self.verify = verify;
+        self
+    }
+
+    /// Read the next sentence, without its trailing `\r\n`.
+    pub fn read_sentence(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        let max_len = self.max_len;
+        budget::run_within(
+            &mut self.rdr,
+            |rdr| rdr.get_mut().budget_slot(),
+            self.per_sentence,
+            |rdr| read_line(rdr, max_len, &mut line),
+        )?;
+
+        while let Some(&(b'\r' | b'\n')) = line.last() {
+            line.pop();
+        }
+        let sentence = String::from_utf8(line)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "sentence is not ASCII"))?;
+        if self.verify && !checksum_matches(&sentence) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "sentence checksum is missing or wrong",
+            ));
+        }
+        Ok(sentence)
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &TimeoutReader<H> {
+        self.rdr.get_ref()
+    }
+
+    /// Unwraps this `NmeaReader`, returning the underlying reader.
+    ///
+    /// Any data that was read ahead is lost.
+    pub fn into_inner(self) -> TimeoutReader<H> {
+        self.rdr.into_inner()
+    }
+}
+
+/// Read from the start of the next sentence up to and including its `\n` into `line`.
+fn read_line<R: BufRead>(rdr: &mut R, max_len: usize, line: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let available = rdr.fill_buf()?;
+        if available.is_empty() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "end of file before a whole sentence",
+            ));
+        }
+
+        let mut used = 0;
+        let mut done = false;
+        for &b in available {
+            used += 1;
+            if line.is_empty() && b != b'$' && b != b'!' {
+                continue;
+            }
+            line.push(b);
+            if b == b'\n' {
+                done = true;
+                break;
+            }
+            if line.len() >= max_len {
+                break;
+            }
+        }
+        rdr.consume(used);
+
+        if done {
+            return Ok(());
+        }
+        if line.len() >= max_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "sentence longer than the length cap",
+            ));
+        }
+    }
+}
+
+/// Returns whether `sentence` ends in a `*hh` checksum matching its contents.
+fn checksum_matches(sentence: &str) -> bool {
+    let star = match sentence.rfind('*') {
+        Some(star) if star > 0 => star,
+        _ => return false,
+    };
+    let expected = match u8::from_str_radix(&sentence[star + 1..], 16) {
+        Ok(expected) if sentence.len() == star + 3 => expected,
+        _ => return false,
+    };
+    sentence.as_bytes()[1..star]
+        .iter()
+        .fold(0, |acc, b| acc ^ b)
+        == expected
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn reads_sentences_until_stall() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut gps = NmeaReader::new(TimeoutReader::new(left, None), Duration::from_millis(50))
+            .verify_checksum(true);
+
+        right
+            .write_all(b"0*4F\r\n$GPGLL,4916.45,N,12311.12,W,225444,A*31\r\n")
+            .unwrap();
+        right.write_all(b"$GPGLL,4916.45,N*00\r\n$GPRMC,1").unwrap();
+        assert_eq!(
+            "$GPGLL,4916.45,N,12311.12,W,225444,A*31",
+            gps.read_sentence().unwrap()
+        );
+        let err = gps.read_sentence().unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        let err = gps.read_sentence().unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right.write_all(&[b'$'; MAX_SENTENCE + 1]).unwrap();
+        let err = gps.read_sentence().unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks for watching the waits a wrapper performs.
+//!
+//! A `WaitObserver` installed on a wrapper through `TimeoutBuilder::observer` is
+//! told whenever the wrapper starts waiting for its handle to become ready and how
+//! the wait ended. Waits only happen when the wrapper has a timeout (or a
+//! `TimeBudget`); without one, operations go straight to the handle and the
+//! observer is not called.
+
+use std::time::Duration;
+
+/// The direction of the operation a wrapper is waiting to perform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Waiting for the handle to become readable.
+    Read,
+    /// Waiting for the handle to become writable.
+    Write,
+}
+
+/// Callbacks invoked around each wait a wrapper performs.
+///
+/// Every method has an empty default implementation, so an observer only needs to
+/// implement the ones it cares about. Each wait results in one call to
+/// `on_wait_start` followed by either `on_wait_end` or `on_timeout`. The callbacks
+/// run on the thread performing the operation, so they should be quick.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::observe::{Direction, WaitObserver};
+/// use timeout_readwrite::TimeoutBuilder;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// struct SlowWaits;
+///
+/// impl WaitObserver for SlowWaits {
+///     fn on_wait_end(&self, direction: Direction, elapsed: Duration) {
+///         if elapsed > Duration::from_millis(500) {
+///             eprintln!("slow {:?} wait: {:?}", direction, elapsed);
+///         }
+///     }
+/// }
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let stream = TimeoutBuilder::new()
+///     .read_timeout(Duration::new(5, 0))
+///     .observer(SlowWaits)
+///     .stream(stream);
+/// # Ok(())
+/// # }
+/// ```
+pub trait WaitObserver: Send + Sync {
+    /// Called before waiting, with the longest the wait may last.
+    fn on_wait_start(&self, direction: Direction, timeout: Duration) {
+        let _ = (direction, timeout);
+    }
+
+    /// Called once the handle is ready, or the wait failed for a reason other than a
+    /// timeout, with the time spent waiting.
+    fn on_wait_end(&self, direction: Direction, elapsed: Duration) {
+        let _ = (direction, elapsed);
+    }
+
+    /// Called when the wait timed out, with the time spent waiting.
+    fn on_timeout(&self, direction: Direction, elapsed: Duration) {
+        let _ = (direction, elapsed);
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reporting how much data moved before a multi-step helper timed out.
+
+use memchr::memmem;
+use std::error;
+use std::fmt;
+use std::io::{BufRead, Error, ErrorKind, Read, Result, Write};
+use std::str;
+
+/// The `PartialTransfer` struct is attached to the `io::ErrorKind::TimedOut` error of
+/// a helper that performs several reads or writes, such as `read_exact_within`, and
+/// records how many bytes were transferred before it timed out.
+///
+/// A caller can use this to decide whether a stream is still usable: nothing
+/// transferred means the protocol is still in step, while a partial message usually
+/// means the connection has to be dropped.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{PartialTransfer, TimeoutReader};
+/// use std::io::ErrorKind;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let mut rdr = TimeoutReader::new(stream, Duration::new(1, 0));
+///
+/// let mut msg = [0u8; 64];
+/// match rdr.read_exact_within(&mut msg, Duration::new(5, 0)) {
+///     Ok(_) => println!("got a message"),
+///     Err(ref e) if e.kind() == ErrorKind::TimedOut => {
+///         let n = PartialTransfer::of(e).map_or(0, |p| p.transferred());
+///         println!("timed out after {} bytes", n);
+///     }
+///     Err(e) => return Err(e),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PartialTransfer {
+    transferred: usize,
+    source: Error,
+}
+
+impl PartialTransfer {
+    /// Returns the number of bytes transferred before the timeout.
+    pub fn transferred(&self) -> usize {
+        self.transferred
+    }
+
+    /// Returns the `PartialTransfer` attached to `err`, if there is one.
+    pub fn of(err: &Error) -> Option<&PartialTransfer> {
+        err.get_ref().and_then(|e| e.downcast_ref())
+    }
+}
+
+impl fmt::Display for PartialTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} after transferring {} bytes",
+            self.source, self.transferred
+        )
+    }
+}
+
+impl error::Error for PartialTransfer {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// How much of a bulk transfer completed, from helpers such as `read_exact_partial`
+/// that treat running out of time as a normal outcome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+    /// All of the data was transferred.
+    Complete,
+    /// Time ran out after `n` bytes were transferred.
+    Partial {
+        /// The number of bytes transferred.
+        n: usize,
+    },
+}
+
+/// What `read_to_string_within` does with bytes that do not form complete UTF-8
+/// characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Tail {
+    /// Keep only complete, valid text. If time runs out part way through a
+    /// character, the text before it is kept and the rest of the character dropped.
+    /// Invalid UTF-8 anywhere fails the read with an `io::ErrorKind::InvalidData`
+    /// error and leaves the string alone.
+    ValidPrefix,
+    /// Keep everything, replacing invalid or incomplete sequences with U+FFFD
+    /// REPLACEMENT CHARACTER.
+    Lossy,
+}
+
+/// Append the text in `bytes`, the outcome of reading them being `rslt`, to `buf` as
+/// `tail` says, returning the length of the text appended.
+pub fn append_utf8(
+    buf: &mut String,
+    bytes: &[u8],
+    rslt: Result<usize>,
+    tail: Utf8Tail,
+) -> Result<usize> {
+    let text = match tail {
+        Utf8Tail::Lossy => String::from_utf8_lossy(bytes),
+        Utf8Tail::ValidPrefix => match str::from_utf8(bytes) {
+            Ok(text) => text.into(),
+            // An unfinished character is only to be expected if the read stopped early.
+            Err(ref e) if e.error_len().is_none() && rslt.is_err() => {
+                String::from_utf8_lossy(&bytes[..e.valid_up_to()])
+            }
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ))
+            }
+        },
+    };
+    buf.push_str(&text);
+    rslt.map(|_| text.len())
+}
+
+/// Turn the result of a `*_within` helper into a `Transfer`, with a timeout as a
+/// partial transfer.
+pub fn transfer<T>(rslt: Result<T>) -> Result<Transfer> {
+    match rslt {
+        Ok(_) => Ok(Transfer::Complete),
+        Err(ref e) if e.kind() == ErrorKind::TimedOut => Ok(Transfer::Partial {
+            n: PartialTransfer::of(e).map_or(0, PartialTransfer::transferred),
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Attach the number of bytes `transferred` to a timeout error.
+pub(crate) fn timed_out(transferred: usize, err: Error) -> Error {
+    if err.kind() == ErrorKind::TimedOut {
+        Error::new(
+            ErrorKind::TimedOut,
+            PartialTransfer {
+                transferred,
+                source: err,
+            },
+        )
+    } else {
+        err
+    }
+}
+
+/// `Read::read_exact`, with the number of bytes read attached to a timeout.
+pub fn read_exact<R: Read + ?Sized>(rdr: &mut R, buf: &mut [u8]) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match rdr.read(&mut buf[filled..]) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(timed_out(filled, e)),
+        }
+    }
+    Ok(())
+}
+
+/// `Read::read_to_end`, with the number of bytes read attached to a timeout.
+///
+/// The bytes read before an error stay at the end of `buf`.
+pub fn read_to_end<R: Read + ?Sized>(rdr: &mut R, buf: &mut Vec<u8>) -> Result<usize> {
+    let start = buf.len();
+    let mut chunk = [0u8; 8192];
+    loop {
+        match rdr.read(&mut chunk) {
+            Ok(0) => return Ok(buf.len() - start),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(timed_out(buf.len() - start, e)),
+        }
+    }
+}
+
+/// Read onto the end of `buf` up to and including the first occurrence of `pattern`,
+/// with the number of bytes read attached to a timeout.
+///
+/// The bytes read before an error stay at the end of `buf`. Reaching end of file
+/// before the pattern is an `io::ErrorKind::UnexpectedEof` error.
+pub fn read_until_pattern<R: BufRead + ?Sized>(
+    rdr: &mut R,
+    pattern: &[u8],
+    buf: &mut Vec<u8>,
+) -> Result<usize> {
+    let start = buf.len();
+    if pattern.is_empty() {
+        return Ok(0);
+    }
+    let finder = memmem::Finder::new(pattern);
+    loop {
+        let available = match rdr.fill_buf() {
+            Ok(available) => available,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(timed_out(buf.len() - start, e)),
+        };
+        if available.is_empty() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "stream ended before the pattern was found",
+            ));
+        }
+
+        // The pattern may straddle what was already read and what just arrived.
+        let from = (buf.len() + 1).saturating_sub(pattern.len()).max(start);
+        let before = buf.len();
+        let n = available.len();
+        buf.extend_from_slice(available);
+        match finder.find(&buf[from..]) {
+            Some(pos) => {
+                let end = from + pos + pattern.len();
+                buf.truncate(end);
+                rdr.consume(end - before);
+                return Ok(end - start);
+            }
+            None => rdr.consume(n),
+        }
+    }
+}
+
+/// `Write::write_all`, with the number of bytes written attached to a timeout.
+pub fn write_all<W: Write + ?Sized>(wtr: &mut W, buf: &[u8]) -> Result<()> {
+    let mut written = 0;
+    while written < buf.len() {
+        match wtr.write(&buf[written..]) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(timed_out(written, e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutStream;
+    use super::*;
+
+    #[test]
+    fn timeout_is_a_partial_transfer() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::new(5, 0), None);
+        let mut buf = [0u8; 4];
+
+        let timeout = Duration::from_millis(50);
+        assert_eq!(
+            Transfer::Partial { n: 0 },
+            stream.read_exact_partial(&mut buf, timeout).unwrap()
+        );
+
+        right.write_all(b"abc").unwrap();
+        assert_eq!(
+            Transfer::Partial { n: 3 },
+            stream.read_exact_partial(&mut buf, timeout).unwrap()
+        );
+
+        right.write_all(b"defg").unwrap();
+        assert_eq!(
+            Transfer::Complete,
+            stream.read_exact_partial(&mut buf, timeout).unwrap()
+        );
+        assert_eq!(b"defg", &buf);
+
+        drop(right);
+        assert!(stream.read_exact_partial(&mut buf, timeout).is_err());
+    }
+
+    #[test]
+    fn read_to_string_within_handles_split_character() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::new(5, 0), None);
+        let timeout = Duration::from_millis(50);
+
+        // The first two bytes of the three in "€".
+        right.write_all(b"cost: \xe2\x82").unwrap();
+        let mut text = String::new();
+        let err = stream
+            .read_to_string_within(&mut text, timeout, Utf8Tail::ValidPrefix)
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!("cost: ", text);
+
+        right.write_all(b"\xe2\x82").unwrap();
+        let mut text = String::new();
+        let err = stream
+            .read_to_string_within(&mut text, timeout, Utf8Tail::Lossy)
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!("\u{fffd}", text);
+
+        right.write_all(b"\xff").unwrap();
+        drop(right);
+        let err = stream
+            .read_to_string_within(&mut text, timeout, Utf8Tail::ValidPrefix)
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!("\u{fffd}", text);
+    }
+
+    #[test]
+    fn pattern_found_across_reads_or_timeout_keeps_data() {
+        use std::io::BufReader;
+        use std::thread;
+
+        use super::super::TimeoutReader;
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = BufReader::new(TimeoutReader::new(left, Duration::from_millis(100)));
+        let writer = thread::spawn(move || {
+            right.write_all(b"+CSQ: 20\r\nO").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            right.write_all(b"K\r\nnext").unwrap();
+            right
+        });
+
+        let mut reply = Vec::new();
+        let n = read_until_pattern(&mut rdr, b"\r\nOK\r\n", &mut reply).unwrap();
+        assert_eq!(b"+CSQ: 20\r\nOK\r\n", &reply[..]);
+        assert_eq!(reply.len(), n);
+        let _right = writer.join().unwrap();
+
+        reply.clear();
+        let err = read_until_pattern(&mut rdr, b"\r\nOK\r\n", &mut reply).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(Some(4), PartialTransfer::of(&err).map(|p| p.transferred()));
+        assert_eq!(b"next", &reply[..]);
+    }
+
+    #[test]
+    fn read_to_end_within_keeps_data_on_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::new(5, 0), None);
+        let mut buf = b"head:".to_vec();
+
+        right.write_all(b"trickle").unwrap();
+        let err = stream
+            .read_to_end_within(&mut buf, Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(7, PartialTransfer::of(&err).unwrap().transferred());
+        assert_eq!(b"head:trickle", &buf[..]);
+
+        right.write_all(b"!").unwrap();
+        drop(right);
+        let n = stream
+            .read_to_end_within(&mut buf, Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(1, n);
+        assert_eq!(b"head:trickle!", &buf[..]);
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::unistd;
+use std::fs::File;
+use std::io::Result;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use super::{TimeoutReader, TimeoutStream, TimeoutWriter};
+
+/// Create a pipe whose ends both time out, returning the reading end wrapped in a
+/// `TimeoutReader` and the writing end in a `TimeoutWriter`.
+///
+/// Both ends are created with the close-on-exec flag set, so they are not leaked
+/// into child processes. The descriptors are wrapped as `File`s, since `OwnedFd`
+/// implements neither `Read` nor `Write`.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::timeout_pipe;
+/// use std::io::{ErrorKind, Read, Write};
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let (mut rdr, mut wtr) = timeout_pipe(Duration::from_millis(100), Duration::new(5, 0))?;
+/// wtr.write_all(b"hello")?;
+///
+/// let mut buf = [0u8; 5];
+/// rdr.read_exact(&mut buf)?;
+/// assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+/// # Ok(())
+/// # }
+/// ```
+pub fn timeout_pipe<R, W>(
+    read_timeout: R,
+    write_timeout: W,
+) -> Result<(TimeoutReader<File>, TimeoutWriter<File>)>
+where
+    R: Into<Option<Duration>>,
+    W: Into<Option<Duration>>,
+{
+    let (rdr, wtr) = cloexec_pipe()?;
+    Ok((
+        TimeoutReader::from_owned_fd(rdr, read_timeout),
+        TimeoutWriter::from_owned_fd(wtr, write_timeout),
+    ))
+}
+
+/// Create a pipe with the close-on-exec flag set on both ends.
+fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    {
+        use nix::fcntl::OFlag;
+
+        Ok(unistd::pipe2(OFlag::O_CLOEXEC)?)
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+        use std::os::fd::AsRawFd;
+
+        let (rdr, wtr) = unistd::pipe()?;
+        fcntl(rdr.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        fcntl(wtr.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok((rdr, wtr))
+    }
+}
+
+/// Create a connected pair of Unix domain sockets, each wrapped in a `TimeoutStream`
+/// with the same read and write timeouts.
+///
+/// This is a quick way to set up a duplex channel between a parent and child
+/// process, or between two ends of a protocol under test. Like
+/// `UnixStream::pair`, both sockets have the close-on-exec flag set; clear it
+/// on the one meant for a child process.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::timeout_socketpair;
+/// use std::io::{Read, Write};
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let (mut parent, mut child) = timeout_socketpair(Duration::new(5, 0), Duration::new(5, 0))?;
+/// parent.write_all(b"ping")?;
+///
+/// let mut buf = [0u8; 4];
+/// child.read_exact(&mut buf)?;
+/// child.write_all(b"pong")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn timeout_socketpair<R, W>(
+    read_timeout: R,
+    write_timeout: W,
+) -> Result<(TimeoutStream<UnixStream>, TimeoutStream<UnixStream>)>
+where
+    R: Into<Option<Duration>>,
+    W: Into<Option<Duration>>,
+{
+    let read_timeout = read_timeout.into();
+    let write_timeout = write_timeout.into();
+    let (left, right) = UnixStream::pair()?;
+    Ok((
+        TimeoutStream::new(left, read_timeout, write_timeout),
+        TimeoutStream::new(right, read_timeout, write_timeout),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pipe_ends_time_out() {
+        let (mut rdr, mut wtr) =
+            timeout_pipe(Duration::from_millis(20), Duration::from_millis(20)).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+
+        wtr.write_all(b"ping").unwrap();
+        rdr.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+
+        let chunk = [0u8; 4096];
+        let err = loop {
+            if let Err(e) = wtr.write(&chunk) {
+                break e;
+            }
+        };
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn socketpair_is_duplex() {
+        let (mut left, mut right) = timeout_socketpair(Duration::from_millis(20), None).unwrap();
+        left.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        right.read_exact(&mut buf).unwrap();
+        right.write_all(b"pong").unwrap();
+        left.read_exact(&mut buf).unwrap();
+        assert_eq!(b"pong", &buf);
+        assert_eq!(ErrorKind::TimedOut, left.read(&mut buf).unwrap_err().kind());
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io::{Read, Result, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::time::{Duration, Instant};
+
+/// How far a transfer through a `Progress` has come, passed to its callback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    /// The total number of bytes transferred so far.
+    pub bytes: u64,
+    /// The time since the `Progress` was created.
+    pub elapsed: Duration,
+    /// The rate since the previous update, in bytes per second.
+    pub bytes_per_second: f64,
+}
+
+/// The `Progress` struct reports how far a long transfer has come, for progress bars
+/// and the like.
+///
+/// A `Progress` sits in front of a reader or a writer, usually a `TimeoutReader` or
+/// `TimeoutWriter` being copied from or to with `io::copy`, and counts the bytes
+/// passing through it. After each read or write, it calls its callback with a
+/// `ProgressUpdate` if enough bytes have gone through since the last report, or if
+/// enough time has passed. With neither limit set, it reports after every read or
+/// write. A read that reaches end of file always reports, so the last update has the
+/// final count.
+///
+/// Reports only ever follow a read or write; there is no timer, so nothing is
+/// reported while one is waiting, and a stall shows up as the wrapper's timeout
+/// instead.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{Progress, TimeoutReader};
+/// use std::fs::File;
+/// use std::io;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+/// let mut rdr = Progress::new(rdr, |update| {
+///     eprint!("\r{} bytes, {:.0} bytes/s", update.bytes, update.bytes_per_second);
+/// })
+/// .every_bytes(1 << 20)
+/// .report_after(Duration::from_millis(250));
+///
+/// io::copy(&mut rdr, &mut File::create("download.bin")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Progress<H> {
+    inner: H,
+    callback: Box<dyn FnMut(&ProgressUpdate) + Send>,
+    every_bytes: Option<u64>,
+    report_after: Option<Duration>,
+    start: Instant,
+    bytes: u64,
+    reported_bytes: u64,
+    reported_at: Instant,
+}
+
+impl<H> Progress<H> {
+    /// Create a new `Progress` in front of `inner`, calling `callback` with each
+    /// update.
+    pub fn new<F>(inner: H, callback: F) -> Progress<H>
+    where
+        F: FnMut(&ProgressUpdate) + Send + 'static,
+    {
+        let now = Instant::now();
+        Progress {
+            inner,
+            callback: Box::new(callback),
+            every_bytes: None,
+            report_after: None,
+            start: now,
+            bytes: 0,
+            reported_bytes: 0,
+            reported_at: now,
+        }
+    }
+
+    /// Report once at least `bytes` bytes have gone through since the last update.
+    pub fn every_bytes(mut self, bytes: u64) -> Progress<H> {
+        self.every_bytes = Some(bytes);
+        self
+    }
+
+    /// Report on the first read or write once at least `interval` has passed since the
+    /// last update.
+    pub fn report_after(mut self, interval: Duration) -> Progress<H> {
+        self.report_after = Some(interval);
+        self
+    }
+
+    /// Returns the total number of bytes transferred so far.
+    pub fn transferred(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Gets a reference to the underlying handle.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying handle.
+    ///
+    /// Bytes transferred directly on the handle are not counted.
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    /// Unwraps this `Progress`, returning the underlying handle.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Count `n` more bytes, and report if an interval has been reached or `force`
+    /// is set.
+    fn advance(&mut self, n: usize, force: bool) {
+        self.bytes += n as u64;
+        let now = Instant::now();
+        let since = now.duration_since(self.reported_at);
+        let moved = self.bytes - self.reported_bytes;
+        let due = match (self.every_bytes, self.report_after) {
+            (None, None) => true,
+            (bytes, interval) => {
+                bytes.is_some_and(|bytes| moved >= bytes)
+                    || interval.is_some_and(|interval| since >= interval)
+            }
+        };
+        if !(due || force) {
+            return;
+        }
+
+        let secs = since.as_secs_f64();
+        let update = ProgressUpdate {
+            bytes: self.bytes,
+            elapsed: now.duration_since(self.start),
+            bytes_per_second: if secs > 0.0 { moved as f64 / secs } else { 0.0 },
+        };
+        self.reported_bytes = self.bytes;
+        self.reported_at = now;
+        (self.callback)(&update);
+    }
+}
+
+impl<H> Read for Progress<H>
+where
+    H: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.advance(n, n == 0 && !buf.is_empty());
+        Ok(n)
+    }
+}
+
+impl<H> Write for Progress<H>
+where
+    H: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.advance(n, false);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<H> AsFd for Progress<H>
+where
+    H: AsFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl<H> fmt::Debug for Progress<H>
+where
+    H: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("inner", &self.inner)
+            .field("bytes", &self.bytes)
+            .field("every_bytes", &self.every_bytes)
+            .field("report_after", &self.report_after)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn reports_every_n_bytes_and_at_eof() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let mut rdr = {
+            let updates = Arc::clone(&updates);
+            Progress::new(Cursor::new(vec![0u8; 10_000]), move |update| {
+                updates.lock().unwrap().push(update.bytes);
+            })
+            .every_bytes(4096)
+        };
+
+        let mut buf = [0u8; 1024];
+        let mut sink = Vec::new();
+        loop {
+            let n = rdr.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            sink.write_all(&buf[..n]).unwrap();
+        }
+        assert_eq!(vec![4096, 8192, 10_000], *updates.lock().unwrap());
+        assert_eq!(10_000, rdr.transferred());
+
+        let mut wtr = Progress::new(io::sink(), |_| {});
+        io::copy(&mut Cursor::new(sink), &mut wtr).unwrap();
+        assert_eq!(10_000, wtr.transferred());
+    }
+
+    #[test]
+    fn reports_after_interval_on_next_read() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let mut rdr = {
+            let updates = Arc::clone(&updates);
+            Progress::new(Cursor::new(vec![0u8; 100]), move |update| {
+                updates.lock().unwrap().push(update.bytes);
+            })
+            .report_after(Duration::from_millis(20))
+        };
+
+        let mut buf = [0u8; 10];
+        rdr.read_exact(&mut buf).unwrap();
+        thread::sleep(Duration::from_millis(40));
+        assert!(updates.lock().unwrap().is_empty());
+        rdr.read_exact(&mut buf).unwrap();
+        rdr.read_exact(&mut buf).unwrap();
+        assert_eq!(vec![20], *updates.lock().unwrap());
+    }
+}
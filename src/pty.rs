@@ -0,0 +1,138 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::pty;
+use std::fs::File;
+use std::io::Result;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use super::{TimeoutReader, TimeoutWriter};
+
+/// The `TimeoutPty` struct holds a freshly allocated pseudo-terminal pair whose
+/// master side is wrapped with timeouts.
+///
+/// Many interactive programs detect that their standard out is a pipe and switch to
+/// block buffering, so output only shows up once a buffer fills or the program exits,
+/// which defeats any read timeout. Attaching such a program to the slave side of a
+/// pseudo-terminal makes it believe it is talking to a terminal, while the master
+/// side is driven through a `TimeoutReader` and a `TimeoutWriter`.
+///
+/// Note that once every process holding the slave side has exited, reads from the
+/// master side fail with an `EIO` error rather than returning end of file.
+#[derive(Debug)]
+pub struct TimeoutPty {
+    reader: TimeoutReader<File>,
+    writer: TimeoutWriter<File>,
+    slave: File,
+}
+
+impl TimeoutPty {
+    /// Allocate a new pseudo-terminal pair, wrapping the master side with the given
+    /// read and write timeouts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutPty;
+    /// use std::io::{BufRead, BufReader};
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut pty = TimeoutPty::open(Duration::new(5, 0), Duration::new(5, 0))?;
+    /// let mut child = pty.spawn(&mut Command::new("python3"))?;
+    ///
+    /// let mut line = String::new();
+    /// BufReader::new(pty.reader()).read_line(&mut line)?;
+    /// child.kill()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open<R, W>(read_timeout: R, write_timeout: W) -> Result<TimeoutPty>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        let pair = pty::openpty(None, None)?;
+        let master = File::from(pair.master);
+        let writer = master.try_clone()?;
+
+        Ok(TimeoutPty {
+            reader: TimeoutReader::new(master, read_timeout),
+            writer: TimeoutWriter::new(writer, write_timeout),
+            slave: File::from(pair.slave),
+        })
+    }
+
+    /// Returns the timed reader for the master side of the pseudo-terminal.
+    pub fn reader(&mut self) -> &mut TimeoutReader<File> {
+        &mut self.reader
+    }
+
+    /// Returns the timed writer for the master side of the pseudo-terminal.
+    pub fn writer(&mut self) -> &mut TimeoutWriter<File> {
+        &mut self.writer
+    }
+
+    /// Returns the slave side of the pseudo-terminal.
+    pub fn slave(&self) -> &File {
+        &self.slave
+    }
+
+    /// Spawn `cmd` with its standard in, standard out, and standard error all attached
+    /// to the slave side of the pseudo-terminal.
+    ///
+    /// The child does not become a session leader, so the pseudo-terminal is not its
+    /// controlling terminal; it is, however, recognized by `isatty`, which is what
+    /// governs the buffering behavior of most programs.
+    pub fn spawn(&self, cmd: &mut Command) -> Result<Child> {
+        cmd.stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?))
+            .spawn()
+    }
+
+    /// Consumes the `TimeoutPty`, returning the master reader, master writer, and
+    /// slave side.
+    ///
+    /// Dropping the slave side after spawning a child allows the master reader to
+    /// observe the child exiting.
+    pub fn into_parts(self) -> (TimeoutReader<File>, TimeoutWriter<File>, File) {
+        (self.reader, self.writer, self.slave)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn slave_output_reaches_master() {
+        let pty = TimeoutPty::open(Duration::new(5, 0), Duration::new(5, 0)).unwrap();
+        let (mut reader, _writer, mut slave) = pty.into_parts();
+
+        slave.write_all(b"hello\n").unwrap();
+
+        let mut buf = [0u8; 16];
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(b"hello\r\n", &buf[..n]);
+    }
+
+    #[test]
+    fn quiet_slave_times_out() {
+        let mut pty = TimeoutPty::open(Duration::from_millis(100), None).unwrap();
+
+        let mut buf = [0u8; 16];
+        let err = pty.reader().read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A shared `epoll` instance that services the waits of many wrappers.
+
+use nix::errno::Errno;
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+use nix::unistd;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::utils;
+
+/// The event data that marks the pipe used to stop the reactor thread.
+const WAKE: u64 = u64::MAX;
+
+/// The `Reactor` struct owns an `epoll` instance and a thread that waits on it for
+/// every wrapper registered with it.
+///
+/// Wrappers keep their blocking `Read` and `Write` implementations. Instead of each
+/// calling `poll` on its own handle, a wrapper built with `TimeoutBuilder::reactor`
+/// arms its handle in the shared `epoll` set and sleeps until the reactor thread
+/// reports it ready or its timeout elapses. Handles are added the first time they are
+/// waited on, and `epoll` forgets them when they are closed.
+///
+/// Handles that `epoll` cannot wait on, such as regular files, and wrappers whose
+/// reactor has been dropped, go back to waiting with `poll`.
+///
+/// A wait through the reactor is not cheaper than `poll`. It re-arms the handle with
+/// `epoll_ctl` and then sleeps until the reactor thread wakes it, where `poll` takes
+/// a single system call. In a ping-pong benchmark over Unix socket pairs on Linux,
+/// round trips through the reactor took between 1.7 and 3 times as long as with
+/// `poll`, for 1 to 64 connections.
+///
+/// This is only available on Linux and Android, with the `reactor` feature.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutBuilder;
+/// use timeout_readwrite::reactor::Reactor;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let reactor = Reactor::new()?;
+/// let builder = TimeoutBuilder::new()
+///     .read_timeout(Duration::new(5, 0))
+///     .reactor(&reactor);
+///
+/// let first = builder.stream(TcpStream::connect("127.0.0.1:8080")?);
+/// let second = builder.stream(TcpStream::connect("127.0.0.1:8081")?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Reactor {
+    shared: Arc<Shared>,
+    wake: File,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reactor {
+    /// Create a new `Reactor`, starting its thread.
+    pub fn new() -> Result<Reactor> {
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC).map_err(Error::from)?;
+        let (wake_rdr, wake_wtr) = unistd::pipe().map_err(Error::from)?;
+        epoll
+            .add(&wake_rdr, EpollEvent::new(EpollFlags::EPOLLIN, WAKE))
+            .map_err(Error::from)?;
+
+        let shared = Arc::new(Shared {
+            epoll,
+            slots: Mutex::new(HashMap::new()),
+            stopped: AtomicBool::new(false),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("timeout-readwrite-reactor".into())
+                .spawn(move || {
+                    let _wake_rdr = wake_rdr;
+                    shared.run()
+                })?
+        };
+
+        Ok(Reactor {
+            shared,
+            wake: File::from(wake_wtr),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the state that wrappers registered with this reactor hold on to.
+    pub(crate) fn shared(&self) -> Arc<Shared> {
+        Arc::clone(&self.shared)
+    }
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reactor")
+            .field("epoll", &self.shared.epoll.0.as_raw_fd())
+            .field("handles", &self.shared.slots.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        let _ = self.wake.write_all(&[0]);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // Wake anyone still waiting, so they go back to `poll`.
+        for slot in self.shared.slots.lock().unwrap().values() {
+            let _guard = slot.state.lock().unwrap();
+            slot.changed.notify_all();
+        }
+    }
+}
+
+/// The part of a `Reactor` shared with its thread and its wrappers.
+pub struct Shared {
+    epoll: Epoll,
+    slots: Mutex<HashMap<RawFd, Arc<Slot>>>,
+    stopped: AtomicBool,
+}
+
+/// What the reactor knows about one handle.
+#[derive(Default)]
+struct Slot {
+    state: Mutex<SlotState>,
+    changed: Condvar,
+}
+
+struct SlotState {
+    /// How many waiters want each kind of event, in the order of `INTERESTS`.
+    waiting: [usize; 3],
+    /// How many events the reactor has delivered for the handle.
+    delivered: u64,
+    /// The events that were last delivered.
+    last: EpollFlags,
+}
+
+impl Default for SlotState {
+    fn default() -> SlotState {
+        SlotState {
+            waiting: [0; 3],
+            delivered: 0,
+            last: EpollFlags::empty(),
+        }
+    }
+}
+
+const INTERESTS: [(PollFlags, EpollFlags); 3] = [
+    (PollFlags::POLLIN, EpollFlags::EPOLLIN),
+    (PollFlags::POLLOUT, EpollFlags::EPOLLOUT),
+    (PollFlags::POLLPRI, EpollFlags::EPOLLPRI),
+];
+
+fn epoll_events(events: PollFlags) -> EpollFlags {
+    INTERESTS
+        .iter()
+        .filter(|&&(poll, _)| events.intersects(poll))
+        .fold(EpollFlags::empty(), |acc, &(_, epoll)| acc | epoll)
+}
+
+impl SlotState {
+    fn add_interest(&mut self, events: PollFlags, count: isize) {
+        for (waiting, &(poll, _)) in self.waiting.iter_mut().zip(INTERESTS.iter()) {
+            if events.intersects(poll) {
+                *waiting = (*waiting as isize + count) as usize;
+            }
+        }
+    }
+
+    fn interest(&self) -> EpollFlags {
+        self.waiting
+            .iter()
+            .zip(INTERESTS.iter())
+            .filter(|&(&waiting, _)| waiting > 0)
+            .fold(EpollFlags::empty(), |acc, (_, &(_, epoll))| acc | epoll)
+    }
+}
+
+impl Shared {
+    /// Deliver events to the waiters until the reactor is dropped.
+    fn run(&self) {
+        let mut events = [EpollEvent::empty(); 64];
+        loop {
+            let n = match self.epoll.wait(&mut events, EpollTimeout::NONE) {
+                Ok(n) => n,
+                Err(Errno::EINTR) => continue,
+                Err(_) => return,
+            };
+            for event in &events[..n] {
+                if event.data() == WAKE {
+                    if self.stopped.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    continue;
+                }
+                let slot = match self.slots.lock().unwrap().get(&(event.data() as RawFd)) {
+                    Some(slot) => Arc::clone(slot),
+                    None => continue,
+                };
+                let mut state = slot.state.lock().unwrap();
+                state.delivered += 1;
+                state.last = event.events();
+                slot.changed.notify_all();
+            }
+        }
+    }
+
+    fn slot(&self, fd: RawFd) -> Arc<Slot> {
+        Arc::clone(self.slots.lock().unwrap().entry(fd).or_default())
+    }
+
+    /// Wait until `fd` receives one of `events`, up to `timeout`, through the reactor.
+    pub fn wait_until_ready(
+        &self,
+        timeout: Option<c_int>,
+        fd: &impl AsFd,
+        events: PollFlags,
+    ) -> Result<()> {
+        // A zero timeout is only a check, which `poll` does on its own.
+        if self.stopped.load(Ordering::SeqCst) || timeout == Some(0) {
+            return utils::wait_until_ready(timeout, fd, events);
+        }
+        let timeout = match timeout {
+            Some(timeout) => utils::ms_to_duration(timeout),
+            None => return Ok(()),
+        };
+        let deadline = Instant::now() + timeout;
+        let wanted = epoll_events(events) | EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP;
+
+        let slot = self.slot(fd.as_fd().as_raw_fd());
+        let mut state = slot.state.lock().unwrap();
+        state.add_interest(events, 1);
+        let rslt = loop {
+            if let Err(e) = self.arm(&state, fd) {
+                // Not something epoll can wait on, so wait the usual way.
+                state.add_interest(events, -1);
+                drop(state);
+                self.release(fd, &slot);
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                return match e {
+                    Errno::EPERM => {
+                        utils::wait_until_ready(Some(utils::duration_to_ms(remaining)), fd, events)
+                    }
+                    e => Err(Error::from(e)),
+                };
+            }
+
+            let seen = state.delivered;
+            while state.delivered == seen && !self.stopped.load(Ordering::SeqCst) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::new(0, 0) {
+                    break;
+                }
+                state = slot.changed.wait_timeout(state, remaining).unwrap().0;
+            }
+
+            if state.delivered == seen + 1 && state.last.intersects(wanted) {
+                break Ok(());
+            }
+            if state.delivered != seen || self.stopped.load(Ordering::SeqCst) {
+                // Whatever woke us may not have been meant for us, so check directly.
+                match utils::wait_until_ready(Some(0), fd, events) {
+                    Err(ref e) if e.kind() == ErrorKind::TimedOut => {}
+                    rslt => break rslt,
+                }
+                if self.stopped.load(Ordering::SeqCst) {
+                    state.add_interest(events, -1);
+                    drop(state);
+                    self.release(fd, &slot);
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    return utils::wait_until_ready(
+                        Some(utils::duration_to_ms(remaining)),
+                        fd,
+                        events,
+                    );
+                }
+            }
+            if Instant::now() >= deadline {
+                break Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for fd to be ready",
+                ));
+            }
+        };
+        state.add_interest(events, -1);
+        drop(state);
+        self.release(fd, &slot);
+        rslt
+    }
+
+    /// Arm `fd` for every event its waiters want.
+    ///
+    /// Registrations are one-shot because the waiters, not the reactor thread, do the
+    /// I/O. A level-triggered registration would keep reporting a ready handle until
+    /// its waiter got around to reading from it, and an edge-triggered one would miss
+    /// readiness that arrived before the waiter armed it.
+    ///
+    /// This is done on every wait, even if an earlier one armed the handle already.
+    /// A one-shot registration may have fired since, and the descriptor number may
+    /// now belong to a different file than the one that was registered, which `epoll`
+    /// tells apart by answering `ENOENT`.
+    fn arm(&self, state: &SlotState, fd: &impl AsFd) -> std::result::Result<(), Errno> {
+        let fd = fd.as_fd();
+        let interest = state.interest() | EpollFlags::EPOLLONESHOT;
+        let mut event = EpollEvent::new(interest, fd.as_raw_fd() as u64);
+        match self.epoll.modify(fd, &mut event) {
+            Err(Errno::ENOENT) => self.epoll.add(fd, event),
+            rslt => rslt,
+        }
+    }
+
+    /// Forget the slot of `fd` once its last waiter has left.
+    ///
+    /// Waiters only get hold of a slot through the map, so if nobody but the map and
+    /// this waiter holds it, nobody else is waiting on it. The registration itself
+    /// stays in the `epoll` set, so the next wait on the handle only has to re-arm it,
+    /// and `epoll` drops it when the handle is closed.
+    fn release(&self, fd: &impl AsFd, slot: &Arc<Slot>) {
+        let mut slots = self.slots.lock().unwrap();
+        let raw = fd.as_fd().as_raw_fd();
+        let idle = slots
+            .get(&raw)
+            .is_some_and(|current| Arc::ptr_eq(current, slot) && Arc::strong_count(slot) == 2);
+        if idle {
+            slots.remove(&raw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::super::TimeoutBuilder;
+    use super::*;
+
+    #[test]
+    fn reactor_services_waits() {
+        let reactor = Reactor::new().unwrap();
+        let builder = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(100))
+            .reactor(&reactor);
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = builder.reader(left);
+
+        let mut buf = [0u8; 16];
+        let start = Instant::now();
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            right.write_all(b"hello").unwrap();
+            right
+        });
+        assert_eq!(5, rdr.read(&mut buf).unwrap());
+        let _right = writer.join().unwrap();
+
+        drop(reactor);
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+    }
+
+    #[test]
+    fn reused_descriptor_is_registered_again() {
+        use std::os::fd::AsRawFd;
+
+        let reactor = Reactor::new().unwrap();
+        let builder = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(50))
+            .reactor(&reactor);
+        let (left, _right) = UnixStream::pair().unwrap();
+        let mut rdr = builder.reader(left);
+
+        let mut buf = [0u8; 16];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        assert_eq!(0, reactor.shared.slots.lock().unwrap().len());
+
+        // Put another socket behind the same descriptor number, closing the first one,
+        // which drops it from the epoll set.
+        let (other, mut other_peer) = UnixStream::pair().unwrap();
+        nix::unistd::dup2(other.as_raw_fd(), rdr.as_raw_fd()).unwrap();
+        other_peer.write_all(b"ping").unwrap();
+
+        let start = Instant::now();
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -8,14 +8,30 @@
 
 use nix::libc::c_int;
 use nix::poll::PollFlags;
+use std::fmt;
+use std::fs::File;
+#[cfg(feature = "nightly")]
+use std::io::BorrowedCursor;
+use std::io::BufRead;
 use std::io::Read;
 use std::io::Result;
-use std::io::Seek;
-use std::io::SeekFrom;
-use std::os::fd::AsFd;
-use std::time::Duration;
+use std::io::Write;
+use std::io::{Error, ErrorKind};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::budget;
+use super::config;
+use super::handle::BufferedHandle;
+use super::lines::TimedLines;
+use super::observe::Direction;
+use super::partial;
+use super::reply::{Reply, ReplyReader};
+use super::timeout::{Reading, Timeout};
+use super::tty::SavedTermios;
 use super::utils;
+use super::wait::Waiter;
 
 /// The `TimeoutReader` struct adds read timeouts to any reader.
 ///
@@ -27,51 +43,243 @@ use super::utils;
 /// an `io::ErrorKind::TimedOut` variant as the value of `io::Error`. All other
 /// error values that would normally be produced by the underlying implementation
 /// of the `Read` trait could also be produced by the `TimeoutReader`.
-pub struct TimeoutReader<H>
+///
+/// This is the generic `Timeout` wrapper for the `Reading` direction, which is where
+/// the methods it shares with `TimeoutWriter` and `TimeoutStream` are documented.
+pub type TimeoutReader<H> = Timeout<H, Reading>;
+
+impl<H> Read for TimeoutReader<H>
 where
     H: Read + AsFd,
 {
-    timeout: Option<c_int>,
-    handle: H,
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_within(
+            &self.waiter,
+            self.read_timeout,
+            self.termios.as_ref().map(|t| t.timeout()),
+            self.has_buffered_data(),
+            &mut self.handle,
+            buf,
+        )
+    }
+
+    #[cfg(feature = "nightly")]
+    fn read_buf(&mut self, cursor: BorrowedCursor<'_>) -> Result<()> {
+        read_buf_within(
+            &self.waiter,
+            self.read_timeout,
+            self.termios.as_ref().map(|t| t.timeout()),
+            self.has_buffered_data(),
+            &mut self.handle,
+            cursor,
+        )
+    }
 }
 
-impl<H> Read for TimeoutReader<H>
+/// Shared references can be read from whenever the handle allows it, so a reader
+/// can be used from several places at once just like a `&TcpStream`.
+impl<H> Read for &TimeoutReader<H>
 where
     H: Read + AsFd,
+    for<'b> &'b H: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        utils::wait_until_ready(self.timeout, &self.handle, PollFlags::POLLIN)?;
-        self.handle.read(buf)
+        let mut handle = &self.handle;
+        read_within(
+            &self.waiter,
+            self.read_timeout,
+            self.termios.as_ref().map(|t| t.timeout()),
+            self.has_buffered_data(),
+            &mut handle,
+            buf,
+        )
     }
+
+    #[cfg(feature = "nightly")]
+    fn read_buf(&mut self, cursor: BorrowedCursor<'_>) -> Result<()> {
+        let mut handle = &self.handle;
+        read_buf_within(
+            &self.waiter,
+            self.read_timeout,
+            self.termios.as_ref().map(|t| t.timeout()),
+            self.has_buffered_data(),
+            &mut handle,
+            cursor,
+        )
+    }
+}
+
+fn read_within<R>(
+    waiter: &Waiter,
+    timeout: Option<c_int>,
+    termios: Option<c_int>,
+    buffered: bool,
+    handle: &mut R,
+    buf: &mut [u8],
+) -> Result<usize>
+where
+    R: Read + AsFd,
+{
+    let len = buf.len();
+    read_within_with(waiter, timeout, termios, buffered, handle, len, |h| {
+        h.read(buf)
+    })
 }
 
-impl<H> Seek for TimeoutReader<H>
+/// Wait for `handle` and then read up to `len` bytes from it with `read`, skipping
+/// the wait if the handle has `buffered` data.
+fn read_within_with<R, F>(
+    waiter: &Waiter,
+    timeout: Option<c_int>,
+    termios: Option<c_int>,
+    buffered: bool,
+    handle: &mut R,
+    len: usize,
+    read: F,
+) -> Result<usize>
 where
-    H: Read + AsFd + Seek,
+    R: AsFd,
+    F: FnOnce(&mut R) -> Result<usize>,
 {
-    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.handle.seek(pos)
+    if buffered {
+        let start = waiter.start();
+        let rslt = read(handle);
+        return waiter.record(Direction::Read, start, rslt);
     }
+    let vtime = match termios {
+        Some(vtime) => vtime,
+        None => return waiter.perform(Direction::Read, timeout, handle, read),
+    };
+
+    let start = waiter.start();
+    let rslt = read_terminal(waiter, vtime, handle, len, read);
+    waiter.record(Direction::Read, start, rslt)
 }
 
-impl<H> AsFd for TimeoutReader<H>
+/// Handles that can also be written to, such as sockets, stay writable through the
+/// reader. Writes wait for the handle up to the write timeout, which is unset unless
+/// `set_write_timeout` is called.
+impl<H> Write for TimeoutReader<H>
 where
-    H: Read + AsFd,
+    H: Read + Write + AsFd,
 {
-    fn as_fd(&self) -> std::os::unix::prelude::BorrowedFd<'_> {
-        self.handle.as_fd()
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.waiter.perform(
+            Direction::Write,
+            self.write_timeout,
+            &mut self.handle,
+            |h| h.write(buf),
+        )
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.waiter
+            .perform_flush(self.write_timeout, &mut self.handle, |h| h.flush())
     }
 }
 
-impl<H> Clone for TimeoutReader<H>
+/// Buffered handles are read through their own buffer, and the wrapper only waits
+/// for the descriptor once that buffer has run dry.
+impl<H> BufRead for TimeoutReader<H>
 where
-    H: Read + AsFd + Clone,
+    H: BufRead + AsFd + BufferedHandle,
 {
-    fn clone(&self) -> TimeoutReader<H> {
-        TimeoutReader {
-            handle: self.handle.clone(),
-            ..*self
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if !self.handle.has_buffered_data() {
+            // The buffer is empty, so anything `fill_buf` returns was freshly read.
+            read_within_with(
+                &self.waiter,
+                self.read_timeout,
+                self.termios.as_ref().map(|t| t.timeout()),
+                self.has_buffered_data(),
+                &mut self.handle,
+                1,
+                |h| h.fill_buf().map(|buf| buf.len()),
+            )?;
         }
+        self.handle.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.handle.consume(amt)
+    }
+}
+
+/// Like `read_within`, but fills a possibly uninitialized buffer.
+#[cfg(feature = "nightly")]
+fn read_buf_within<R>(
+    waiter: &Waiter,
+    timeout: Option<c_int>,
+    termios: Option<c_int>,
+    buffered: bool,
+    handle: &mut R,
+    mut cursor: BorrowedCursor<'_>,
+) -> Result<()>
+where
+    R: Read + AsFd,
+{
+    if termios.is_some() {
+        // Terminal timeouts are detected from the number of bytes read, which only
+        // `read` reports. Terminal input is small, so the extra copy hardly matters.
+        let mut buf = vec![0u8; cursor.capacity()];
+        let n = read_within(waiter, timeout, termios, buffered, handle, &mut buf)?;
+        cursor.append(&buf[..n]);
+        return Ok(());
+    }
+
+    let start = waiter.start();
+    let before = cursor.written();
+    let ready = if buffered {
+        Ok(())
+    } else {
+        waiter.wait_until_ready(timeout, handle, PollFlags::POLLIN)
+    };
+    let rslt = ready
+        .and_then(|_| handle.read_buf(cursor.reborrow()))
+        .map(|()| cursor.written() - before);
+    waiter.record(Direction::Read, start, rslt).map(|_| ())
+}
+
+/// Read from a terminal that enforces the timeout itself through `VTIME`, set from
+/// `vtime` milliseconds.
+fn read_terminal<R, F>(
+    waiter: &Waiter,
+    vtime: c_int,
+    handle: &mut R,
+    len: usize,
+    read: F,
+) -> Result<usize>
+where
+    R: AsFd,
+    F: FnOnce(&mut R) -> Result<usize>,
+{
+    waiter.check_limits(handle)?;
+
+    // With VMIN at zero, the terminal driver signals an expired VTIME by
+    // returning no data at all. End of file returns nothing too, but without
+    // waiting out the timeout first.
+    let start = Instant::now();
+    match read(handle)? {
+        0 if len > 0 && start.elapsed() >= utils::ms_to_duration(vtime) => Err(Error::new(
+            ErrorKind::TimedOut,
+            "timed out waiting for terminal input",
+        )),
+        n => Ok(n),
+    }
+}
+
+/// Shows the descriptor rather than the handle, so that every `TimeoutReader` can be
+/// debugged, whatever it wraps.
+impl<H> fmt::Debug for TimeoutReader<H>
+where
+    H: Read + AsFd,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutReader")
+            .field("fd", &self.as_raw_fd())
+            .field("timeout", &self.timeout())
+            .field("termios", &self.termios.is_some())
+            .finish()
     }
 }
 
@@ -112,12 +320,377 @@ where
     /// ```
     pub fn new<T: Into<Option<Duration>>>(handle: H, timeout: T) -> TimeoutReader<H> {
         TimeoutReader {
-            timeout: timeout.into().map(utils::duration_to_ms),
-            handle,
+            read_timeout: utils::initial_timeout(timeout.into()),
+            ..Timeout::bare(handle)
+        }
+    }
+
+    /// Create a new `TimeoutReader` that, when the handle is a terminal device, lets
+    /// the terminal driver enforce the timeout through `VMIN` and `VTIME`.
+    ///
+    /// For serial ports in particular, having the driver time out the `read` itself is
+    /// both more accurate and cheaper than polling before every read. The device is
+    /// switched to non-canonical mode with `VMIN` and `VTIME` set from the timeout for
+    /// as long as the `TimeoutReader` (and every clone of it) lives; the original
+    /// settings are restored once the last one is dropped or unwrapped.
+    ///
+    /// `VTIME` counts in tenths of a second up to 25.5 seconds, so the timeout is
+    /// rounded up to the next tenth of a second. If the handle is not a terminal, there
+    /// is no timeout, or the timeout is longer than `VTIME` can express, the terminal
+    /// settings are left alone and this behaves the same as `TimeoutReader::new`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::fs::File;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let port = File::open("/dev/ttyUSB0")?;
+    /// let mut rdr = TimeoutReader::new_termios(port, Duration::from_millis(500))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_termios<T: Into<Option<Duration>>>(
+        handle: H,
+        timeout: T,
+    ) -> Result<TimeoutReader<H>> {
+        let mut rdr = TimeoutReader::new(handle, timeout);
+        rdr.termios = SavedTermios::apply(&rdr.handle, rdr.read_timeout)?.map(Arc::new);
+        Ok(rdr)
+    }
+
+    /// Create a new `TimeoutReader`, falling back to the default timeout from the
+    /// `TIMEOUT_READWRITE_DEFAULT_MS` environment variable when `timeout` is `None`.
+    ///
+    /// This lets operators tune timeouts without a rebuild. If the variable is not
+    /// set, or does not hold a whole number of milliseconds, the `TimeoutReader` has no
+    /// timeout, just as with `TimeoutReader::new`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::fs::File;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut f = File::open("file.txt")?;
+    /// let mut rdr = TimeoutReader::new_with_default(f, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_default<T: Into<Option<Duration>>>(handle: H, timeout: T) -> TimeoutReader<H> {
+        let timeout = timeout.into().or_else(config::env_default_timeout);
+        TimeoutReader::new(handle, timeout)
+    }
+
+    /// Returns the timeout of this reader.
+    ///
+    /// If the timeout is `None`, then `read` calls will block indefinitely. The
+    /// timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.read_timeout.map(utils::ms_to_duration)
+    }
+
+    /// Sets the timeout of this reader.
+    ///
+    /// If the value specified is `None`, then `read` calls will block indefinitely. An
+    /// `Err` is returned if the zero `Duration` is passed to this method. A reader
+    /// created with `new_termios` keeps the `VTIME` it started with, and its reads go
+    /// on timing out after that long, so change the timeout of such a reader by
+    /// creating a new one.
+    pub fn set_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.read_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Sets the timeout for writing through this reader, for handles that can also be
+    /// written to.
+    ///
+    /// If the value specified is `None`, which is the default, `write` calls will
+    /// block indefinitely. An `Err` is returned if the zero `Duration` is passed to
+    /// this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::io::{Read, Write};
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+    /// rdr.set_write_timeout(Some(Duration::new(1, 0)))?;
+    ///
+    /// let mut request = [0u8; 4];
+    /// rdr.read_exact(&mut request)?;
+    /// rdr.write_all(b"done")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_write_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.write_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Returns the timeout for writing through this reader.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout.map(utils::ms_to_duration)
+    }
+
+    /// Returns whether the handle reports buffered data, if reads check for it.
+    fn has_buffered_data(&self) -> bool {
+        self.buffered.is_some_and(|buffered| buffered(&self.handle))
+    }
+
+    /// Iterate over the lines of this reader, giving each line at most `per_line` to
+    /// arrive.
+    ///
+    /// See `TimedLines` for what happens when a line runs out of time.
+    pub fn timed_lines(self, per_line: Duration) -> TimedLines<H> {
+        TimedLines::new(self, per_line)
+    }
+
+    /// Discard whatever data arrives until none has arrived for `quiet`, returning how
+    /// many bytes were discarded.
+    ///
+    /// This clears out stale bytes before starting a transaction, such as leftover
+    /// replies on a serial line or modem. The reader's own timeout does not apply, and
+    /// going quiet is not counted as a timeout. End of file also counts as quiet. If
+    /// the other end never stops sending, this never returns.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::fs::File;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let port = File::open("/dev/ttyUSB0")?;
+    /// let mut rdr = TimeoutReader::new(port, Duration::new(1, 0));
+    /// let stale = rdr.drain_input(Duration::from_millis(50))?;
+    /// println!("discarded {} stale bytes", stale);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn drain_input(&mut self, quiet: Duration) -> Result<usize> {
+        self.read_until_quiet(quiet, None, |_| {})
+    }
+
+    /// Read onto the end of `buf` until no data has arrived for `quiet_period`,
+    /// returning how many bytes were read.
+    ///
+    /// Some devices mark the end of a response only by going quiet. This collects the
+    /// response and returns once the device has been silent for `quiet_period`, or has
+    /// reached end of file. If data keeps trickling in until `max_wait` has elapsed, an
+    /// `io::ErrorKind::TimedOut` error is returned instead, and the bytes read so far
+    /// are left in `buf`. As with `drain_input`, the reader's own timeout does not
+    /// apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::fs::OpenOptions;
+    /// use std::io::Write;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut port = OpenOptions::new().read(true).write(true).open("/dev/ttyUSB0")?;
+    /// port.write_all(b"STATUS\r")?;
+    ///
+    /// let mut rdr = TimeoutReader::new(port, Duration::new(1, 0));
+    /// let mut response = Vec::new();
+    /// rdr.wait_for_silence(&mut response, Duration::from_millis(100), Duration::new(5, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_for_silence(
+        &mut self,
+        buf: &mut Vec<u8>,
+        quiet_period: Duration,
+        max_wait: Duration,
+    ) -> Result<usize> {
+        self.read_until_quiet(quiet_period, Some(max_wait), |data| {
+            buf.extend_from_slice(data)
+        })
+    }
+
+    /// Read until no data has arrived for `quiet`, passing each chunk to `sink`, and
+    /// return how many bytes were read, failing if that takes longer than `max_wait`.
+    fn read_until_quiet<F>(
+        &mut self,
+        quiet: Duration,
+        max_wait: Option<Duration>,
+        mut sink: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&[u8]),
+    {
+        let quiet = utils::timeout_ms(quiet);
+        let deadline = max_wait
+            .map(|max_wait| Instant::now() + utils::ms_to_duration(utils::timeout_ms(max_wait)));
+        let mut buf = [0u8; 1024];
+        let mut total = 0;
+        loop {
+            let left = deadline.map(|deadline| {
+                utils::duration_to_ms(deadline.saturating_duration_since(Instant::now()))
+            });
+            let wait = left.map_or(quiet, |left| left.min(quiet));
+            match utils::wait_until_ready(Some(wait), &self.handle, PollFlags::POLLIN) {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut && wait == quiet => return Ok(total),
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "data kept arriving for longer than the maximum wait",
+                    ))
+                }
+                rslt => rslt?,
+            }
+            let start = self.waiter.start();
+            let rslt = self.handle.read(&mut buf);
+            match self.waiter.record(Direction::Read, start, rslt)? {
+                0 => return Ok(total),
+                n => {
+                    sink(&buf[..n]);
+                    total += n;
+                }
+            }
         }
     }
 }
 
+impl<H> TimeoutReader<H>
+where
+    H: BufRead + AsFd + BufferedHandle,
+{
+    /// Read onto the end of `buf` up to and including the first occurrence of
+    /// `pattern`, giving up once `timeout` has elapsed altogether, and return how many
+    /// bytes were read.
+    ///
+    /// The data is searched as it arrives, so a pattern split across reads is still
+    /// found, and nothing past the pattern is consumed from the handle's buffer. If
+    /// time runs out, the bytes collected so far are left in `buf`, and the error
+    /// carries a `PartialTransfer` with their number. Reaching end of file before the
+    /// pattern fails with an `io::ErrorKind::UnexpectedEof` error.
+    ///
+    /// To search a reader that does not buffer, wrap it in a `BufReader` and use
+    /// `partial::read_until_pattern` with a `TimeBudget`.
+    pub fn read_until_pattern(
+        &mut self,
+        pattern: &[u8],
+        buf: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> Result<usize> {
+        let mut n = 0;
+        budget::run_within(
+            self,
+            |rdr| &mut rdr.waiter.budget,
+            timeout,
+            |rdr| {
+                n = partial::read_until_pattern(rdr, pattern, buf)?;
+                Ok(())
+            },
+        )?;
+        Ok(n)
+    }
+
+    /// Read a whole, possibly multi-line, reply of a protocol such as SMTP or FTP
+    /// through `replies`, giving up once `timeout` has elapsed altogether.
+    ///
+    /// See `Reply` for the conventions understood. A reply cut off by the timeout is
+    /// kept in `replies`, and calling again with the same `ReplyReader` carries on
+    /// with it.
+    ///
+    /// To read replies from a reader that does not buffer, wrap it in a `BufReader`
+    /// and use `ReplyReader::read_reply` with a `TimeBudget`.
+    pub fn read_reply_within(
+        &mut self,
+        replies: &mut ReplyReader,
+        timeout: Duration,
+    ) -> Result<Reply> {
+        let mut reply = None;
+        budget::run_within(
+            self,
+            |rdr| &mut rdr.waiter.budget,
+            timeout,
+            |rdr| {
+                reply = Some(replies.read_reply(rdr)?);
+                Ok(())
+            },
+        )?;
+        Ok(reply.expect("a reply was read"))
+    }
+}
+
+impl TimeoutReader<File> {
+    /// Create a new `TimeoutReader` with an optional timeout that takes ownership of a
+    /// bare file descriptor, such as one received from another process.
+    ///
+    /// The descriptor is read through a `File`, which works for any kind of
+    /// descriptor, not just regular files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::os::fd::OwnedFd;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo(fd: OwnedFd) -> std::io::Result<()> {
+    /// let mut rdr = TimeoutReader::from_owned_fd(fd, Duration::new(5, 0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_owned_fd<T: Into<Option<Duration>>>(
+        fd: OwnedFd,
+        timeout: T,
+    ) -> TimeoutReader<File> {
+        TimeoutReader::new(File::from(fd), timeout)
+    }
+
+    /// Create a new `TimeoutReader` with an optional timeout around a duplicate of the
+    /// descriptor behind `handle`, leaving `handle` itself with the caller.
+    ///
+    /// The `TimeoutReader` owns the duplicate, so it can be moved to another thread or
+    /// outlive `handle`, and its close-on-exec flag is its own. Note that a duplicate
+    /// still shares the file offset and file status flags such as `O_NONBLOCK` with
+    /// `handle`, since those belong to the open file rather than to the descriptor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::io;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut rdr = TimeoutReader::new_dup(&io::stdin(), Duration::new(5, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_dup<F, T>(handle: &F, timeout: T) -> Result<TimeoutReader<File>>
+    where
+        F: AsFd,
+        T: Into<Option<Duration>>,
+    {
+        let fd = handle.as_fd().try_clone_to_owned()?;
+        Ok(TimeoutReader::from_owned_fd(fd, timeout))
+    }
+}
+
+impl From<OwnedFd> for TimeoutReader<File> {
+    /// Take ownership of a bare file descriptor, without a timeout.
+    fn from(fd: OwnedFd) -> TimeoutReader<File> {
+        TimeoutReader::from_owned_fd(fd, None)
+    }
+}
+
 pub trait TimeoutReadExt<H>
 where
     H: Read + AsFd,
@@ -138,10 +711,13 @@ where
 mod tests {
     use std::env;
     use std::fs::File;
-    use std::io::Read;
-    use std::os::fd::AsRawFd;
+    use std::io::BufReader;
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::{AsRawFd, BorrowedFd};
+    use std::os::unix::net::UnixStream;
     use std::path::PathBuf;
-    use std::time::Duration;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use super::*;
 
@@ -161,6 +737,168 @@ mod tests {
         };
     }
 
+    #[test]
+    fn zero_timeout_only_checks_readiness() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(left, Duration::new(0, 0));
+        assert_eq!(Some(Duration::new(0, 0)), rdr.timeout());
+
+        let mut buf = [0u8; 8];
+        let start = Instant::now();
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        right.write_all(b"ready").unwrap();
+        assert_eq!(5, rdr.read(&mut buf).unwrap());
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+    }
+
+    /// A handle that buffers what it reads from a socket.
+    struct Buffered(BufReader<UnixStream>);
+
+    impl Read for Buffered {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl BufRead for Buffered {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            self.0.fill_buf()
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.0.consume(amt)
+        }
+    }
+
+    impl AsFd for Buffered {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.0.get_ref().as_fd()
+        }
+    }
+
+    impl BufferedHandle for Buffered {
+        fn has_buffered_data(&self) -> bool {
+            !self.0.buffer().is_empty()
+        }
+    }
+
+    #[test]
+    fn buf_read_uses_buffer_before_waiting() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(Buffered(BufReader::new(left)), Duration::from_millis(50));
+        right.write_all(b"one\ntwo\n").unwrap();
+
+        let mut line = String::new();
+        rdr.read_line(&mut line).unwrap();
+        assert_eq!("one\n", line);
+
+        // Already buffered, even though the socket has nothing left to read.
+        line.clear();
+        rdr.read_line(&mut line).unwrap();
+        assert_eq!("two\n", line);
+
+        let err = rdr.read_line(&mut line).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn checking_buffered_data_skips_wait() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(Buffered(BufReader::new(left)), Duration::from_millis(50));
+        right.write_all(b"hello").unwrap();
+
+        let mut buf = [0u8; 2];
+        assert_eq!(2, rdr.read(&mut buf).unwrap());
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        rdr.set_check_buffered(true);
+        assert_eq!(2, rdr.read(&mut buf).unwrap());
+        assert_eq!(b"ll", &buf);
+    }
+
+    #[test]
+    fn reply_within_resumes_after_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(Buffered(BufReader::new(left)), Duration::new(5, 0));
+        let mut replies = ReplyReader::new();
+
+        right.write_all(b"220-Hello\r\n220 Re").unwrap();
+        let err = rdr
+            .read_reply_within(&mut replies, Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right.write_all(b"ady\r\n").unwrap();
+        let reply = rdr
+            .read_reply_within(&mut replies, Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(&["Hello", "Ready"], reply.lines());
+    }
+
+    #[test]
+    fn drain_input_discards_until_quiet() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(left, Duration::from_millis(10));
+        right.write_all(b"stale").unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            right.write_all(b" junk").unwrap();
+            right
+        });
+
+        assert_eq!(10, rdr.drain_input(Duration::from_millis(100)).unwrap());
+        let _right = writer.join().unwrap();
+        assert_eq!(0, rdr.stats().timeouts);
+    }
+
+    #[test]
+    fn wait_for_silence_gives_up_on_chatter() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(left, None);
+        right.write_all(b"OK\r\n").unwrap();
+        let mut response = Vec::new();
+        let quiet = Duration::from_millis(30);
+        let n = rdr
+            .wait_for_silence(&mut response, quiet, Duration::new(5, 0))
+            .unwrap();
+        assert_eq!(4, n);
+        assert_eq!(b"OK\r\n", &response[..]);
+
+        let writer = thread::spawn(move || {
+            for _ in 0..20 {
+                right.write_all(b".").unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        response.clear();
+        let err = rdr
+            .wait_for_silence(&mut response, quiet, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(!response.is_empty());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn writes_pass_through_with_write_timeout() {
+        use super::super::testing;
+
+        let (mock, mut control) = testing::mock_pollable().unwrap();
+        let mut rdr = TimeoutReader::new(mock, Duration::new(5, 0));
+        assert_eq!(None, rdr.write_timeout());
+        rdr.write_all(b"hello").unwrap();
+        assert_eq!(b"hello", &control.take_written().unwrap()[..]);
+
+        rdr.set_write_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        control.block_writes().unwrap();
+        assert_eq!(ErrorKind::TimedOut, rdr.write(b"x").unwrap_err().kind());
+        assert_eq!(2, rdr.stats().writes);
+    }
+
     #[test]
     fn read_regular_file_with_timeout() {
         let original_contents = include_str!("../test_data/regular_file.txt");
@@ -198,6 +936,113 @@ mod tests {
         assert_eq!(original_contents, read_contents);
     }
 
+    #[test]
+    fn read_terminal_with_termios_timeout() {
+        use nix::sys::termios::{tcgetattr, LocalFlags};
+
+        let pty = ::TimeoutPty::open(None, None).unwrap();
+        let (_reader, mut master, slave) = pty.into_parts();
+        let check = slave.try_clone().unwrap();
+
+        let mut slave = TimeoutReader::new_termios(slave, Duration::from_millis(200)).unwrap();
+        assert!(!tcgetattr(&check)
+            .unwrap()
+            .local_flags
+            .contains(LocalFlags::ICANON));
+
+        let mut buf = [0u8; 8];
+        let err = slave.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        master.write_all(b"ab").unwrap();
+        let n = slave.read(&mut buf).unwrap();
+        assert_eq!(b"ab", &buf[..n]);
+
+        // VTIME still ends the wait, and is still reported as a timeout rather than
+        // as end of file.
+        slave.set_timeout(Some(Duration::new(5, 0))).unwrap();
+        let err = slave.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        // The saved settings travel with the reader, so it can be dropped anywhere.
+        thread::spawn(move || drop(slave)).join().unwrap();
+        assert!(tcgetattr(&check)
+            .unwrap()
+            .local_flags
+            .contains(LocalFlags::ICANON));
+
+        // Without a timeout there is nothing for VTIME to enforce.
+        let _slave = TimeoutReader::new_termios(check.try_clone().unwrap(), None).unwrap();
+        assert!(tcgetattr(&check)
+            .unwrap()
+            .local_flags
+            .contains(LocalFlags::ICANON));
+    }
+
+    #[test]
+    fn debug_shows_timeout_without_handle() {
+        use nix::unistd::pipe;
+
+        struct Opaque(File);
+
+        impl Read for Opaque {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        impl AsFd for Opaque {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+
+        let (rx, _tx) = pipe().unwrap();
+        let fd = rx.as_raw_fd();
+        let rdr = TimeoutReader::new(Opaque(File::from(rx)), Duration::from_millis(1500));
+        assert_eq!(
+            format!(
+                "TimeoutReader {{ fd: {}, timeout: Some(1.5s), termios: false }}",
+                fd
+            ),
+            format!("{:?}", rdr)
+        );
+    }
+
+    #[test]
+    fn owned_fd_round_trip() {
+        use nix::unistd::pipe;
+
+        let (rx, tx) = pipe().unwrap();
+        let rx_fd = rx.as_raw_fd();
+        let mut rx = TimeoutReader::from_owned_fd(rx, Duration::from_millis(100));
+
+        let mut buf = [0u8; 4];
+        let err = rx.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        File::from(tx).write_all(b"ok").unwrap();
+        assert_eq!(2, rx.read(&mut buf).unwrap());
+
+        let rx = OwnedFd::from(rx);
+        assert_eq!(rx_fd, rx.as_raw_fd());
+    }
+
+    #[test]
+    fn new_dup_owns_its_descriptor() {
+        use nix::unistd::pipe;
+
+        let (rx, tx) = pipe().unwrap();
+        let mut dup = TimeoutReader::new_dup(&rx, Duration::new(5, 0)).unwrap();
+        assert_ne!(rx.as_raw_fd(), dup.as_raw_fd());
+        drop(rx);
+
+        File::from(tx).write_all(b"ok").unwrap();
+        let mut buf = String::new();
+        dup.read_to_string(&mut buf).unwrap();
+        assert_eq!("ok", buf);
+    }
+
     #[test]
     fn read_regular_file_with_timeout_extension_trait() {
         let original_contents = include_str!("../test_data/regular_file.txt");
@@ -214,4 +1059,24 @@ mod tests {
 
         assert_eq!(original_contents, read_contents);
     }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn read_buf_fills_uninitialized_buffer() {
+        use std::io::BorrowedBuf;
+        use std::mem::MaybeUninit;
+
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutReader::new(left, Duration::from_millis(100));
+        let mut storage = [MaybeUninit::<u8>::uninit(); 16];
+        let mut buf = BorrowedBuf::from(&mut storage[..]);
+
+        let err = rdr.read_buf(buf.unfilled()).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right.write_all(b"uninit").unwrap();
+        rdr.read_buf(buf.unfilled()).unwrap();
+        assert_eq!(b"uninit", buf.filled());
+        assert_eq!(6, rdr.stats().bytes_read);
+    }
 }
@@ -0,0 +1,242 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Numeric replies of line protocols such as SMTP and FTP.
+
+use std::io::{BufRead, Error, ErrorKind, Read, Result};
+use std::mem;
+use std::str;
+
+/// The longest reply line accepted, in bytes.
+const MAX_LINE: u64 = 8192;
+
+/// The `Reply` struct is a whole reply of a protocol such as SMTP or FTP, which can
+/// span several lines.
+///
+/// Each line of a multi-line reply but the last has a hyphen after the code, as in
+/// `250-PIPELINING`, and the last has a space, as in `250 OK`. FTP also allows lines
+/// without a code in between; those are kept whole.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    /// Returns the three-digit reply code.
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Returns the text of each line, without the code and separator in front of it
+    /// or the line ending after it.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Returns whether the code is in the 2xx range, which these protocols use for
+    /// success.
+    pub fn is_positive(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+}
+
+/// Read a whole reply from `rdr`.
+///
+/// A malformed reply fails with an `io::ErrorKind::InvalidData` error, and end of
+/// file part way through one with an `io::ErrorKind::UnexpectedEof` error. Whatever
+/// part of the reply was read before any other error, such as a timeout, is lost;
+/// read through a `ReplyReader` to carry on with it instead.
+pub fn read_reply<R: BufRead + ?Sized>(rdr: &mut R) -> Result<Reply> {
+    ReplyReader::new().read_reply(rdr)
+}
+
+/// The `ReplyReader` struct reads replies, keeping the part of a reply read so far
+/// when an error such as a timeout cuts it short.
+///
+/// Calling `read_reply` again with the same `ReplyReader` carries on with that reply,
+/// including a line that was only partly read, so the protocol stays in step. A
+/// malformed reply is dropped, and the next call starts afresh.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::reply::ReplyReader;
+/// use std::io::{BufReader, ErrorKind};
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+/// use timeout_readwrite::TimeoutReader;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:25")?;
+/// let mut rdr = BufReader::new(TimeoutReader::new(stream, Duration::new(5, 0)));
+/// let mut replies = ReplyReader::new();
+///
+/// let greeting = loop {
+///     match replies.read_reply(&mut rdr) {
+///         Err(ref e) if e.kind() == ErrorKind::TimedOut => println!("still waiting"),
+///         rslt => break rslt?,
+///     }
+/// };
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ReplyReader {
+    /// The part of a line read before an error cut it short.
+    line: Vec<u8>,
+    /// The lines of a multi-line reply read so far.
+    reply: Option<Reply>,
+}
+
+impl ReplyReader {
+    /// Create a new `ReplyReader` with nothing read yet.
+    pub fn new() -> ReplyReader {
+        ReplyReader::default()
+    }
+
+    /// Read a whole reply from `rdr`, carrying on with any reply an earlier call left
+    /// unfinished.
+    ///
+    /// The errors are the same as those of `reply::read_reply`.
+    pub fn read_reply<R: BufRead + ?Sized>(&mut self, rdr: &mut R) -> Result<Reply> {
+        loop {
+            let line = read_line(rdr, &mut self.line)?;
+            let mut reply = match self.reply.take() {
+                Some(reply) => reply,
+                None => {
+                    let (code, more, text) = parse_first(&line).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "malformed reply line")
+                    })?;
+                    let reply = Reply {
+                        code,
+                        lines: vec![text.to_owned()],
+                    };
+                    if !more {
+                        return Ok(reply);
+                    }
+                    self.reply = Some(reply);
+                    continue;
+                }
+            };
+            match parse_first(&line) {
+                Some((c, more, text)) if c == reply.code => {
+                    reply.lines.push(text.to_owned());
+                    if !more {
+                        return Ok(reply);
+                    }
+                }
+                _ => reply.lines.push(line),
+            }
+            self.reply = Some(reply);
+        }
+    }
+}
+
+/// Split a line into its code, whether more lines follow, and its text.
+fn parse_first(line: &str) -> Option<(u16, bool, &str)> {
+    let bytes = line.as_bytes();
+    if bytes.len() < 3 || !bytes[..3].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let code = line[..3].parse().ok()?;
+    match bytes.get(3) {
+        None => Some((code, false, "")),
+        Some(&b' ') => Some((code, false, &line[4..])),
+        Some(&b'-') => Some((code, true, &line[4..])),
+        Some(_) => None,
+    }
+}
+
+/// Read the rest of a line onto `partial`, and return the whole of it without its
+/// line ending. If reading fails, the part read so far is left in `partial`.
+fn read_line<R: BufRead + ?Sized>(rdr: &mut R, partial: &mut Vec<u8>) -> Result<String> {
+    let limit = MAX_LINE.saturating_sub(partial.len() as u64);
+    rdr.take(limit).read_until(b'\n', partial)?;
+    if partial.last() != Some(&b'\n') {
+        return Err(match partial.len() as u64 {
+            MAX_LINE => {
+                partial.clear();
+                Error::new(ErrorKind::InvalidData, "reply line too long")
+            }
+            _ => Error::new(ErrorKind::UnexpectedEof, "end of file inside a reply"),
+        });
+    }
+    let mut line = mem::take(partial);
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| Error::new(ErrorKind::InvalidData, "reply is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutReader;
+    use super::*;
+
+    #[test]
+    fn multi_line_reply_assembled() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = BufReader::new(TimeoutReader::new(left, Duration::from_millis(50)));
+
+        right
+            .write_all(b"250-mail.example.com\r\n250-PIPELINING\r\n250 8BITMIME\r\n")
+            .unwrap();
+        right
+            .write_all(b"230-Welcome\r\n  to FTP\r\n230 Done\r\n")
+            .unwrap();
+        right.write_all(b"354-Go ahead\r\n").unwrap();
+
+        let reply = read_reply(&mut rdr).unwrap();
+        assert_eq!(250, reply.code());
+        assert_eq!(
+            &["mail.example.com", "PIPELINING", "8BITMIME"],
+            reply.lines()
+        );
+        assert!(reply.is_positive());
+
+        let reply = read_reply(&mut rdr).unwrap();
+        assert_eq!(&["Welcome", "  to FTP", "Done"], reply.lines());
+
+        let err = read_reply(&mut rdr).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn reply_resumed_after_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = BufReader::new(TimeoutReader::new(left, Duration::from_millis(50)));
+        let mut replies = ReplyReader::new();
+
+        right
+            .write_all(b"250-mail.example.com\r\n250-PIPE")
+            .unwrap();
+        let err = replies.read_reply(&mut rdr).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right
+            .write_all(b"LINING\r\n250 8BITMIME\r\n220 Ready\r\n")
+            .unwrap();
+        let reply = replies.read_reply(&mut rdr).unwrap();
+        assert_eq!(
+            &["mail.example.com", "PIPELINING", "8BITMIME"],
+            reply.lines()
+        );
+        assert_eq!(220, replies.read_reply(&mut rdr).unwrap().code());
+
+        right.write_all(b"nonsense\r\n354 Go ahead\r\n").unwrap();
+        let err = replies.read_reply(&mut rdr).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(354, replies.read_reply(&mut rdr).unwrap().code());
+    }
+}
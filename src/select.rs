@@ -0,0 +1,233 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Wait for several file descriptors at once.
+
+use nix::poll::PollFd;
+pub use nix::poll::PollFlags;
+use std::io::{ErrorKind, Result};
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+use super::utils;
+
+/// What a source passed to `select` is waited on for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interest(PollFlags);
+
+impl Interest {
+    /// Wait until the source has data to read, or has reached end of file.
+    pub fn readable() -> Interest {
+        Interest(PollFlags::POLLIN)
+    }
+
+    /// Wait until the source can accept more data.
+    pub fn writable() -> Interest {
+        Interest(PollFlags::POLLOUT)
+    }
+}
+
+/// Wait up to `timeout` for any of `sources` to become ready, returning the index of
+/// the first one that did, or `None` if the timeout elapsed first.
+///
+/// A source also counts as ready when it hangs up or has an error pending, so that
+/// the caller finds out about it from the next operation. If `timeout` is `None`,
+/// this waits indefinitely.
+///
+/// This is the function behind the `timeout_select!` macro, for callers that build
+/// the list of sources at run time.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::select::{self, Interest};
+/// use std::os::fd::AsFd;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// let (left, _left_peer) = UnixStream::pair().unwrap();
+/// let (right, _right_peer) = UnixStream::pair().unwrap();
+/// let sources = [
+///     (left.as_fd(), Interest::readable()),
+///     (right.as_fd(), Interest::writable()),
+/// ];
+/// assert_eq!(Some(1), select::select(Some(Duration::from_millis(100)), &sources).unwrap());
+/// ```
+pub fn select(
+    timeout: Option<Duration>,
+    sources: &[(BorrowedFd, Interest)],
+) -> Result<Option<usize>> {
+    let sources: Vec<(BorrowedFd, PollFlags)> = sources
+        .iter()
+        .map(|&(fd, Interest(events))| (fd, events))
+        .collect();
+
+    match wait_ready(timeout, &sources) {
+        Ok(ready) => Ok(ready.first().map(|&(index, _)| index)),
+        Err(ref e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wait up to `timeout` for any of `sources` to receive one of its poll events,
+/// returning the index of each source that did, together with the events it received.
+///
+/// This is `poll` with the bookkeeping done: the descriptors are borrowed for the
+/// duration of the call, the timeout is converted and scaled like every other
+/// timeout in this crate, and only the ready entries are returned, in the order they
+/// were given. The events returned can include `POLLHUP`, `POLLERR` and `POLLNVAL`
+/// even though they cannot be asked for. If `timeout` is `None`, this waits
+/// indefinitely.
+///
+/// If none of the sources became ready in time, an `io::ErrorKind::TimedOut` error is
+/// returned, as with the wrappers.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::select::{self, PollFlags};
+/// use std::io::Write;
+/// use std::os::fd::AsFd;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// let (left, mut left_peer) = UnixStream::pair().unwrap();
+/// let (right, _right_peer) = UnixStream::pair().unwrap();
+/// left_peer.write_all(b"ping").unwrap();
+///
+/// let sources = [
+///     (left.as_fd(), PollFlags::POLLIN),
+///     (right.as_fd(), PollFlags::POLLIN),
+/// ];
+/// let ready = select::wait_ready(Some(Duration::from_millis(100)), &sources).unwrap();
+/// assert_eq!(vec![(0, PollFlags::POLLIN)], ready);
+/// ```
+pub fn wait_ready(
+    timeout: Option<Duration>,
+    sources: &[(BorrowedFd, PollFlags)],
+) -> Result<Vec<(usize, PollFlags)>> {
+    let mut fds: Vec<PollFd> = sources
+        .iter()
+        .map(|&(fd, events)| PollFd::new(fd, events))
+        .collect();
+
+    utils::wait_any(timeout.map(utils::timeout_ms), &mut fds)?;
+    Ok(fds
+        .iter()
+        .enumerate()
+        .filter_map(|(index, fd)| match fd.revents() {
+            Some(revents) if !revents.is_empty() => Some((index, revents)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Wait for the first of several sources to become ready and run the matching arm.
+///
+/// Each arm names what to wait for, `readable` or `writable`, and a source
+/// implementing `AsFd`. The sources are all waited on together in one `poll` call,
+/// and the arm of the first ready source runs. An optional final `timeout` arm runs
+/// if none became ready in time; without one, the macro waits indefinitely.
+///
+/// The macro evaluates to an `io::Result` holding the value of the arm that ran, or
+/// the error from `poll` itself if it failed.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate timeout_readwrite;
+///
+/// use std::io::Read;
+/// use std::os::unix::net::UnixStream;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let (mut control, _control_peer) = UnixStream::pair()?;
+/// let (mut data, _data_peer) = UnixStream::pair()?;
+/// let mut buf = [0u8; 64];
+///
+/// let outcome = timeout_select! {
+///     readable(control) => format!("{} control bytes", control.read(&mut buf)?),
+///     readable(data) => format!("{} data bytes", data.read(&mut buf)?),
+///     timeout(Duration::from_millis(50)) => String::from("idle"),
+/// }?;
+/// assert_eq!("idle", outcome);
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! timeout_select {
+    (@arms [$($arms:tt)*] timeout($timeout:expr) => $on_timeout:expr $(,)*) => {
+        timeout_select!(@run [$($arms)*] Some($timeout), Ok($on_timeout))
+    };
+    (@arms [$($arms:tt)*] $interest:ident($source:expr) => $body:expr, $($rest:tt)*) => {
+        timeout_select!(@arms [$($arms)* ($interest($source) => $body)] $($rest)*)
+    };
+    (@arms [$($arms:tt)*] $interest:ident($source:expr) => $body:expr) => {
+        timeout_select!(@arms [$($arms)* ($interest($source) => $body)])
+    };
+    (@arms [$($arms:tt)*]) => {
+        timeout_select!(@run [$($arms)*] None, unreachable!("poll returned without a timeout"))
+    };
+    (@run [$(($interest:ident($source:expr) => $body:expr))+] $timeout:expr, $on_timeout:expr) => {{
+        let ready = $crate::select::select(
+            $timeout,
+            &[$((::std::os::fd::AsFd::as_fd(&$source), $crate::select::Interest::$interest())),+],
+        );
+        match ready {
+            Ok(Some(ready)) => {
+                let mut arm = 0usize;
+                let mut value = None;
+                $(
+                    if value.is_none() && ready == arm {
+                        value = Some($body);
+                    }
+                    #[allow(unused_assignments)]
+                    {
+                        arm += 1;
+                    }
+                )+
+                Ok(value.expect("poll reported a source that was not passed to it"))
+            }
+            Ok(None) => $on_timeout,
+            Err(e) => Err(e),
+        }
+    }};
+    ($($arms:tt)+) => {
+        timeout_select!(@arms [] $($arms)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_arm_of_ready_source() {
+        let (mut first, _first_peer) = UnixStream::pair().unwrap();
+        let (mut second, mut second_peer) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+
+        let rslt = timeout_select! {
+            readable(first) => ("first", first.read(&mut buf).unwrap()),
+            readable(second) => ("second", second.read(&mut buf).unwrap()),
+            timeout(Duration::from_millis(50)) => ("timeout", 0),
+        };
+        assert_eq!(("timeout", 0), rslt.unwrap());
+
+        second_peer.write_all(b"ping").unwrap();
+        let rslt = timeout_select! {
+            readable(first) => ("first", first.read(&mut buf).unwrap()),
+            readable(second) => ("second", second.read(&mut buf).unwrap())
+        };
+        assert_eq!(("second", 4), rslt.unwrap());
+    }
+}
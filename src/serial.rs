@@ -0,0 +1,200 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Interoperability with the `serialport` crate.
+//!
+//! `serialport::TTYPort` only implements `AsRawFd`, so it cannot be wrapped by the
+//! timeout types in this crate directly. `SerialHandle` owns a `TTYPort` and adds
+//! the missing `AsFd` implementation, and `TimeoutSerialPortExt` provides the
+//! familiar `with_timeout` method on `TTYPort` itself.
+//!
+//! A serial port carries its own single timeout, which applies to both reading and
+//! writing. Converting a `TTYPort` with `From` adopts that timeout for both
+//! directions of the `TimeoutStream`, and `into_serial_port` hands the read timeout
+//! back to the port.
+//!
+//! The port's timeout is not replaced by the wrapper's, though. `TTYPort` waits for
+//! itself inside every `read`, `write` and `flush`, after the wrapper has waited, so
+//! a port timeout shorter than the wrapper's can still end an operation early, and
+//! without a wrapper timeout the port's timeout is the only one that applies.
+//! `with_timeout` sets the port's timeout to match the wrapper's, or to wait
+//! indefinitely when there is none. When changing the timeouts of the stream
+//! afterwards, or wrapping a `SerialHandle` by hand, keep the port's timeout at least
+//! as long through `SerialHandle::get_mut`.
+
+use serialport::{SerialPort, TTYPort};
+use std::io::{Read, Result, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::Duration;
+
+use super::TimeoutStream;
+
+/// The `SerialHandle` struct adapts a `serialport::TTYPort` so it can be wrapped in
+/// any of the timeout types in this crate.
+#[derive(Debug)]
+pub struct SerialHandle {
+    port: TTYPort,
+}
+
+impl SerialHandle {
+    /// Create a new `SerialHandle` taking ownership of `port`.
+    pub fn new(port: TTYPort) -> SerialHandle {
+        SerialHandle { port }
+    }
+
+    /// Gets a reference to the underlying serial port.
+    pub fn get_ref(&self) -> &TTYPort {
+        &self.port
+    }
+
+    /// Gets a mutable reference to the underlying serial port.
+    pub fn get_mut(&mut self) -> &mut TTYPort {
+        &mut self.port
+    }
+
+    /// Unwraps this `SerialHandle`, returning the underlying serial port.
+    pub fn into_inner(self) -> TTYPort {
+        self.port
+    }
+}
+
+impl From<TTYPort> for SerialHandle {
+    fn from(port: TTYPort) -> SerialHandle {
+        SerialHandle::new(port)
+    }
+}
+
+impl Read for SerialHandle {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialHandle {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.port.flush()
+    }
+}
+
+impl AsFd for SerialHandle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // SAFETY: the `TTYPort` owns its descriptor and keeps it open until it is
+        // dropped, which cannot happen while `self` is borrowed.
+        unsafe { BorrowedFd::borrow_raw(self.port.as_raw_fd()) }
+    }
+}
+
+impl From<TTYPort> for TimeoutStream<SerialHandle> {
+    /// Wrap a serial port, using its configured timeout for both reading and writing.
+    fn from(port: TTYPort) -> TimeoutStream<SerialHandle> {
+        let timeout = port.timeout();
+        TimeoutStream::new(SerialHandle::new(port), timeout, timeout)
+    }
+}
+
+impl TimeoutStream<SerialHandle> {
+    /// Unwraps this `TimeoutStream`, returning the underlying serial port with its own
+    /// timeout set to the read timeout of the stream.
+    ///
+    /// If the stream has no read timeout, the timeout of the port is left unchanged.
+    pub fn into_serial_port(self) -> serialport::Result<TTYPort> {
+        let timeout = self.read_timeout().ok().and_then(|t| t);
+        let mut port = self.into_inner().into_inner();
+        if let Some(timeout) = timeout {
+            port.set_timeout(timeout)?;
+        }
+        Ok(port)
+    }
+}
+
+pub trait TimeoutSerialPortExt {
+    fn with_timeout<T: Into<Option<Duration>>>(self, timeout: T) -> TimeoutStream<SerialHandle>;
+}
+
+impl TimeoutSerialPortExt for TTYPort {
+    fn with_timeout<T: Into<Option<Duration>>>(
+        mut self,
+        timeout: T,
+    ) -> TimeoutStream<SerialHandle> {
+        let timeout = timeout.into();
+        // Setting the timeout of a `TTYPort` cannot fail.
+        let _ = self.set_timeout(timeout.unwrap_or(Duration::MAX));
+        TimeoutStream::new(SerialHandle::new(self), timeout, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialport::{SerialPort, TTYPort};
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn wrap_pseudo_terminal_pair() {
+        let (master, slave) = TTYPort::pair().unwrap();
+        let mut master = master.with_timeout(Duration::new(5, 0));
+        let mut slave = slave.with_timeout(Duration::from_millis(100));
+
+        let mut buf = [0u8; 8];
+        let err = slave.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        master.write_all(b"AT\r").unwrap();
+        let mut buf = [0u8; 3];
+        slave.read_exact(&mut buf).unwrap();
+        assert_eq!(b"AT\r", &buf);
+    }
+
+    #[test]
+    fn port_timeout_follows_wrapper() {
+        let (master, slave) = TTYPort::pair().unwrap();
+        let mut master = master.with_timeout(None);
+        let slave = slave.with_timeout(Duration::from_millis(300));
+        assert_eq!(Duration::MAX, master.get_ref().get_ref().timeout());
+        assert_eq!(
+            Duration::from_millis(300),
+            slave.get_ref().get_ref().timeout()
+        );
+
+        // The port no longer gives up after its default of 100ms.
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            let mut slave = slave;
+            slave.write_all(b"OK").unwrap();
+            slave
+        });
+        let mut buf = [0u8; 2];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(b"OK", &buf);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn convert_timeouts() {
+        let (mut master, _slave) = TTYPort::pair().unwrap();
+        master.set_timeout(Duration::from_millis(250)).unwrap();
+
+        let mut stream = TimeoutStream::from(master);
+        assert_eq!(
+            Some(Duration::from_millis(250)),
+            stream.read_timeout().unwrap()
+        );
+
+        stream
+            .set_read_timeout(Some(Duration::from_millis(750)))
+            .unwrap();
+        let master = stream.into_serial_port().unwrap();
+        assert_eq!(Duration::from_millis(750), master.timeout());
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use std::fmt;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::utils;
+
+/// The `SharedTimeoutStream` struct shares one duplex handle between several owners,
+/// each with its own read and write timeouts.
+///
+/// A common pattern is to hand one connection to a reader thread and a writer thread.
+/// Cloning a `SharedTimeoutStream` gives each of them its own copy that refers to the
+/// same handle through an `Arc`, without duplicating the file descriptor. Timeouts
+/// belong to each copy, so the reader thread can wait for data indefinitely while the
+/// writer thread gives up on a stalled peer after a few seconds.
+///
+/// The handle is read and written through shared references, so `Read` and `Write`
+/// are available when it supports that the way `TcpStream` and `UnixStream` do.
+///
+/// If any `Read` or `Write` operation times out, the method called will return
+/// an `io::ErrorKind::TimedOut` variant as the value of `io::Error`. All other
+/// error values that would normally be produced by the underlying implementation
+/// of the `Read` or `Write` trait could also be produced by the `SharedTimeoutStream`.
+pub struct SharedTimeoutStream<H>
+where
+    H: AsFd,
+{
+    read_timeout: Option<c_int>,
+    write_timeout: Option<c_int>,
+    handle: Arc<H>,
+}
+
+impl<H> Read for SharedTimeoutStream<H>
+where
+    H: AsFd,
+    for<'a> &'a H: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl<H> Write for SharedTimeoutStream<H>
+where
+    H: AsFd,
+    for<'a> &'a H: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl<H> Read for &SharedTimeoutStream<H>
+where
+    H: AsFd,
+    for<'a> &'a H: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        utils::wait_until_ready(self.read_timeout, &*self.handle, PollFlags::POLLIN)?;
+        (&*self.handle).read(buf)
+    }
+}
+
+impl<H> Write for &SharedTimeoutStream<H>
+where
+    H: AsFd,
+    for<'a> &'a H: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        utils::wait_until_ready(self.write_timeout, &*self.handle, PollFlags::POLLOUT)?;
+        (&*self.handle).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        utils::wait_until_ready(self.write_timeout, &*self.handle, PollFlags::POLLOUT)?;
+        (&*self.handle).flush()
+    }
+}
+
+impl<H> AsFd for SharedTimeoutStream<H>
+where
+    H: AsFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.handle.as_fd()
+    }
+}
+
+impl<H> fmt::Debug for SharedTimeoutStream<H>
+where
+    H: AsFd,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedTimeoutStream")
+            .field("fd", &self.handle.as_fd().as_raw_fd())
+            .field(
+                "read_timeout",
+                &self.read_timeout.map(utils::ms_to_duration),
+            )
+            .field(
+                "write_timeout",
+                &self.write_timeout.map(utils::ms_to_duration),
+            )
+            .finish()
+    }
+}
+
+impl<H> Clone for SharedTimeoutStream<H>
+where
+    H: AsFd,
+{
+    /// Returns another owner of the same handle, starting out with the same timeouts.
+    fn clone(&self) -> SharedTimeoutStream<H> {
+        SharedTimeoutStream {
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            handle: Arc::clone(&self.handle),
+        }
+    }
+}
+
+impl<H> SharedTimeoutStream<H>
+where
+    H: AsFd,
+{
+    /// Create a new `SharedTimeoutStream` with optional read and write timeouts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::SharedTimeoutStream;
+    /// use std::io::{Read, Write};
+    /// use std::net::TcpStream;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut reader = SharedTimeoutStream::new(stream, None, None);
+    /// let mut writer = reader.clone();
+    /// writer.set_write_timeout(Some(Duration::new(5, 0)))?;
+    ///
+    /// thread::spawn(move || writer.write_all(b"hello"));
+    /// let mut buf = [0u8; 5];
+    /// reader.read_exact(&mut buf)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<R, W>(handle: H, read_timeout: R, write_timeout: W) -> SharedTimeoutStream<H>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        SharedTimeoutStream {
+            read_timeout: utils::initial_timeout(read_timeout.into()),
+            write_timeout: utils::initial_timeout(write_timeout.into()),
+            handle: Arc::new(handle),
+        }
+    }
+
+    /// Sets the read timeout of this copy to the timeout specified.
+    ///
+    /// Other copies of the stream keep their own read timeout. This mirrors
+    /// `TcpStream::set_read_timeout`: if the value specified is `None`, then `read`
+    /// calls will block indefinitely, and an `Err` is returned if the zero `Duration`
+    /// is passed to this method.
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.read_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Sets the write timeout of this copy to the timeout specified.
+    ///
+    /// Other copies of the stream keep their own write timeout. This mirrors
+    /// `TcpStream::set_write_timeout`: if the value specified is `None`, then `write`
+    /// calls will block indefinitely, and an `Err` is returned if the zero `Duration`
+    /// is passed to this method.
+    pub fn set_write_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.write_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Returns the read timeout of this copy.
+    ///
+    /// The timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.read_timeout.map(utils::ms_to_duration))
+    }
+
+    /// Returns the write timeout of this copy.
+    ///
+    /// The timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.write_timeout.map(utils::ms_to_duration))
+    }
+
+    /// Gets a reference to the underlying handle.
+    pub fn get_ref(&self) -> &H {
+        &self.handle
+    }
+
+    /// Unwraps this `SharedTimeoutStream`, returning the underlying handle if this is
+    /// the last copy of it. Otherwise, the stream is handed back unchanged.
+    pub fn try_unwrap(self) -> ::std::result::Result<H, SharedTimeoutStream<H>> {
+        let SharedTimeoutStream {
+            read_timeout,
+            write_timeout,
+            handle,
+        } = self;
+        Arc::try_unwrap(handle).map_err(|handle| SharedTimeoutStream {
+            read_timeout,
+            write_timeout,
+            handle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn clones_share_handle_with_own_timeouts() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut reader = SharedTimeoutStream::new(left, None, None);
+        let mut writer = reader.clone();
+        reader
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(None, writer.read_timeout().unwrap());
+
+        let mut buf = [0u8; 4];
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        thread::spawn(move || writer.write_all(b"ping"))
+            .join()
+            .unwrap()
+            .unwrap();
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+
+        assert!(reader.try_unwrap().is_ok());
+    }
+}
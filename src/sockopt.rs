@@ -0,0 +1,85 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::libc::c_int;
+use nix::sys::socket::{getsockopt, setsockopt, sockopt};
+use nix::sys::time::{TimeVal, TimeValLike};
+use std::io::Result;
+#[cfg(not(any(target_os = "linux", target_os = "fuchsia")))]
+use std::io::{Error, ErrorKind};
+use std::os::fd::{AsFd, OwnedFd};
+
+/// Convert the milliseconds stored by the wrappers into a socket timeout.
+///
+/// A `timeval` of zero disables the kernel timeout, so `None` maps to zero while a
+/// zero millisecond timeout is rounded up to the smallest timeout the kernel accepts.
+fn to_timeval(timeout: Option<c_int>) -> TimeVal {
+    match timeout {
+        None => TimeVal::zero(),
+        Some(ms) if ms <= 0 => TimeVal::microseconds(1),
+        Some(ms) => TimeVal::milliseconds(ms as i64),
+    }
+}
+
+/// Remembers the `SO_RCVTIMEO` and `SO_SNDTIMEO` values a socket had before a
+/// wrapper took it over, and puts them back when dropped.
+///
+/// The guard keeps its own duplicate of the descriptor. Socket options belong to the
+/// socket rather than to the descriptor, so restoring through the duplicate affects
+/// the handle the wrapper gave back, even after the wrapper has been unwrapped.
+pub struct SavedSocketTimeouts {
+    fd: OwnedFd,
+    read: TimeVal,
+    write: TimeVal,
+}
+
+impl SavedSocketTimeouts {
+    /// Save the current socket timeouts of `fd`.
+    pub fn save(fd: &impl AsFd) -> Result<SavedSocketTimeouts> {
+        let fd = fd.as_fd().try_clone_to_owned()?;
+        let read = getsockopt(&fd, sockopt::ReceiveTimeout)?;
+        let write = getsockopt(&fd, sockopt::SendTimeout)?;
+        Ok(SavedSocketTimeouts { fd, read, write })
+    }
+
+    /// Set the socket receive timeout.
+    pub fn set_read_timeout(&self, timeout: Option<c_int>) -> Result<()> {
+        setsockopt(&self.fd, sockopt::ReceiveTimeout, &to_timeval(timeout))?;
+        Ok(())
+    }
+
+    /// Set the socket send timeout.
+    pub fn set_write_timeout(&self, timeout: Option<c_int>) -> Result<()> {
+        setsockopt(&self.fd, sockopt::SendTimeout, &to_timeval(timeout))?;
+        Ok(())
+    }
+}
+
+impl Drop for SavedSocketTimeouts {
+    fn drop(&mut self) {
+        let _ = setsockopt(&self.fd, sockopt::ReceiveTimeout, &self.read);
+        let _ = setsockopt(&self.fd, sockopt::SendTimeout, &self.write);
+    }
+}
+
+/// Set `TCP_USER_TIMEOUT`, the longest transmitted data may remain unacknowledged
+/// before the kernel gives up on the connection. `None` restores the system default.
+#[cfg(any(target_os = "linux", target_os = "fuchsia"))]
+pub fn set_tcp_user_timeout(fd: &impl AsFd, timeout: Option<c_int>) -> Result<()> {
+    let ms = timeout.map_or(0, |ms| ms.max(1) as u32);
+    setsockopt(fd, sockopt::TcpUserTimeout, &ms)?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "fuchsia")))]
+pub fn set_tcp_user_timeout(_fd: &impl AsFd, _timeout: Option<c_int>) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "TCP_USER_TIMEOUT is not supported on this platform",
+    ))
+}
@@ -0,0 +1,85 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `mio::event::Source` implementations, so the wrappers can be registered with a
+//! `mio::Poll` directly. Registration is forwarded to the underlying descriptor.
+
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::io::{Read, Result, Write};
+use std::os::fd::{AsFd, AsRawFd};
+
+use super::{TimeoutReader, TimeoutStream, TimeoutWriter};
+
+macro_rules! impl_source {
+    ($wrapper:ident, $($bound:tt)+) => {
+        impl<H> Source for $wrapper<H>
+        where
+            H: $($bound)+,
+        {
+            fn register(
+                &mut self,
+                registry: &Registry,
+                token: Token,
+                interests: Interest,
+            ) -> Result<()> {
+                SourceFd(&self.as_fd().as_raw_fd()).register(registry, token, interests)
+            }
+
+            fn reregister(
+                &mut self,
+                registry: &Registry,
+                token: Token,
+                interests: Interest,
+            ) -> Result<()> {
+                SourceFd(&self.as_fd().as_raw_fd()).reregister(registry, token, interests)
+            }
+
+            fn deregister(&mut self, registry: &Registry) -> Result<()> {
+                SourceFd(&self.as_fd().as_raw_fd()).deregister(registry)
+            }
+        }
+    };
+}
+
+impl_source!(TimeoutReader, Read + AsFd);
+impl_source!(TimeoutWriter, Write + AsFd);
+impl_source!(TimeoutStream, Read + Write + AsFd);
+
+#[cfg(test)]
+mod tests {
+    use mio::{Events, Interest, Poll, Token};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutStream;
+
+    #[test]
+    fn register_stream_with_mio() {
+        let (mut left, right) = UnixStream::pair().unwrap();
+        let mut right = TimeoutStream::new(right, Duration::new(5, 0), None);
+
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut right, Token(7), Interest::READABLE)
+            .unwrap();
+
+        left.write_all(b"x").unwrap();
+        let mut events = Events::with_capacity(4);
+        poll.poll(&mut events, Some(Duration::new(5, 0))).unwrap();
+        assert!(events
+            .iter()
+            .any(|e| e.token() == Token(7) && e.is_readable()));
+
+        let mut buf = [0u8; 1];
+        right.read_exact(&mut buf).unwrap();
+        poll.registry().deregister(&mut right).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::time::Duration;
+
+/// A snapshot of the I/O a wrapper has performed, returned by its `stats` method.
+///
+/// The counters cover the wrapper and all of its clones, from the moment the first
+/// of them was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// The number of `read` calls, successful or not.
+    pub reads: u64,
+    /// The number of `write` calls, successful or not.
+    pub writes: u64,
+    /// The number of operations that failed with `io::ErrorKind::TimedOut`.
+    pub timeouts: u64,
+    /// The total number of bytes read.
+    pub bytes_read: u64,
+    /// The total number of bytes written.
+    pub bytes_written: u64,
+    /// The total time spent waiting for the handle to become ready.
+    pub wait_time: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::TimeoutStream;
+
+    #[test]
+    fn stats_count_operations() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, Duration::from_millis(50), None);
+        let mut right = TimeoutStream::new(right, None, Duration::new(5, 0));
+
+        let mut buf = [0u8; 8];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        right.write_all(b"hello").unwrap();
+        assert_eq!(5, left.read(&mut buf).unwrap());
+
+        let stats = left.stats();
+        assert_eq!(2, stats.reads);
+        assert_eq!(0, stats.writes);
+        assert_eq!(1, stats.timeouts);
+        assert_eq!(5, stats.bytes_read);
+        assert!(stats.wait_time >= Duration::from_millis(50));
+
+        let stats = right.stats();
+        assert_eq!(1, stats.writes);
+        assert_eq!(5, stats.bytes_written);
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{self, Result};
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use super::TimeoutReader;
+
+/// Create a `TimeoutReader` over the standard input of the process.
+///
+/// `std::io::Stdin` keeps its own buffer in front of the file descriptor, so polling
+/// the descriptor says nothing about data already sitting in that buffer, and a
+/// timeout could fire while input is in fact available. The returned reader
+/// instead reads from its own duplicate of the standard input descriptor without
+/// any buffering, so readiness of the descriptor always matches what `read` sees.
+/// Avoid mixing it with reads through `std::io::stdin()`, as anything that buffer
+/// has already consumed is not visible to this reader.
+///
+/// What counts as "data available" depends on what standard input is connected to:
+///
+/// * On a terminal in its usual canonical mode, input becomes available one line at
+///   a time, once the user presses enter; a single `read` returns at most that line.
+/// * On a pipe or socket, any bytes written by the other end are available, and end
+///   of file is reported once the writer closes its end.
+/// * A regular file or `/dev/null` is always ready, so the timeout never fires.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::stdin_with_timeout;
+/// use std::io::{BufRead, BufReader, ErrorKind};
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// println!("Continue? [y/N] (10 seconds to answer)");
+/// let mut answer = String::new();
+/// let stdin = stdin_with_timeout(Duration::new(10, 0))?;
+/// match BufReader::new(stdin).read_line(&mut answer) {
+///     Ok(_) if answer.trim() == "y" => println!("continuing"),
+///     Ok(_) => println!("stopping"),
+///     Err(ref e) if e.kind() == ErrorKind::TimedOut => println!("no answer, stopping"),
+///     Err(e) => return Err(e),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn stdin_with_timeout<T: Into<Option<Duration>>>(timeout: T) -> Result<TimeoutReader<File>> {
+    let fd = io::stdin().as_fd().try_clone_to_owned()?;
+    Ok(TimeoutReader::new(File::from(fd), timeout))
+}
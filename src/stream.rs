@@ -0,0 +1,1065 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use memchr::memchr;
+use nix::libc::c_int;
+use nix::poll::{PollFd, PollFlags};
+use nix::sys::socket::{self, MsgFlags};
+use std::fmt;
+use std::fs::File;
+#[cfg(feature = "nightly")]
+use std::io::BorrowedCursor;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::io::{Error, ErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::budget;
+use super::config;
+use super::net;
+use super::observe::Direction;
+use super::partial;
+use super::sockopt::{self, SavedSocketTimeouts};
+use super::timeout::{Duplex, Timeout};
+use super::utils;
+use super::wait::Heartbeat;
+
+/// Selects how a `TimeoutStream` enforces its timeouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutStrategy {
+    /// Wait for the handle to become ready with `poll` before each operation. This
+    /// works for any pollable handle and is the default.
+    Poll,
+    /// Hand the timeouts to the kernel via the `SO_RCVTIMEO` and `SO_SNDTIMEO` socket
+    /// options. This saves the extra `poll` system call on every operation and bounds
+    /// the `recv` or `send` call itself, but only works on sockets.
+    ///
+    /// The socket options in effect before the stream was created are restored once
+    /// the `TimeoutStream` (and every clone of it) has been dropped or unwrapped.
+    SocketOption,
+}
+
+/// The `TimeoutStream` struct adds read and write timeouts to any duplex handle.
+///
+/// Sockets, pseudo-terminals, and other handles that are both readable and writable
+/// usually need a timeout in each direction. Wrapping them in a `TimeoutReader` or a
+/// `TimeoutWriter` only covers one of those directions; a `TimeoutStream` waits until
+/// the handle is readable before each `read` and until it is writable before each
+/// `write`, each bounded by its own optional timeout.
+///
+/// If any `Read` or `Write` operation times out, the method called will return
+/// an `io::ErrorKind::TimedOut` variant as the value of `io::Error`. All other
+/// error values that would normally be produced by the underlying implementation
+/// of the `Read` or `Write` trait could also be produced by the `TimeoutStream`.
+///
+/// This is the generic `Timeout` wrapper for the `Duplex` direction, which is where
+/// the methods it shares with `TimeoutReader` and `TimeoutWriter` are documented.
+pub type TimeoutStream<H> = Timeout<H, Duplex>;
+
+impl<H> Read for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = self.waiter.start();
+        let rslt = self.wait_readable().and_then(|_| self.handle.read(buf));
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Read, start, rslt)
+    }
+
+    #[cfg(feature = "nightly")]
+    fn read_buf(&mut self, mut cursor: BorrowedCursor<'_>) -> Result<()> {
+        let start = self.waiter.start();
+        let before = cursor.written();
+        let rslt = self
+            .wait_readable()
+            .and_then(|_| self.handle.read_buf(cursor.reborrow()))
+            .map(|()| cursor.written() - before);
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Read, start, rslt).map(|_| ())
+    }
+}
+
+impl<H> Write for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let start = self.waiter.start();
+        let rslt = self
+            .wait_until_ready(self.write_timeout, PollFlags::POLLOUT)
+            .and_then(|_| {
+                let _beats = self.waiter.hold_heartbeats();
+                self.handle.write(buf)
+            });
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Write, start, rslt)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let rslt = self
+            .wait_until_ready(self.write_timeout, PollFlags::POLLOUT)
+            .and_then(|_| self.handle.flush());
+        self.waiter.record_flush(rslt)
+    }
+}
+
+/// Shared references can be read from and written to whenever the handle allows it,
+/// so one stream can be read on one thread while it is written on another, just like
+/// a `&TcpStream`.
+impl<H> Read for &TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+    for<'b> &'b H: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = self.waiter.start();
+        let rslt = self.wait_readable().and_then(|_| (&self.handle).read(buf));
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Read, start, rslt)
+    }
+
+    #[cfg(feature = "nightly")]
+    fn read_buf(&mut self, mut cursor: BorrowedCursor<'_>) -> Result<()> {
+        let start = self.waiter.start();
+        let before = cursor.written();
+        let rslt = self
+            .wait_readable()
+            .and_then(|_| (&self.handle).read_buf(cursor.reborrow()))
+            .map(|()| cursor.written() - before);
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Read, start, rslt).map(|_| ())
+    }
+}
+
+impl<H> Write for &TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+    for<'b> &'b H: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let start = self.waiter.start();
+        let rslt = self
+            .wait_until_ready(self.write_timeout, PollFlags::POLLOUT)
+            .and_then(|_| {
+                let _beats = self.waiter.hold_heartbeats();
+                (&self.handle).write(buf)
+            });
+        let rslt = self.check_sockopt_timeout(rslt);
+        self.waiter.record(Direction::Write, start, rslt)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let rslt = self
+            .wait_until_ready(self.write_timeout, PollFlags::POLLOUT)
+            .and_then(|_| (&self.handle).flush());
+        self.waiter.record_flush(rslt)
+    }
+}
+
+/// Shows the descriptor rather than the handle, so that every `TimeoutStream` can be
+/// debugged, whatever it wraps.
+impl<H> fmt::Debug for TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutStream")
+            .field("fd", &self.as_raw_fd())
+            .field(
+                "read_timeout",
+                &self.read_timeout.map(utils::ms_to_duration),
+            )
+            .field(
+                "write_timeout",
+                &self.write_timeout.map(utils::ms_to_duration),
+            )
+            .field("strategy", &self.strategy())
+            .finish()
+    }
+}
+
+impl<H> TimeoutStream<H>
+where
+    H: Read + Write + AsFd,
+{
+    /// Create a new `TimeoutStream` with optional read and write timeouts.
+    ///
+    /// # Examples
+    ///
+    /// This first example creates the `TimeoutStream` with a 5-second read timeout
+    /// and a 1-second write timeout.
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(5, 0), Duration::new(1, 0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// This example creates the `TimeoutStream` with only a read timeout.
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(5, 0), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<R, W>(handle: H, read_timeout: R, write_timeout: W) -> TimeoutStream<H>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        TimeoutStream {
+            read_timeout: utils::initial_timeout(read_timeout.into()),
+            write_timeout: utils::initial_timeout(write_timeout.into()),
+            ..Timeout::bare(handle)
+        }
+    }
+
+    /// Create a new `TimeoutStream`, falling back to the default timeout from the
+    /// `TIMEOUT_READWRITE_DEFAULT_MS` environment variable for either timeout that is
+    /// `None`.
+    ///
+    /// This lets operators tune timeouts without a rebuild. If the variable is not
+    /// set, or does not hold a whole number of milliseconds, the missing timeouts stay
+    /// unset, just as with `TimeoutStream::new`.
+    pub fn new_with_default<R, W>(handle: H, read_timeout: R, write_timeout: W) -> TimeoutStream<H>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        let read_timeout = read_timeout.into().or_else(config::env_default_timeout);
+        let write_timeout = write_timeout.into().or_else(config::env_default_timeout);
+        TimeoutStream::new(handle, read_timeout, write_timeout)
+    }
+
+    /// Create a new `TimeoutStream` with optional read and write timeouts, enforced
+    /// using the given `TimeoutStrategy`.
+    ///
+    /// With `TimeoutStrategy::SocketOption`, this fails if the handle is not a socket.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::{TimeoutStrategy, TimeoutStream};
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::with_strategy(
+    ///     stream,
+    ///     Duration::new(5, 0),
+    ///     Duration::new(1, 0),
+    ///     TimeoutStrategy::SocketOption,
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_strategy<R, W>(
+        handle: H,
+        read_timeout: R,
+        write_timeout: W,
+        strategy: TimeoutStrategy,
+    ) -> Result<TimeoutStream<H>>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        let mut stream = TimeoutStream::new(handle, read_timeout, write_timeout);
+        if strategy == TimeoutStrategy::SocketOption {
+            let saved = SavedSocketTimeouts::save(&stream.handle)?;
+            saved.set_read_timeout(stream.read_timeout)?;
+            saved.set_write_timeout(stream.write_timeout)?;
+            stream.sockopts = Some(Arc::new(saved));
+        }
+        Ok(stream)
+    }
+
+    /// Returns the strategy this stream uses to enforce its timeouts.
+    pub fn strategy(&self) -> TimeoutStrategy {
+        match self.sockopts {
+            Some(_) => TimeoutStrategy::SocketOption,
+            None => TimeoutStrategy::Poll,
+        }
+    }
+
+    /// Sets the read timeout to the timeout specified.
+    ///
+    /// This mirrors `TcpStream::set_read_timeout`: if the value specified is `None`,
+    /// then `read` calls will block indefinitely, and an `Err` is returned if the zero
+    /// `Duration` is passed to this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, None, None);
+    /// stream.set_read_timeout(Some(Duration::new(5, 0)))?;
+    /// assert!(stream.set_read_timeout(Some(Duration::new(0, 0))).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        let timeout = utils::checked_timeout(dur)?;
+        if let Some(ref sockopts) = self.sockopts {
+            sockopts.set_read_timeout(timeout)?;
+        }
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Sets the write timeout to the timeout specified.
+    ///
+    /// This mirrors `TcpStream::set_write_timeout`: if the value specified is `None`,
+    /// then `write` calls will block indefinitely, and an `Err` is returned if the
+    /// zero `Duration` is passed to this method.
+    pub fn set_write_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        let timeout = utils::checked_timeout(dur)?;
+        if let Some(ref sockopts) = self.sockopts {
+            sockopts.set_write_timeout(timeout)?;
+        }
+        if self.sync_user_timeout {
+            sockopt::set_tcp_user_timeout(&self.handle, timeout)?;
+        }
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    /// Returns the read timeout of this stream.
+    ///
+    /// If the timeout is `None`, then `read` calls will block indefinitely. The
+    /// timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.read_timeout.map(utils::ms_to_duration))
+    }
+
+    /// Returns the write timeout of this stream.
+    ///
+    /// If the timeout is `None`, then `write` calls will block indefinitely. The
+    /// timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.write_timeout.map(utils::ms_to_duration))
+    }
+
+    /// Sets the stream to send `message` every `interval` while a `read` waits for
+    /// data, to keep quiet connections from being dropped by middleboxes.
+    ///
+    /// The message is written straight to the descriptor, bypassing the handle, so it
+    /// must not be used with handles that frame their own data, such as TLS streams.
+    /// A beat is skipped if the handle cannot take the message without blocking, or if
+    /// a `write` through this stream or one of its clones is in progress, so a beat
+    /// never lands in the middle of one. It can still land between the writes that make
+    /// up a `write_all` on another thread, or in the middle of writes that reach the
+    /// descriptor some other way, such as through a `SharedTimeoutStream`, so do not
+    /// combine heartbeats with concurrent writers whose messages must not be split.
+    /// Writing a beat counts neither as a `write` nor as activity for the idle
+    /// watchdog.
+    /// Heartbeats are sent while waiting with no read timeout too, but not with
+    /// `TimeoutStrategy::SocketOption`, which does not wait with `poll`. The interval
+    /// is not affected by the timeout scale.
+    ///
+    /// If `interval` is `None`, heartbeats are turned off, and an `Err` is returned if
+    /// the zero `Duration` is passed to this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::io::Read;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(300, 0), Duration::new(5, 0));
+    /// stream.set_heartbeat(Some(Duration::new(30, 0)), b"PING\r\n")?;
+    ///
+    /// let mut buf = [0u8; 1024];
+    /// stream.read(&mut buf)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_heartbeat(&mut self, interval: Option<Duration>, message: &[u8]) -> Result<()> {
+        self.waiter.heartbeat = match interval {
+            Some(interval) if interval == Duration::new(0, 0) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "cannot set a 0 duration heartbeat interval",
+                ))
+            }
+            Some(interval) => Some(Arc::new(Heartbeat::new(
+                utils::duration_to_ms(interval).max(1),
+                message,
+            ))),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Returns the heartbeat interval and message of this stream, if heartbeats are
+    /// turned on.
+    pub fn heartbeat(&self) -> Option<(Duration, &[u8])> {
+        self.waiter
+            .heartbeat
+            .as_ref()
+            .map(|heartbeat| (heartbeat.interval(), heartbeat.message()))
+    }
+
+    /// Receives data on the socket without removing it from the queue, waiting for data
+    /// to arrive up until the read timeout.
+    ///
+    /// Successive calls return the same data, which makes this suitable for sniffing
+    /// the protocol spoken on a connection before handing it off. This uses `recv`
+    /// with `MSG_PEEK`, so it fails if the underlying handle is not a socket.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let stream = TimeoutStream::new(stream, Duration::new(5, 0), None);
+    ///
+    /// let mut magic = [0u8; 4];
+    /// let n = stream.peek(&mut magic)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        self.wait_until_ready(self.read_timeout, PollFlags::POLLIN)?;
+        let rslt = socket::recv(self.handle.as_fd().as_raw_fd(), buf, MsgFlags::MSG_PEEK);
+        self.check_sockopt_timeout(rslt.map_err(Error::from))
+    }
+
+    /// Write all of `request`, then read the start of the reply into `reply`, with the
+    /// write and the read sharing one `timeout`, and return how many bytes were read.
+    ///
+    /// The read returns whatever the peer has sent once any of it arrives, as a single
+    /// `read` would, and `Ok(0)` means the peer closed the connection. Whatever the
+    /// write leaves of `timeout` is all the read gets. If time runs out before the
+    /// whole request is written, the error carries a `PartialTransfer` with the number
+    /// of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(1, 0), Duration::new(1, 0));
+    ///
+    /// let mut reply = [0u8; 512];
+    /// let n = stream.transact(b"STATUS\n", &mut reply, Duration::new(2, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transact(
+        &mut self,
+        request: &[u8],
+        reply: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        let mut n = 0;
+        budget::run_within(
+            self,
+            |stream| &mut stream.waiter.budget,
+            timeout,
+            |stream| {
+                partial::write_all(stream, request)?;
+                n = loop {
+                    match stream.read(reply) {
+                        Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                        rslt => break rslt?,
+                    }
+                };
+                Ok(())
+            },
+        )?;
+        Ok(n)
+    }
+
+    /// Write all of `request`, then read onto the end of `reply` until `delimiter`
+    /// arrives, with the write and the read sharing one `timeout`, and return the
+    /// length of the reply, up to and including the delimiter.
+    ///
+    /// The stream is not buffered, so the read may also take in bytes the peer sent
+    /// after the delimiter. Those are left at the end of `reply`, after the returned
+    /// length. End of file before the delimiter is an `io::ErrorKind::UnexpectedEof`
+    /// error. If time runs out, the error carries a `PartialTransfer` with the number
+    /// of bytes written or, once the whole request has been written, read, and the
+    /// bytes read so far are kept in `reply`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(1, 0), Duration::new(1, 0));
+    ///
+    /// let mut reply = Vec::new();
+    /// let len = stream.transact_until(b"PING\r\n", b'\n', &mut reply, Duration::new(2, 0))?;
+    /// assert_eq!(b"PONG\r\n", &reply[..len]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transact_until(
+        &mut self,
+        request: &[u8],
+        delimiter: u8,
+        reply: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> Result<usize> {
+        let start = reply.len();
+        let mut len = 0;
+        budget::run_within(
+            self,
+            |stream| &mut stream.waiter.budget,
+            timeout,
+            |stream| {
+                partial::write_all(stream, request)?;
+                let mut chunk = [0u8; 4096];
+                loop {
+                    let n = match stream.read(&mut chunk) {
+                        Ok(0) => {
+                            return Err(Error::new(
+                                ErrorKind::UnexpectedEof,
+                                "end of file before the delimiter",
+                            ))
+                        }
+                        Ok(n) => n,
+                        Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(partial::timed_out(reply.len() - start, e)),
+                    };
+                    let from = reply.len();
+                    reply.extend_from_slice(&chunk[..n]);
+                    if let Some(pos) = memchr(delimiter, &reply[from..]) {
+                        len = from + pos + 1 - start;
+                        return Ok(());
+                    }
+                }
+            },
+        )?;
+        Ok(len)
+    }
+
+    /// Close the connection gracefully: shut down the write half, then discard the
+    /// peer's remaining input until it closes its end too, giving up once `timeout`
+    /// has elapsed, and return how many bytes were discarded.
+    ///
+    /// Closing a TCP socket with unread input pending makes the kernel reset the
+    /// connection, which can destroy a final reply still on its way to the peer.
+    /// Waiting for the peer's end of file first avoids that, but a peer that never
+    /// closes would hold the connection open forever. The handle is dropped in either
+    /// case; if time runs out, an `io::ErrorKind::TimedOut` error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::io::Write;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut stream = TimeoutStream::new(stream, Duration::new(5, 0), Duration::new(5, 0));
+    /// stream.write_all(b"QUIT\r\n")?;
+    /// stream.close_gracefully(Duration::new(2, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn close_gracefully(mut self, timeout: Duration) -> Result<usize> {
+        self.shutdown(Shutdown::Write)?;
+        let mut n = 0;
+        budget::run_within(
+            &mut self,
+            |stream| &mut stream.waiter.budget,
+            timeout,
+            |stream| {
+                let mut chunk = [0u8; 4096];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => return Ok(()),
+                        Ok(m) => n += m,
+                        Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            },
+        )?;
+        Ok(n)
+    }
+
+    /// Waits, up until the write timeout, or for as long as it takes if there is none,
+    /// for a connection started on a nonblocking socket to complete.
+    ///
+    /// When `connect` on a nonblocking socket fails with `EINPROGRESS`, the connection
+    /// goes on in the background. The socket becomes writable once the attempt is
+    /// over, and `SO_ERROR` tells whether it succeeded. This does both, returning
+    /// `Ok` once the stream is connected, or the error the connection failed with,
+    /// such as `io::ErrorKind::ConnectionRefused`. If the attempt is still going when
+    /// the timeout elapses, an `io::ErrorKind::TimedOut` error is returned, and the
+    /// attempt carries on.
+    ///
+    /// The socket is left in nonblocking mode. The stream works either way, since it
+    /// waits before every operation, but the socket can be switched back to blocking
+    /// mode through `get_ref`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate nix;
+    /// extern crate timeout_readwrite;
+    ///
+    /// use nix::errno::Errno;
+    /// use nix::sys::socket::{self, AddressFamily, SockFlag, SockType, SockaddrIn};
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::net::TcpStream;
+    /// use std::os::fd::AsRawFd;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let fd = socket::socket(
+    ///     AddressFamily::Inet,
+    ///     SockType::Stream,
+    ///     SockFlag::SOCK_NONBLOCK,
+    ///     None,
+    /// )?;
+    /// let addr = SockaddrIn::new(127, 0, 0, 1, 8080);
+    /// match socket::connect(fd.as_raw_fd(), &addr) {
+    ///     Ok(()) | Err(Errno::EINPROGRESS) => {}
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    ///
+    /// let stream = TimeoutStream::new(TcpStream::from(fd), None, Duration::new(5, 0));
+    /// stream.wait_connected()?;
+    /// # Ok(())
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn wait_connected(&self) -> Result<()> {
+        match self.write_timeout {
+            Some(_) => self.waiter.wait_until_ready(
+                self.write_timeout,
+                &self.handle,
+                PollFlags::POLLOUT,
+            )?,
+            // Without a timeout the waiter would not wait at all, but the outcome of the
+            // connection is only known once the socket is writable.
+            None => {
+                let mut fds = [PollFd::new(self.handle.as_fd(), PollFlags::POLLOUT)];
+                utils::wait_any(None, &mut fds)?
+            }
+        }
+        net::connect_result(&self.handle)
+    }
+
+    /// Wait for the stream to be readable, unless the handle has data buffered.
+    fn wait_readable(&self) -> Result<()> {
+        if self.buffered.is_some_and(|buffered| buffered(&self.handle)) {
+            return Ok(());
+        }
+        self.wait_until_ready(self.read_timeout, PollFlags::POLLIN)
+    }
+
+    /// Wait for the handle to be ready, unless the kernel enforces the timeouts.
+    fn wait_until_ready(&self, timeout: Option<c_int>, events: PollFlags) -> Result<()> {
+        match self.sockopts {
+            Some(_) => self.waiter.check_limits(&self.handle),
+            None => self.waiter.wait_until_ready(timeout, &self.handle, events),
+        }
+    }
+
+    /// A socket that hits its `SO_RCVTIMEO` or `SO_SNDTIMEO` fails the call with
+    /// `EAGAIN`; report that the same way as a timed out `poll`.
+    fn check_sockopt_timeout(&self, rslt: Result<usize>) -> Result<usize> {
+        match rslt {
+            Err(ref e) if self.sockopts.is_some() && e.kind() == ErrorKind::WouldBlock => {
+                let err = Error::new(
+                    ErrorKind::TimedOut,
+                    "timed out waiting for socket to be ready",
+                );
+                self.waiter.close_if_timed_out(&self.handle, Err(err))
+            }
+            rslt => rslt,
+        }
+    }
+}
+
+impl TimeoutStream<TcpStream> {
+    /// Keep the `TCP_USER_TIMEOUT` socket option in sync with the write timeout.
+    ///
+    /// A write timeout only bounds how long a `write` waits for room in the send
+    /// buffer; once the data is buffered, the kernel may keep retransmitting it to a
+    /// dead peer for many minutes. With this enabled, `TCP_USER_TIMEOUT` is set to
+    /// the write timeout, so the kernel also gives up on data that remains
+    /// unacknowledged for that long and the connection fails with an error.
+    /// Subsequent calls to `set_write_timeout` update the socket option as well.
+    ///
+    /// Disabling it restores the system default. `TCP_USER_TIMEOUT` is only
+    /// available on Linux; elsewhere enabling it fails with
+    /// `io::ErrorKind::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutConnectExt;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let addr = "127.0.0.1:8080".parse().unwrap();
+    /// let mut stream = TcpStream::connect_with_timeout(&addr, Duration::new(5, 0))?;
+    /// stream.set_tcp_user_timeout_sync(true)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_tcp_user_timeout_sync(&mut self, enabled: bool) -> Result<()> {
+        let timeout = if enabled { self.write_timeout } else { None };
+        sockopt::set_tcp_user_timeout(&self.handle, timeout)?;
+        self.sync_user_timeout = enabled;
+        Ok(())
+    }
+}
+
+impl TimeoutStream<File> {
+    /// Create a new `TimeoutStream` with optional read and write timeouts that takes
+    /// ownership of a bare file descriptor, such as a socket received from another
+    /// process.
+    ///
+    /// The descriptor is read and written through a `File`, which works for any kind
+    /// of descriptor, not just regular files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutStream;
+    /// use std::os::fd::OwnedFd;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo(fd: OwnedFd) -> std::io::Result<()> {
+    /// let mut stream = TimeoutStream::from_owned_fd(fd, Duration::new(5, 0), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_owned_fd<R, W>(
+        fd: OwnedFd,
+        read_timeout: R,
+        write_timeout: W,
+    ) -> TimeoutStream<File>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        TimeoutStream::new(File::from(fd), read_timeout, write_timeout)
+    }
+}
+
+impl From<OwnedFd> for TimeoutStream<File> {
+    /// Take ownership of a bare file descriptor, without any timeouts.
+    fn from(fd: OwnedFd) -> TimeoutStream<File> {
+        TimeoutStream::from_owned_fd(fd, None, None)
+    }
+}
+
+pub trait TimeoutStreamExt<H>
+where
+    H: Read + Write + AsFd,
+{
+    fn with_timeouts<R, W>(self, read_timeout: R, write_timeout: W) -> TimeoutStream<H>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>;
+}
+
+impl<H> TimeoutStreamExt<H> for H
+where
+    H: Read + Write + AsFd,
+{
+    fn with_timeouts<R, W>(self, read_timeout: R, write_timeout: W) -> TimeoutStream<H>
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        TimeoutStream::new(self, read_timeout, write_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn round_trip_over_socketpair() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, Duration::new(5, 0), Duration::new(5, 0));
+        let mut right = right.with_timeouts(Duration::new(5, 0), Duration::new(5, 0));
+
+        left.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+    }
+
+    #[test]
+    fn transact_shares_one_timeout() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::new(5, 0), Duration::new(5, 0));
+
+        let peer = thread::spawn(move || {
+            let mut request = [0u8; 5];
+            right.read_exact(&mut request).unwrap();
+            right.write_all(b"PONG\r\nextra").unwrap();
+            right
+        });
+        let mut reply = b"old".to_vec();
+        let len = stream
+            .transact_until(b"PING\n", b'\n', &mut reply, Duration::new(5, 0))
+            .unwrap();
+        assert_eq!(b"PONG\r\n", &reply[3..3 + len]);
+        let _right = peer.join().unwrap();
+
+        let start = Instant::now();
+        let mut buf = [0u8; 16];
+        let err = stream
+            .transact(b"PING\n", &mut buf, Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(start.elapsed() < Duration::new(1, 0));
+    }
+
+    #[test]
+    fn close_gracefully_drains_until_eof() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let stream = TimeoutStream::new(left, Duration::new(5, 0), Duration::new(5, 0));
+        let mut peer = TimeoutStream::new(right, Duration::new(5, 0), Duration::new(5, 0));
+
+        peer.write_all(b"bye").unwrap();
+        let closer = thread::spawn(move || stream.close_gracefully(Duration::new(5, 0)));
+        let mut buf = Vec::new();
+        peer.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        peer.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(3, closer.join().unwrap().unwrap());
+
+        let (left, _right) = UnixStream::pair().unwrap();
+        let stream = TimeoutStream::new(left, Duration::new(5, 0), Duration::new(5, 0));
+        let err = stream
+            .close_gracefully(Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn heartbeats_sent_while_read_waits() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::from_millis(130), None);
+        stream
+            .set_heartbeat(Some(Duration::from_millis(50)), b"beat")
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        right.set_nonblocking(true).unwrap();
+        let mut beats = Vec::new();
+        right.read_to_end(&mut beats).unwrap_err();
+        assert_eq!(b"beatbeat", &beats[..]);
+        assert_eq!(0, stream.stats().writes);
+    }
+
+    #[test]
+    fn heartbeats_skipped_while_writing() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut stream = TimeoutStream::new(left, Duration::from_millis(130), None);
+        stream
+            .set_heartbeat(Some(Duration::from_millis(50)), b"beat")
+            .unwrap();
+
+        // A write through a clone is in progress for as long as the guard is held.
+        let other = stream.try_clone().unwrap();
+        let writing = other.waiter.hold_heartbeats();
+        let mut buf = [0u8; 16];
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        drop(writing);
+
+        right.set_nonblocking(true).unwrap();
+        let err = right.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::WouldBlock, err.kind());
+    }
+
+    #[test]
+    fn read_times_out_on_quiet_peer() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, Duration::from_millis(100), None);
+
+        let mut buf = [0u8; 4];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn shared_reference_io_across_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let (left, right) = UnixStream::pair().unwrap();
+        let left = Arc::new(TimeoutStream::new(left, Duration::from_millis(100), None));
+        let right = TimeoutStream::new(right, Duration::new(5, 0), None);
+
+        let reader = Arc::clone(&left);
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 4];
+            (&*reader).read_exact(&mut buf).map(|_| buf)
+        });
+        (&right).write_all(b"ping").unwrap();
+        assert_eq!(b"ping", &handle.join().unwrap().unwrap());
+
+        let mut buf = [0u8; 4];
+        let err = (&*left).read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let (mut left, right) = UnixStream::pair().unwrap();
+        let mut right = TimeoutStream::new(right, Duration::new(5, 0), None);
+
+        left.write_all(b"GET /").unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(3, right.peek(&mut buf).unwrap());
+        assert_eq!(b"GET", &buf);
+
+        let mut buf = [0u8; 5];
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(b"GET /", &buf);
+    }
+
+    #[test]
+    fn peek_times_out_on_quiet_peer() {
+        let (_left, right) = UnixStream::pair().unwrap();
+        let right = TimeoutStream::new(right, Duration::from_millis(100), None);
+
+        let mut buf = [0u8; 4];
+        let err = right.peek(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn socket_option_strategy_times_out_and_restores() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        left.set_read_timeout(Some(Duration::new(7, 0))).unwrap();
+
+        let mut left = TimeoutStream::with_strategy(
+            left,
+            Duration::from_millis(100),
+            None,
+            TimeoutStrategy::SocketOption,
+        )
+        .unwrap();
+        assert_eq!(TimeoutStrategy::SocketOption, left.strategy());
+        assert_eq!(
+            Some(Duration::from_millis(100)),
+            left.get_ref().read_timeout().unwrap()
+        );
+
+        let mut buf = [0u8; 4];
+        let err = left.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        let left = left.into_inner();
+        assert_eq!(Some(Duration::new(7, 0)), left.read_timeout().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tcp_user_timeout_follows_write_timeout() {
+        use nix::sys::socket::{getsockopt, sockopt::TcpUserTimeout};
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = TimeoutStream::new(stream, None, Duration::from_millis(1500));
+
+        stream.set_tcp_user_timeout_sync(true).unwrap();
+        assert_eq!(1500, getsockopt(stream.get_ref(), TcpUserTimeout).unwrap());
+
+        stream.set_write_timeout(Some(Duration::new(3, 0))).unwrap();
+        assert_eq!(3000, getsockopt(stream.get_ref(), TcpUserTimeout).unwrap());
+
+        stream.set_tcp_user_timeout_sync(false).unwrap();
+        assert_eq!(0, getsockopt(stream.get_ref(), TcpUserTimeout).unwrap());
+    }
+
+    #[test]
+    fn std_compatible_timeout_accessors() {
+        let (left, _right) = UnixStream::pair().unwrap();
+        let mut left = TimeoutStream::new(left, None, None);
+        assert_eq!(None, left.read_timeout().unwrap());
+
+        left.set_read_timeout(Some(Duration::from_millis(1500)))
+            .unwrap();
+        left.set_write_timeout(Some(Duration::new(2, 0))).unwrap();
+        assert_eq!(
+            Some(Duration::from_millis(1500)),
+            left.read_timeout().unwrap()
+        );
+        assert_eq!(Some(Duration::new(2, 0)), left.write_timeout().unwrap());
+
+        let err = left
+            .set_read_timeout(Some(Duration::new(0, 0)))
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!(
+            Some(Duration::from_millis(1500)),
+            left.read_timeout().unwrap()
+        );
+    }
+}
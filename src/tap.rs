@@ -0,0 +1,277 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io::{Error, Read, Result, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::time::{Duration, Instant};
+
+use super::observe::Direction;
+
+/// The number of bytes shown on each line of a hex dump.
+const DUMP_WIDTH: usize = 16;
+
+/// One read or write seen by a `Tap`.
+///
+/// The `Display` implementation writes a header line giving the time, the direction,
+/// the outcome and how long the call took, followed by a hex dump of the data, one
+/// line per 16 bytes.
+#[derive(Debug)]
+pub struct TapEvent<'a> {
+    /// Whether the call was a read or a write.
+    pub direction: Direction,
+    /// When the call started, measured from the creation of the `Tap`.
+    pub at: Duration,
+    /// How long the call took, which includes any time the wrapper under the `Tap`
+    /// spent waiting for its handle.
+    pub elapsed: Duration,
+    /// The bytes read or written, which is empty if the call failed.
+    pub data: &'a [u8],
+    /// The error the call failed with, if it did.
+    pub error: Option<&'a Error>,
+}
+
+impl<'a> fmt::Display for TapEvent<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self.direction {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        };
+        write!(
+            f,
+            "[{:5}.{:06}] ",
+            self.at.as_secs(),
+            self.at.subsec_micros()
+        )?;
+        match self.error {
+            Some(e) => write!(f, "{} failed after {:?}: {}", verb, self.elapsed, e)?,
+            None => write!(
+                f,
+                "{} {} bytes after {:?}",
+                verb,
+                self.data.len(),
+                self.elapsed
+            )?,
+        }
+
+        for (line, chunk) in self.data.chunks(DUMP_WIDTH).enumerate() {
+            write!(f, "\n  {:04x} ", line * DUMP_WIDTH)?;
+            for byte in chunk {
+                write!(f, " {:02x}", byte)?;
+            }
+            for _ in chunk.len()..DUMP_WIDTH {
+                f.write_str("   ")?;
+            }
+            f.write_str("  |")?;
+            for &byte in chunk {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+            f.write_str("|")?;
+        }
+        Ok(())
+    }
+}
+
+/// The `Tap` struct reports every read and write that passes through it, for
+/// debugging.
+///
+/// A `Tap` sits in front of a handle, usually a `TimeoutReader`, `TimeoutWriter` or
+/// `TimeoutStream`, and passes each call straight through to it. Afterwards, it hands
+/// a `TapEvent` describing the call to its sink: either a `Write` sink, which gets a
+/// timestamped hex dump of each event, or a callback. The time a call took includes
+/// the wrapper's wait, so a slow peer shows up as a long gap before its data, and a
+/// timeout as a failed call that took as long as the timeout.
+///
+/// Errors writing to a `Write` sink are ignored, so a broken log never affects the
+/// data passing through.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{Tap, TimeoutStream};
+/// use std::io::{self, Write};
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let stream = TimeoutStream::new(stream, Duration::new(5, 0), Duration::new(5, 0));
+///
+/// let mut stream = Tap::new(stream, io::stderr());
+/// stream.write_all(b"hello")?;
+/// // [    0.000012] write 5 bytes after 4.25µs
+/// //   0000  68 65 6c 6c 6f                                   |hello|
+/// # Ok(())
+/// # }
+/// ```
+pub struct Tap<H> {
+    inner: H,
+    start: Instant,
+    sink: Box<dyn FnMut(&TapEvent) + Send>,
+}
+
+impl<H> Tap<H> {
+    /// Create a new `Tap` in front of `inner`, writing a hex dump of each read and
+    /// write to `sink`.
+    pub fn new<W>(inner: H, mut sink: W) -> Tap<H>
+    where
+        W: Write + Send + 'static,
+    {
+        Tap::with_callback(inner, move |event| {
+            let _ = writeln!(sink, "{}", event);
+        })
+    }
+
+    /// Create a new `Tap` in front of `inner`, calling `callback` after each read and
+    /// write.
+    pub fn with_callback<F>(inner: H, callback: F) -> Tap<H>
+    where
+        F: FnMut(&TapEvent) + Send + 'static,
+    {
+        Tap {
+            inner,
+            start: Instant::now(),
+            sink: Box::new(callback),
+        }
+    }
+
+    /// Gets a reference to the underlying handle.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying handle.
+    ///
+    /// Calls made directly on the handle are not reported.
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    /// Unwraps this `Tap`, returning the underlying handle.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Report a call that started at `start`, with its outcome.
+    fn report(&mut self, direction: Direction, start: Instant, data: &[u8], rslt: &Result<usize>) {
+        let (data, error) = match *rslt {
+            Ok(n) => (&data[..n], None),
+            Err(ref e) => (&data[..0], Some(e)),
+        };
+        (self.sink)(&TapEvent {
+            direction,
+            at: start.duration_since(self.start),
+            elapsed: start.elapsed(),
+            data,
+            error,
+        });
+    }
+}
+
+impl<H> Read for Tap<H>
+where
+    H: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = Instant::now();
+        let rslt = self.inner.read(buf);
+        self.report(Direction::Read, start, buf, &rslt);
+        rslt
+    }
+}
+
+impl<H> Write for Tap<H>
+where
+    H: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let start = Instant::now();
+        let rslt = self.inner.write(buf);
+        self.report(Direction::Write, start, buf, &rslt);
+        rslt
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<H> AsFd for Tap<H>
+where
+    H: AsFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl<H> AsRawFd for Tap<H>
+where
+    H: AsRawFd,
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<H> fmt::Debug for Tap<H>
+where
+    H: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tap")
+            .field("inner", &self.inner)
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::super::TimeoutReader;
+    use super::*;
+
+    #[test]
+    fn reports_reads_and_timeouts() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let rdr = TimeoutReader::new(left, Duration::from_millis(50));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut rdr = {
+            let log = Arc::clone(&log);
+            Tap::with_callback(rdr, move |event| {
+                log.lock().unwrap().push(event.to_string());
+            })
+        };
+
+        right.write_all(b"hello\0").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(6, rdr.read(&mut buf).unwrap());
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+
+        let log = log.lock().unwrap();
+        assert_eq!(2, log.len());
+        let mut lines = log[0].lines();
+        assert!(lines.next().unwrap().contains("] read 6 bytes after "));
+        assert_eq!(
+            "  0000  68 65 6c 6c 6f 00                                |hello.|",
+            lines.next().unwrap()
+        );
+        assert!(log[1].contains("] read failed after "));
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{ErrorKind, Result, Write};
+
+/// What a `TeeWriter` does when writing to one of its sinks times out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnSinkTimeout {
+    /// Stop writing to the sink for good, and carry on with the others.
+    Drop,
+    /// Fail the operation with the sink's `io::ErrorKind::TimedOut` error. The sink
+    /// stays in place, so later operations try it again.
+    Error,
+    /// Skip the sink for this operation only, and carry on with the others.
+    Continue,
+}
+
+/// The `TeeWriter` struct copies everything written to it to several sinks, each with
+/// its own timeout.
+///
+/// Each sink is usually a `TimeoutWriter`, so that a stalled sink gives up after its
+/// own timeout rather than holding up the others forever. What happens next is up to
+/// the `OnSinkTimeout` policy: the sink can be dropped, the write can fail, or the
+/// sink can miss out on just that write. Errors other than timeouts always fail the
+/// operation.
+///
+/// Every sink receives the whole of each write, so `write` either accepts all of
+/// `buf` or fails. A sink that times out part way through may have received some of
+/// the data, which leaves a gap in its copy of the stream unless the policy is
+/// `OnSinkTimeout::Error`.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutWriter;
+/// use timeout_readwrite::tee::{OnSinkTimeout, TeeWriter};
+/// use std::fs::File;
+/// use std::io::Write;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let log = File::create("capture.log")?;
+/// let socket = TcpStream::connect("127.0.0.1:8080")?;
+/// let sinks: Vec<Box<dyn Write>> = vec![
+///     Box::new(TimeoutWriter::new(log, Duration::new(5, 0))),
+///     Box::new(TimeoutWriter::new(socket, Duration::from_millis(200))),
+/// ];
+///
+/// // A stuck socket is dropped instead of stalling the log.
+/// let mut tee = TeeWriter::new(sinks, OnSinkTimeout::Drop);
+/// tee.write_all(b"hello")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TeeWriter<W> {
+    sinks: Vec<Option<W>>,
+    policy: OnSinkTimeout,
+}
+
+impl<W> TeeWriter<W>
+where
+    W: Write,
+{
+    /// Create a new `TeeWriter` copying to `sinks`, handling timeouts according to
+    /// `policy`.
+    pub fn new(sinks: Vec<W>, policy: OnSinkTimeout) -> TeeWriter<W> {
+        TeeWriter {
+            sinks: sinks.into_iter().map(Some).collect(),
+            policy,
+        }
+    }
+
+    /// Returns the sink at `index`, or `None` if it has been dropped.
+    pub fn sink(&self, index: usize) -> Option<&W> {
+        self.sinks.get(index).and_then(Option::as_ref)
+    }
+
+    /// Returns a mutable reference to the sink at `index`, or `None` if it has been
+    /// dropped.
+    pub fn sink_mut(&mut self, index: usize) -> Option<&mut W> {
+        self.sinks.get_mut(index).and_then(Option::as_mut)
+    }
+
+    /// Returns the number of sinks that have not been dropped.
+    pub fn active_sinks(&self) -> usize {
+        self.sinks.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Unwraps this `TeeWriter`, returning the sinks in their original positions, with
+    /// `None` in place of those that were dropped.
+    pub fn into_sinks(self) -> Vec<Option<W>> {
+        self.sinks
+    }
+
+    /// Run `op` on each remaining sink, applying the timeout policy.
+    fn for_each_sink<F>(&mut self, mut op: F) -> Result<()>
+    where
+        F: FnMut(&mut W) -> Result<()>,
+    {
+        for slot in self.sinks.iter_mut() {
+            let rslt = match *slot {
+                Some(ref mut sink) => op(sink),
+                None => continue,
+            };
+            match rslt {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => match self.policy {
+                    OnSinkTimeout::Drop => *slot = None,
+                    OnSinkTimeout::Error => return rslt,
+                    OnSinkTimeout::Continue => {}
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W> Write for TeeWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.for_each_sink(|sink| sink.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.for_each_sink(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::time::Duration;
+
+    use super::super::testing;
+    use super::super::TimeoutWriter;
+    use super::*;
+
+    #[test]
+    fn stalled_sink_follows_policy() {
+        let (fast, fast_control) = testing::mock_pollable().unwrap();
+        let (slow, mut slow_control) = testing::mock_pollable().unwrap();
+        slow_control.block_writes().unwrap();
+        let sinks = vec![
+            TimeoutWriter::new(fast, Duration::new(5, 0)),
+            TimeoutWriter::new(slow, Duration::from_millis(20)),
+        ];
+
+        let mut tee = TeeWriter::new(sinks, OnSinkTimeout::Error);
+        let err = tee.write(b"one").unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert_eq!(2, tee.active_sinks());
+
+        tee.policy = OnSinkTimeout::Continue;
+        tee.write_all(b"two").unwrap();
+        assert_eq!(2, tee.active_sinks());
+
+        tee.policy = OnSinkTimeout::Drop;
+        tee.write_all(b"three").unwrap();
+        assert_eq!(1, tee.active_sinks());
+        assert!(tee.sink(1).is_none());
+        assert_eq!(b"onetwothree", &fast_control.take_written().unwrap()[..]);
+    }
+}
@@ -0,0 +1,866 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Utilities for exercising timeout paths deterministically in tests.
+//!
+//! Timeout handling is notoriously slow and flaky to test against real peers. This
+//! module provides a `ManualClock` that only moves when told to, which can drive a
+//! `TimeBudget` without sleeping, and a `MockPollable` handle whose readiness is
+//! scripted through its `ReadinessControl`. `ScriptedReader` replays a timeline of
+//! chunks for reproducing slow or stalling peers, and a `Recorder` captures the
+//! traffic of a real peer as a `Recording` that it can replay. `DelayedReader` and
+//! `DelayedWriter` add latency to every operation on a handle.
+//!
+//! This module is only available with the `testing` feature, typically enabled
+//! from `[dev-dependencies]`.
+
+use nix::sys::socket::{self, MsgFlags};
+use nix::unistd;
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use super::budget::{Clock, SystemClock};
+
+/// A `Clock` that stands still until it is advanced by hand.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeBudget;
+/// use timeout_readwrite::testing::ManualClock;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = Arc::new(ManualClock::new());
+/// let budget = TimeBudget::with_clock(Duration::new(30, 0), clock.clone());
+/// assert!(!budget.is_exhausted());
+///
+/// clock.advance(Duration::new(30, 0));
+/// assert!(budget.is_exhausted());
+/// ```
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<Instant>,
+}
+
+impl ManualClock {
+    /// Create a new `ManualClock` starting at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A handle whose readiness is scripted by its `ReadinessControl`.
+///
+/// `MockPollable` is backed by a socket pair, so the wrappers poll it exactly as they
+/// would a real descriptor: it becomes readable when the control makes data available
+/// or closes, and stops being writable while the control holds writes back.
+pub struct MockPollable {
+    inner: UnixStream,
+}
+
+/// The controlling end of a `MockPollable`.
+pub struct ReadinessControl {
+    peer: UnixStream,
+    // A second descriptor for the mock's own end, used to fill its send buffer.
+    filler: UnixStream,
+    blocked: bool,
+}
+
+/// Create a `MockPollable` together with the `ReadinessControl` that drives it.
+///
+/// The mock starts out writable and not readable.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::testing;
+/// use std::io::{ErrorKind, Read};
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let (mock, control) = testing::mock_pollable()?;
+/// let mut rdr = TimeoutReader::new(mock, Duration::from_millis(10));
+///
+/// let mut buf = [0u8; 5];
+/// assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+///
+/// control.make_readable(b"hello")?;
+/// rdr.read_exact(&mut buf)?;
+/// assert_eq!(b"hello", &buf);
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+pub fn mock_pollable() -> Result<(MockPollable, ReadinessControl)> {
+    let (inner, peer) = UnixStream::pair()?;
+    let filler = inner.try_clone()?;
+    Ok((
+        MockPollable { inner },
+        ReadinessControl {
+            peer,
+            filler,
+            blocked: false,
+        },
+    ))
+}
+
+impl Read for MockPollable {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for MockPollable {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsFd for MockPollable {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl fmt::Debug for MockPollable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockPollable")
+            .field("fd", &self.inner.as_raw_fd())
+            .finish()
+    }
+}
+
+/// Send without blocking, reporting a full buffer as `Ok(0)`.
+fn send_nonblocking(sock: &UnixStream, buf: &[u8]) -> Result<usize> {
+    match socket::send(sock.as_raw_fd(), buf, MsgFlags::MSG_DONTWAIT) {
+        Ok(n) => Ok(n),
+        Err(nix::errno::Errno::EAGAIN) => Ok(0),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// Receive without blocking, reporting an empty buffer as `Ok(0)`.
+fn recv_nonblocking(sock: &UnixStream, buf: &mut [u8]) -> Result<usize> {
+    match socket::recv(sock.as_raw_fd(), buf, MsgFlags::MSG_DONTWAIT) {
+        Ok(n) => Ok(n),
+        Err(nix::errno::Errno::EAGAIN) => Ok(0),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+impl ReadinessControl {
+    /// Makes `data` available for the mock to read, which makes it readable.
+    pub fn make_readable(&self, data: &[u8]) -> Result<()> {
+        (&self.peer).write_all(data)
+    }
+
+    /// Returns everything the mock has written so far.
+    ///
+    /// Data written while writes are held back is not included, since it was only
+    /// filler.
+    pub fn take_written(&self) -> Result<Vec<u8>> {
+        let mut written = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = recv_nonblocking(&self.peer, &mut buf)?;
+            if n == 0 {
+                return Ok(written);
+            }
+            written.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// Holds back writes, so the mock stops being writable until `unblock_writes`.
+    ///
+    /// Anything the mock wrote before this call is discarded; call `take_written`
+    /// first to keep it.
+    pub fn block_writes(&mut self) -> Result<()> {
+        if self.blocked {
+            return Ok(());
+        }
+        self.take_written()?;
+
+        let chunk = [0u8; 4096];
+        while send_nonblocking(&self.filler, &chunk)? > 0 {}
+        self.blocked = true;
+        Ok(())
+    }
+
+    /// Lets writes through again after `block_writes`.
+    pub fn unblock_writes(&mut self) -> Result<()> {
+        if self.blocked {
+            self.take_written()?;
+            self.blocked = false;
+        }
+        Ok(())
+    }
+
+    /// Closes the controlling end, which the mock sees as end of file on reads and
+    /// as a broken pipe on writes.
+    pub fn close(self) -> Result<()> {
+        self.peer.shutdown(Shutdown::Both)
+    }
+}
+
+impl fmt::Debug for ReadinessControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadinessControl")
+            .field("writes_blocked", &self.blocked)
+            .finish()
+    }
+}
+
+/// A readable handle that replays a script of chunks, each after its own delay.
+///
+/// The chunks are written into a pipe by a helper thread, so `ScriptedReader` is a
+/// real descriptor that the wrappers wait on like any other. Each chunk is written
+/// in one piece once its delay, measured from the previous chunk, has passed; after
+/// the last chunk the pipe is closed and reads report end of file.
+///
+/// This makes it easy to reproduce a slow trickle of small chunks, a long silence
+/// before the first byte, or a stall in the middle of a message.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::testing::ScriptedReader;
+/// use std::io::{ErrorKind, Read};
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// // Half a message, then a stall longer than the reader is willing to wait.
+/// let script = ScriptedReader::new(vec![
+///     (Duration::new(0, 0), &b"HEAD"[..]),
+///     (Duration::from_millis(200), &b"TAIL"[..]),
+/// ])?;
+/// let mut rdr = TimeoutReader::new(script, Duration::from_millis(50));
+///
+/// let mut buf = [0u8; 8];
+/// let err = rdr.read_exact(&mut buf).unwrap_err();
+/// assert_eq!(ErrorKind::TimedOut, err.kind());
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+pub struct ScriptedReader {
+    pipe: File,
+}
+
+impl ScriptedReader {
+    /// Create a new `ScriptedReader` replaying `script`, a sequence of delays and the
+    /// chunks to make available once each delay has passed.
+    pub fn new<I, B>(script: I) -> Result<ScriptedReader>
+    where
+        I: IntoIterator<Item = (Duration, B)>,
+        B: AsRef<[u8]>,
+    {
+        let script: Vec<(Duration, Vec<u8>)> = script
+            .into_iter()
+            .map(|(delay, chunk)| (delay, chunk.as_ref().to_vec()))
+            .collect();
+        let (rdr, wtr) = unistd::pipe()?;
+        let mut wtr = File::from(wtr);
+
+        thread::Builder::new()
+            .name("scripted-reader".into())
+            .spawn(move || {
+                for (delay, chunk) in script {
+                    thread::sleep(delay);
+                    // The reader went away; nobody is left to replay the script to.
+                    if wtr.write_all(&chunk).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(ScriptedReader {
+            pipe: File::from(rdr),
+        })
+    }
+}
+
+impl Read for ScriptedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl AsFd for ScriptedReader {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.pipe.as_fd()
+    }
+}
+
+/// A source of delays for `DelayedReader` and `DelayedWriter`.
+///
+/// A fixed `Duration` delays every operation by the same amount, `UniformDelay` picks
+/// a delay at random from a range, and any closure returning a `Duration` can be used
+/// for other distributions.
+pub trait DelayDistribution {
+    /// Returns the delay to apply to the next operation.
+    fn next_delay(&mut self) -> Duration;
+}
+
+impl DelayDistribution for Duration {
+    fn next_delay(&mut self) -> Duration {
+        *self
+    }
+}
+
+impl<F> DelayDistribution for F
+where
+    F: FnMut() -> Duration,
+{
+    fn next_delay(&mut self) -> Duration {
+        self()
+    }
+}
+
+/// A `DelayDistribution` that picks delays uniformly at random between a minimum and
+/// a maximum, inclusive.
+///
+/// The generator is a small xorshift, which is plenty for spreading out delays but is
+/// not suitable for anything else. Use `with_seed` for a repeatable sequence.
+#[derive(Clone, Debug)]
+pub struct UniformDelay {
+    min: Duration,
+    max: Duration,
+    state: u64,
+}
+
+impl UniformDelay {
+    /// Create a new `UniformDelay` between `min` and `max`, seeded from the clock.
+    pub fn new(min: Duration, max: Duration) -> UniformDelay {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        UniformDelay::with_seed(min, max, seed)
+    }
+
+    /// Create a new `UniformDelay` between `min` and `max` that always produces the
+    /// same sequence of delays for the same `seed`.
+    pub fn with_seed(min: Duration, max: Duration, seed: u64) -> UniformDelay {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        UniformDelay {
+            min,
+            max,
+            // Xorshift gets stuck at zero.
+            state: seed | 1,
+        }
+    }
+}
+
+impl DelayDistribution for UniformDelay {
+    fn next_delay(&mut self) -> Duration {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        let span = (self.max - self.min).as_nanos() as u64;
+        match span.checked_add(1) {
+            Some(range) => self.min + Duration::from_nanos(self.state % range),
+            None => self.min + Duration::from_nanos(self.state),
+        }
+    }
+}
+
+/// The `DelayedReader` struct sleeps for a delay drawn from a `DelayDistribution`
+/// before each read on the handle it wraps.
+///
+/// Wrapping a `TimeoutReader` in a `DelayedReader` shows how an application copes
+/// with a handle that is consistently close to, or past, its timeouts. The delay
+/// happens outside of the wrapped handle, so it is not counted against the handle's
+/// own timeout.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::testing::{DelayedReader, UniformDelay};
+/// use std::fs::File;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let f = File::open("file.txt")?;
+/// let rdr = TimeoutReader::new(f, Duration::new(5, 0));
+/// let delays = UniformDelay::new(Duration::from_millis(10), Duration::from_millis(50));
+/// let mut rdr = DelayedReader::new(rdr, delays);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DelayedReader<R, D> {
+    inner: R,
+    delays: D,
+}
+
+impl<R, D> DelayedReader<R, D>
+where
+    R: Read,
+    D: DelayDistribution,
+{
+    /// Create a new `DelayedReader` delaying each read on `inner` according to
+    /// `delays`.
+    pub fn new(inner: R, delays: D) -> DelayedReader<R, D> {
+        DelayedReader { inner, delays }
+    }
+
+    /// Gets a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps this `DelayedReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R, D> Read for DelayedReader<R, D>
+where
+    R: Read,
+    D: DelayDistribution,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        thread::sleep(self.delays.next_delay());
+        self.inner.read(buf)
+    }
+}
+
+/// The `DelayedWriter` struct sleeps for a delay drawn from a `DelayDistribution`
+/// before each write or flush on the handle it wraps.
+///
+/// This is the writing counterpart of `DelayedReader`.
+#[derive(Debug)]
+pub struct DelayedWriter<W, D> {
+    inner: W,
+    delays: D,
+}
+
+impl<W, D> DelayedWriter<W, D>
+where
+    W: Write,
+    D: DelayDistribution,
+{
+    /// Create a new `DelayedWriter` delaying each write on `inner` according to
+    /// `delays`.
+    pub fn new(inner: W, delays: D) -> DelayedWriter<W, D> {
+        DelayedWriter { inner, delays }
+    }
+
+    /// Gets a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps this `DelayedWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W, D> Write for DelayedWriter<W, D>
+where
+    W: Write,
+    D: DelayDistribution,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        thread::sleep(self.delays.next_delay());
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        thread::sleep(self.delays.next_delay());
+        self.inner.flush()
+    }
+}
+
+/// The magic bytes and version at the start of a saved `Recording`.
+const RECORDING_MAGIC: &[u8; 5] = b"TRWR\x01";
+
+/// A capture of the chunks read from a handle and when each one arrived, made by a
+/// `Recorder`.
+///
+/// Each chunk is stored with the delay since the previous one, in the same form as
+/// the script of a `ScriptedReader`, which is what `replay` plays it back through.
+/// A recording that saw end of file ends with an empty chunk marking when it
+/// arrived.
+///
+/// `write_to` saves a recording in a compact binary form, and `read_from` loads it
+/// back, so that a capture taken from a misbehaving peer in the field can be checked
+/// in next to the test that reproduces it. The format is the magic bytes `TRWR`, a
+/// version byte of 1, then for each chunk its delay in microseconds and its length,
+/// both as LEB128 variable-length integers, followed by its bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    chunks: Vec<(Duration, Vec<u8>)>,
+}
+
+impl Recording {
+    /// Returns the chunks of this recording, each with the delay since the previous
+    /// one.
+    pub fn chunks(&self) -> &[(Duration, Vec<u8>)] {
+        &self.chunks
+    }
+
+    /// Save this recording to `wtr`.
+    pub fn write_to<W: Write>(&self, mut wtr: W) -> Result<()> {
+        let mut out = RECORDING_MAGIC.to_vec();
+        for &(delay, ref chunk) in &self.chunks {
+            write_varint(&mut out, delay.as_micros() as u64);
+            write_varint(&mut out, chunk.len() as u64);
+            out.extend_from_slice(chunk);
+        }
+        wtr.write_all(&out)
+    }
+
+    /// Load a recording saved by `write_to` from `rdr`.
+    ///
+    /// Anything that is not a recording fails with an `io::ErrorKind::InvalidData`
+    /// error.
+    pub fn read_from<R: Read>(mut rdr: R) -> Result<Recording> {
+        let mut data = Vec::new();
+        rdr.read_to_end(&mut data)?;
+        if !data.starts_with(RECORDING_MAGIC) {
+            return Err(Error::new(ErrorKind::InvalidData, "not a recording"));
+        }
+
+        let mut rest = &data[RECORDING_MAGIC.len()..];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let delay = Duration::from_micros(read_varint(&mut rest)?);
+            let len = read_varint(&mut rest)?;
+            if len > rest.len() as u64 {
+                return Err(Error::new(ErrorKind::InvalidData, "truncated recording"));
+            }
+            let (chunk, tail) = rest.split_at(len as usize);
+            chunks.push((delay, chunk.to_vec()));
+            rest = tail;
+        }
+        Ok(Recording { chunks })
+    }
+
+    /// Play this recording back through a `ScriptedReader`, with every delay
+    /// multiplied by `scale`.
+    ///
+    /// A `scale` of 1.0 keeps the original timing, smaller values speed the replay up
+    /// and larger ones slow it down, which is handy for checking how close to its
+    /// timeouts a reader was.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scale` is negative or not finite.
+    pub fn replay(&self, scale: f64) -> Result<ScriptedReader> {
+        ScriptedReader::new(
+            self.chunks
+                .iter()
+                .map(|&(delay, ref chunk)| (delay.mul_f64(scale), chunk)),
+        )
+    }
+}
+
+/// Append `value` to `out` as an unsigned LEB128 integer.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Take an unsigned LEB128 integer off the front of `data`.
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "truncated recording"))
+}
+
+/// The `Recorder` struct captures everything read through it, along with when each
+/// chunk arrived, into a `Recording`.
+///
+/// Put a `Recorder` around a handle, or around a `TimeoutReader`, to capture the
+/// traffic of a peer that trips a timeout bug, then save the `Recording` and replay
+/// it in a test with the original timing. Only successful reads are captured; time
+/// spent in reads that fail, such as timeouts, shows up as the delay before the next
+/// chunk.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutReader;
+/// use timeout_readwrite::testing::{Recorder, Recording};
+/// use std::fs::File;
+/// use std::io::Read;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// // In the field: capture the peer's traffic.
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let mut rdr = Recorder::new(TimeoutReader::new(stream, Duration::new(5, 0)));
+/// let mut reply = Vec::new();
+/// let rslt = rdr.read_to_end(&mut reply);
+/// rdr.into_recording().write_to(File::create("peer.rec")?)?;
+///
+/// // In a test: replay it at the same pace.
+/// let recording = Recording::read_from(File::open("peer.rec")?)?;
+/// let mut rdr = TimeoutReader::new(recording.replay(1.0)?, Duration::new(5, 0));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Recorder<R> {
+    inner: R,
+    last: Instant,
+    recording: Recording,
+}
+
+impl<R> Recorder<R>
+where
+    R: Read,
+{
+    /// Create a new `Recorder` capturing the reads on `inner`, with the first delay
+    /// measured from now.
+    pub fn new(inner: R) -> Recorder<R> {
+        Recorder {
+            inner,
+            last: Instant::now(),
+            recording: Recording::default(),
+        }
+    }
+
+    /// Returns what has been recorded so far.
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Gets a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the wrapped reader.
+    ///
+    /// Reads made directly on the reader are not recorded.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps this `Recorder`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Unwraps this `Recorder`, returning what it recorded.
+    pub fn into_recording(self) -> Recording {
+        self.recording
+    }
+}
+
+impl<R> Read for Recorder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        let now = Instant::now();
+        // Keep only what the saved form can hold, so that recordings round-trip.
+        let delay = Duration::from_micros(now.duration_since(self.last).as_micros() as u64);
+        self.recording.chunks.push((delay, buf[..n].to_vec()));
+        self.last = now;
+        Ok(n)
+    }
+}
+
+impl<R> AsFd for Recorder<R>
+where
+    R: AsFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl fmt::Debug for ScriptedReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptedReader")
+            .field("fd", &self.pipe.as_raw_fd())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::super::{TimeoutReader, TimeoutWriter};
+    use super::*;
+
+    #[test]
+    fn scripted_readiness_drives_timeouts() {
+        let (mock, control) = mock_pollable().unwrap();
+        let mut rdr = TimeoutReader::new(mock, Duration::from_millis(10));
+
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        control.make_readable(b"ping").unwrap();
+        rdr.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+
+        control.close().unwrap();
+        assert_eq!(0, rdr.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn blocked_writes_time_out() {
+        let (mock, mut control) = mock_pollable().unwrap();
+        let mut wtr = TimeoutWriter::new(mock, Duration::from_millis(10));
+
+        wtr.write_all(b"before").unwrap();
+        assert_eq!(b"before", &control.take_written().unwrap()[..]);
+
+        control.block_writes().unwrap();
+        assert_eq!(ErrorKind::TimedOut, wtr.write(b"x").unwrap_err().kind());
+
+        control.unblock_writes().unwrap();
+        wtr.write_all(b"after").unwrap();
+        assert_eq!(b"after", &control.take_written().unwrap()[..]);
+    }
+
+    #[test]
+    fn scripted_reader_keeps_chunk_boundaries() {
+        let script = ScriptedReader::new(vec![
+            (Duration::from_millis(100), &b"slow"[..]),
+            (Duration::from_millis(10), &b"ly"[..]),
+        ])
+        .unwrap();
+        let mut rdr = TimeoutReader::new(script, Duration::from_millis(20));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+        let mut rdr = TimeoutReader::new(rdr.into_inner(), Duration::new(5, 0));
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+        assert_eq!(2, rdr.read(&mut buf).unwrap());
+        assert_eq!(b"ly", &buf[..2]);
+        assert_eq!(0, rdr.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn recording_round_trips_and_replays() {
+        let script = ScriptedReader::new(vec![
+            (Duration::new(0, 0), &b"HEAD"[..]),
+            (Duration::from_millis(100), &b"TAIL"[..]),
+        ])
+        .unwrap();
+        let mut rdr = Recorder::new(TimeoutReader::new(script, Duration::new(5, 0)));
+        let mut data = Vec::new();
+        rdr.read_to_end(&mut data).unwrap();
+        assert_eq!(b"HEADTAIL", &data[..]);
+
+        let recording = rdr.into_recording();
+        let chunks = recording.chunks();
+        assert_eq!(3, chunks.len());
+        assert_eq!(b"TAIL", &chunks[1].1[..]);
+        assert!(chunks[1].0 >= Duration::from_millis(90));
+        assert!(chunks[2].1.is_empty());
+
+        let mut saved = Vec::new();
+        recording.write_to(&mut saved).unwrap();
+        assert_eq!(recording, Recording::read_from(&saved[..]).unwrap());
+        saved.truncate(saved.len() - 1);
+        let err = Recording::read_from(&saved[..]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+
+        // The stall before the tail trips a short timeout, as it did live.
+        let mut rdr = TimeoutReader::new(recording.replay(1.0).unwrap(), Duration::from_millis(20));
+        let mut buf = [0u8; 8];
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+
+        let mut rdr = recording.replay(0.0).unwrap();
+        let start = Instant::now();
+        data.clear();
+        rdr.read_to_end(&mut data).unwrap();
+        assert_eq!(b"HEADTAIL", &data[..]);
+        assert!(start.elapsed() < Duration::from_millis(90));
+    }
+
+    #[test]
+    fn delays_apply_before_each_operation() {
+        let (min, max) = (Duration::from_millis(5), Duration::from_millis(9));
+        let mut delays = UniformDelay::with_seed(min, max, 7);
+        for _ in 0..100 {
+            let delay = delays.next_delay();
+            assert!(delay >= min && delay <= max);
+        }
+
+        let (mock, control) = mock_pollable().unwrap();
+        let mut calls = 0;
+        let mut wtr = DelayedWriter::new(mock, || {
+            calls += 1;
+            Duration::from_millis(20)
+        });
+        let start = Instant::now();
+        wtr.write_all(b"late").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        drop(wtr);
+        assert_eq!(1, calls);
+        assert_eq!(b"late", &control.take_written().unwrap()[..]);
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::io::{Result, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The `ThrottledWriter` struct caps the rate at which data is written to the writer
+/// it wraps.
+///
+/// Slow devices, such as microcontrollers behind a serial link, can lose data when
+/// it arrives faster than they can process it, long before their buffers fill up far
+/// enough to stall writes. `ThrottledWriter` paces writes with a token bucket: the
+/// bucket fills at the configured rate up to its burst size, each byte written takes
+/// a token, and writes sleep until there are tokens for the whole write, or for a
+/// whole burst if the write is larger, so each write still goes out in one piece.
+///
+/// Wrapping a `TimeoutWriter` bounds both how fast data goes out and how long a
+/// stalled device can hold up a write. Time spent waiting for tokens does not count
+/// against the `TimeoutWriter`'s timeout, which only starts once the throttle lets
+/// the data through.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::{ThrottledWriter, TimeoutWriter};
+/// use std::fs::OpenOptions;
+/// use std::io::Write;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let port = OpenOptions::new().write(true).open("/dev/ttyUSB0")?;
+/// let wtr = TimeoutWriter::new(port, Duration::new(1, 0));
+/// // At most 960 bytes per second, roughly 9600 baud.
+/// let mut wtr = ThrottledWriter::new(wtr, 960);
+/// wtr.write_all(b"AT+RESET\r\n")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ThrottledWriter<W> {
+    inner: W,
+    rate: u64,
+    burst: u64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl<W> ThrottledWriter<W>
+where
+    W: Write,
+{
+    /// Create a new `ThrottledWriter` that writes at most `bytes_per_sec` bytes per
+    /// second, with bursts of up to one second's worth of data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn new(inner: W, bytes_per_sec: u64) -> ThrottledWriter<W> {
+        ThrottledWriter::with_burst(inner, bytes_per_sec, bytes_per_sec)
+    }
+
+    /// Create a new `ThrottledWriter` that writes at most `bytes_per_sec` bytes per
+    /// second, with bursts of up to `burst` bytes.
+    ///
+    /// The bucket starts out full, so the first `burst` bytes go out right away.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` or `burst` is zero.
+    pub fn with_burst(inner: W, bytes_per_sec: u64, burst: u64) -> ThrottledWriter<W> {
+        assert!(bytes_per_sec > 0, "rate must be greater than zero");
+        assert!(burst > 0, "burst must be greater than zero");
+        ThrottledWriter {
+            inner,
+            rate: bytes_per_sec,
+            burst,
+            tokens: burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Returns the rate in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Returns the burst size in bytes.
+    pub fn burst(&self) -> u64 {
+        self.burst
+    }
+
+    /// Gets a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps this `ThrottledWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Add the tokens accumulated since the last refill.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled);
+        self.refilled = now;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.burst as f64);
+    }
+
+    /// Wait until there are tokens for all of `len` bytes, or for a whole burst if
+    /// that is less, and return how many of them may be written now.
+    ///
+    /// Waiting for a single token instead would have each write carry only the few
+    /// bytes that trickled in meanwhile, once the burst is used up.
+    fn acquire(&mut self, len: usize) -> usize {
+        self.refill();
+        let wanted = cmp::min(len as u64, self.burst) as f64;
+        if self.tokens < wanted {
+            let missing = wanted - self.tokens;
+            thread::sleep(Duration::from_secs_f64(missing / self.rate as f64));
+            self.refill();
+        }
+        cmp::min(len as u64, cmp::max(self.tokens as u64, 1)) as usize
+    }
+}
+
+impl<W> Write for ThrottledWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let allowed = self.acquire(buf.len());
+        let n = self.inner.write(&buf[..allowed])?;
+        self.tokens -= n as f64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn writes_are_paced_after_burst() {
+        let mut wtr = ThrottledWriter::with_burst(Vec::new(), 1_000, 100);
+
+        let start = Instant::now();
+        wtr.write_all(&[0u8; 100]).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        wtr.write_all(&[1u8; 200]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert_eq!(300, wtr.get_ref().len());
+    }
+
+    #[test]
+    fn paced_writes_are_not_split_up() {
+        struct Counting {
+            writes: usize,
+            written: usize,
+        }
+
+        impl Write for Counting {
+            fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                self.writes += 1;
+                self.written += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let counting = Counting {
+            writes: 0,
+            written: 0,
+        };
+        let mut wtr = ThrottledWriter::with_burst(counting, 10_000, 1_000);
+
+        let start = Instant::now();
+        wtr.write_all(&[0u8; 3_000]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert_eq!(3_000, wtr.get_ref().written);
+        assert!(wtr.get_ref().writes <= 4, "{} writes", wtr.get_ref().writes);
+    }
+}
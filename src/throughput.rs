@@ -0,0 +1,364 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Watching how fast data moves through a wrapper.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The `Stalled` struct is attached to the `io::ErrorKind::TimedOut` error of a
+/// wrapper whose transfer rate fell below the minimum set with
+/// `TimeoutBuilder::min_throughput`.
+///
+/// A stall is reported as a timeout, so code that already handles timeouts treats it
+/// the same way, while `Stalled::of` tells the two apart.
+///
+/// # Examples
+///
+/// ```
+/// use timeout_readwrite::TimeoutBuilder;
+/// use timeout_readwrite::throughput::Stalled;
+/// use std::io::Read;
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+///
+/// # fn foo() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:8080")?;
+/// let mut stream = TimeoutBuilder::new()
+///     .read_timeout(Duration::new(1, 0))
+///     .min_throughput(1024, Duration::new(10, 0))
+///     .stream(stream);
+///
+/// let mut body = Vec::new();
+/// if let Err(e) = stream.read_to_end(&mut body) {
+///     if let Some(stalled) = Stalled::of(&e) {
+///         println!("peer slowed to {:.0} bytes/s", stalled.bytes_per_second());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Stalled {
+    bytes_per_second: f64,
+    minimum: u64,
+}
+
+impl Stalled {
+    /// Returns the rate over the grace period, in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes_per_second
+    }
+
+    /// Returns the minimum rate that was not met, in bytes per second.
+    pub fn minimum(&self) -> u64 {
+        self.minimum
+    }
+
+    /// Returns the `Stalled` attached to `err`, if there is one.
+    pub fn of(err: &Error) -> Option<&Stalled> {
+        err.get_ref().and_then(|e| e.downcast_ref())
+    }
+}
+
+impl fmt::Display for Stalled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "transfer stalled at {:.1} bytes per second, below the minimum of {}",
+            self.bytes_per_second, self.minimum
+        )
+    }
+}
+
+impl error::Error for Stalled {}
+
+/// The rates at which data has moved through a wrapper, from
+/// `TimeoutReader::bytes_per_second` and the like.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Throughput {
+    /// The average rate over the window set with `TimeoutBuilder::measure_throughput`,
+    /// or over the wrapper's lifetime if that is shorter, in bytes per second.
+    pub rolling: f64,
+    /// The average rate since the wrapper was built, in bytes per second.
+    pub lifetime: f64,
+}
+
+/// The bytes moved within a trailing window of time.
+#[derive(Debug)]
+struct Window {
+    length: Duration,
+    started: Instant,
+    samples: VecDeque<(Instant, u64)>,
+    bytes: u64,
+    total: u64,
+}
+
+impl Window {
+    fn new(length: Duration) -> Window {
+        Window {
+            length,
+            started: Instant::now(),
+            samples: VecDeque::new(),
+            bytes: 0,
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, now: Instant, n: u64) {
+        if n > 0 {
+            self.samples.push_back((now, n));
+            self.bytes += n;
+            self.total += n;
+        }
+        self.expire(now);
+    }
+
+    /// Forget the samples that have left the window by `now`.
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, n)) = self.samples.front() {
+            if now.saturating_duration_since(at) < self.length {
+                break;
+            }
+            self.samples.pop_front();
+            self.bytes -= n;
+        }
+    }
+}
+
+/// Measures the rate at which data moves through a wrapper.
+#[derive(Debug)]
+pub(crate) struct ThroughputMeter {
+    window: Mutex<Window>,
+}
+
+impl ThroughputMeter {
+    pub fn new(window: Duration) -> ThroughputMeter {
+        ThroughputMeter {
+            window: Mutex::new(Window::new(window)),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window.lock().unwrap().length
+    }
+
+    /// Returns a meter with the same window that starts measuring now.
+    pub fn fresh(&self) -> ThroughputMeter {
+        ThroughputMeter::new(self.window())
+    }
+
+    /// Note `n` bytes transferred.
+    pub fn record(&self, n: usize) {
+        self.window.lock().unwrap().add(Instant::now(), n as u64);
+    }
+
+    pub fn throughput(&self) -> Throughput {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        window.expire(now);
+        let lifetime = now.duration_since(window.started).as_secs_f64();
+        let rolling = lifetime.min(window.length.as_secs_f64());
+        let rate = |bytes: u64, secs: f64| if secs > 0.0 { bytes as f64 / secs } else { 0.0 };
+        Throughput {
+            rolling: rate(window.bytes, rolling),
+            lifetime: rate(window.total, lifetime),
+        }
+    }
+}
+
+/// Fails a wrapper's operations once its transfer rate has stayed below a minimum
+/// for a whole grace period.
+#[derive(Debug)]
+pub(crate) struct StallDetector {
+    minimum: u64,
+    window: Mutex<Window>,
+}
+
+impl StallDetector {
+    pub fn new(minimum: u64, grace: Duration) -> StallDetector {
+        StallDetector {
+            minimum,
+            window: Mutex::new(Window::new(grace)),
+        }
+    }
+
+    pub fn minimum(&self) -> u64 {
+        self.minimum
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.window.lock().unwrap().length
+    }
+
+    /// Returns a detector with the same settings whose grace period starts now.
+    pub fn fresh(&self) -> StallDetector {
+        StallDetector::new(self.minimum, self.grace())
+    }
+
+    /// Note `n` bytes transferred.
+    pub fn record(&self, n: usize) {
+        self.window.lock().unwrap().add(Instant::now(), n as u64);
+    }
+
+    /// Returns how much longer the transfer can go on without any more data before
+    /// it counts as stalled, or a `Stalled` error if it already does.
+    pub fn remaining(&self) -> Result<Duration> {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        window.expire(now);
+        let needed = (self.minimum as f64 * window.length.as_secs_f64()).ceil() as u64;
+
+        // Nothing is judged until a whole grace period has passed. After that, the
+        // transfer stalls once enough of the samples have left the window.
+        // Counted as time left rather than as an `Instant`, which a long grace period
+        // would overflow.
+        let left_after = |at: Instant| {
+            window
+                .length
+                .saturating_sub(now.saturating_duration_since(at))
+        };
+        let mut left = left_after(window.started);
+        let mut bytes = window.bytes;
+        for &(at, n) in window.samples.iter() {
+            if bytes < needed {
+                break;
+            }
+            bytes -= n;
+            left = cmp::max(left, left_after(at));
+        }
+        if left > Duration::ZERO {
+            return Ok(left);
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            Stalled {
+                bytes_per_second: window.bytes as f64 / window.length.as_secs_f64(),
+                minimum: self.minimum,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::super::TimeoutBuilder;
+    use super::*;
+
+    #[test]
+    fn measures_rolling_and_lifetime_rates() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutBuilder::new()
+            .read_timeout(Duration::new(1, 0))
+            .measure_throughput(Duration::from_millis(100))
+            .reader(left);
+        assert!(TimeoutBuilder::new()
+            .reader(right.try_clone().unwrap())
+            .bytes_per_second()
+            .is_none());
+
+        right.write_all(&[0u8; 1000]).unwrap();
+        let mut buf = [0u8; 1000];
+        rdr.read_exact(&mut buf).unwrap();
+        thread::sleep(Duration::from_millis(150));
+
+        // The burst has left the window, but still counts over the lifetime.
+        let rates = rdr.bytes_per_second().unwrap();
+        assert_eq!(0.0, rates.rolling);
+        assert!(rates.lifetime > 0.0 && rates.lifetime < 1000.0 / 0.15);
+    }
+
+    #[test]
+    fn trickling_peer_is_stalled() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutBuilder::new()
+            .read_timeout(Duration::new(1, 0))
+            .min_throughput(100, Duration::from_millis(200))
+            .reader(left);
+
+        let writer = thread::spawn(move || {
+            // One byte every 50ms is 20 bytes per second, well under the minimum.
+            for _ in 0..20 {
+                if right.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let start = Instant::now();
+        let mut buf = [0u8; 16];
+        let mut total = 0;
+        let err = loop {
+            match rdr.read(&mut buf) {
+                Ok(n) => total += n,
+                Err(e) => break e,
+            }
+        };
+        assert!(total > 0);
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        let stalled = Stalled::of(&err).unwrap();
+        assert_eq!(100, stalled.minimum());
+        assert!(stalled.bytes_per_second() < 100.0);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        drop(rdr);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn stalled_reader_recovers() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutBuilder::new()
+            .read_timeout(Duration::new(1, 0))
+            .min_throughput(100, Duration::from_millis(100))
+            .reader(left);
+
+        let mut buf = [0u8; 64];
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert!(Stalled::of(&err).is_some());
+        assert!(Stalled::of(&rdr.read(&mut buf).unwrap_err()).is_some());
+
+        // Data that is already waiting can still be read, which brings the rate back
+        // over the minimum, so later reads wait as usual.
+        right.write_all(&[0u8; 64]).unwrap();
+        assert_eq!(64, rdr.read(&mut buf).unwrap());
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            right.write_all(b"more").unwrap();
+            right
+        });
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn huge_grace_period_is_allowed() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutBuilder::new()
+            .read_timeout(Duration::from_millis(50))
+            .min_throughput(100, Duration::MAX)
+            .reader(left);
+
+        let mut buf = [0u8; 4];
+        let err = rdr.read(&mut buf).unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+        assert!(Stalled::of(&err).is_none());
+        right.write_all(b"data").unwrap();
+        assert_eq!(4, rdr.read(&mut buf).unwrap());
+    }
+}
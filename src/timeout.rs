@@ -0,0 +1,509 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The generic core behind `TimeoutReader`, `TimeoutWriter` and `TimeoutStream`.
+
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::budget::{self, TimeBudget};
+use super::handle::{BufferedHandle, TryCloneHandle};
+use super::observe::Direction;
+use super::partial::{self, Transfer, Utf8Tail};
+use super::sockopt::SavedSocketTimeouts;
+use super::stats::IoStats;
+use super::throughput::Throughput;
+use super::tty::SavedTermios;
+use super::utils;
+use super::wait::Waiter;
+
+/// The direction of a `TimeoutReader`, whose timeout applies to reads.
+#[derive(Debug)]
+pub enum Reading {}
+
+/// The direction of a `TimeoutWriter`, whose timeout applies to writes.
+#[derive(Debug)]
+pub enum Writing {}
+
+/// The direction of a `TimeoutStream`, which has a timeout for reads and another for
+/// writes.
+#[derive(Debug)]
+pub enum Duplex {}
+
+/// The `Timeout` struct adds timeouts to a handle, waiting until the handle is ready
+/// before each operation.
+///
+/// `TimeoutReader`, `TimeoutWriter` and `TimeoutStream` are this struct for the
+/// directions `Reading`, `Writing` and `Duplex`. What they have in common, such as
+/// budgets, statistics and the helpers that bound a whole transfer, is implemented
+/// here once; see each of them for the rest.
+pub struct Timeout<H, D> {
+    pub(crate) read_timeout: Option<c_int>,
+    pub(crate) write_timeout: Option<c_int>,
+    pub(crate) flush_timeout: Option<c_int>,
+    pub(crate) termios: Option<Arc<SavedTermios>>,
+    pub(crate) sockopts: Option<Arc<SavedSocketTimeouts>>,
+    pub(crate) sync_user_timeout: bool,
+    pub(crate) waiter: Waiter,
+    /// Set by `set_check_buffered`, since `Read` cannot require `BufferedHandle`.
+    pub(crate) buffered: Option<fn(&H) -> bool>,
+    pub(crate) handle: H,
+    pub(crate) direction: PhantomData<D>,
+}
+
+impl<H, D> Timeout<H, D> {
+    /// Wraps `handle` without any timeouts, for the constructors to fill in.
+    pub(crate) fn bare(handle: H) -> Timeout<H, D> {
+        Timeout {
+            read_timeout: None,
+            write_timeout: None,
+            flush_timeout: None,
+            termios: None,
+            sockopts: None,
+            sync_user_timeout: false,
+            waiter: Waiter::default(),
+            buffered: None,
+            handle,
+            direction: PhantomData,
+        }
+    }
+
+    /// Binds this wrapper to a shared `TimeBudget`, or unbinds it with `None`.
+    ///
+    /// While bound, each wait is limited to the time left in the budget, and every
+    /// operation fails with an `io::ErrorKind::TimedOut` variant as the value of
+    /// `io::Error` once the budget has run out. Clones of this wrapper made
+    /// afterwards are bound to the same budget.
+    ///
+    /// With `TimeoutStrategy::SocketOption`, the kernel enforces the socket timeouts
+    /// of a `TimeoutStream` on its own, so the budget is only checked before each
+    /// operation.
+    pub fn set_budget(&mut self, budget: Option<Arc<TimeBudget>>) {
+        self.waiter.budget = budget;
+    }
+
+    /// Returns the `TimeBudget` this wrapper is bound to, if any.
+    pub fn budget(&self) -> Option<&Arc<TimeBudget>> {
+        self.waiter.budget.as_ref()
+    }
+
+    /// Returns the budget slot of this wrapper, for helpers that tighten it.
+    pub(crate) fn budget_slot(&mut self) -> &mut Option<Arc<TimeBudget>> {
+        &mut self.waiter.budget
+    }
+
+    /// Returns the statistics of this wrapper and its clones so far.
+    pub fn stats(&self) -> IoStats {
+        self.waiter.counters.snapshot()
+    }
+
+    /// Returns the rates at which data has moved through this wrapper and its clones,
+    /// or `None` unless it was built with `TimeoutBuilder::measure_throughput`.
+    pub fn bytes_per_second(&self) -> Option<Throughput> {
+        self.waiter.meter.as_ref().map(|meter| meter.throughput())
+    }
+
+    /// Replaces the wait state, for `TimeoutBuilder`.
+    pub(crate) fn set_waiter(&mut self, waiter: Waiter) {
+        self.waiter = waiter;
+    }
+
+    /// Gets a reference to the underlying handle.
+    pub fn get_ref(&self) -> &H {
+        &self.handle
+    }
+
+    /// Gets a mutable reference to the underlying handle.
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.handle
+    }
+
+    /// Unwraps this wrapper, returning the underlying handle.
+    ///
+    /// If this is the last clone of a `TimeoutReader` created with `new_termios`, the
+    /// original terminal settings are restored, and likewise the original socket
+    /// timeouts of a `TimeoutStream` using `TimeoutStrategy::SocketOption`.
+    pub fn into_inner(self) -> H {
+        self.handle
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    H: BufferedHandle,
+{
+    /// Sets whether `read` checks `BufferedHandle::has_buffered_data` before waiting
+    /// for the descriptor. This is off by default.
+    ///
+    /// Data a handle has buffered in user space, such as decrypted plaintext in a TLS
+    /// stream, can be read without waiting, but the descriptor has nothing more to offer
+    /// for it, so a `read` that waited first could time out even though data was ready
+    /// all along. With this on, `read` goes straight to the handle whenever it reports
+    /// buffered data. `BufRead` on a `TimeoutReader` always checks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::{BufferedHandle, TimeoutReader};
+    /// use std::io::{BufReader, Read};
+    /// use std::net::TcpStream;
+    /// use std::os::fd::{AsFd, BorrowedFd};
+    /// use std::time::Duration;
+    ///
+    /// struct Framed(BufReader<TcpStream>);
+    /// # impl Read for Framed {
+    /// #     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+    /// # }
+    /// # impl AsFd for Framed {
+    /// #     fn as_fd(&self) -> BorrowedFd<'_> { self.0.get_ref().as_fd() }
+    /// # }
+    ///
+    /// impl BufferedHandle for Framed {
+    ///     fn has_buffered_data(&self) -> bool {
+    ///         !self.0.buffer().is_empty()
+    ///     }
+    /// }
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = Framed(BufReader::new(TcpStream::connect("127.0.0.1:8080")?));
+    /// let mut rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+    /// rdr.set_check_buffered(true);
+    ///
+    /// let mut buf = [0u8; 1024];
+    /// rdr.read(&mut buf)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_check_buffered(&mut self, enabled: bool) {
+        self.buffered = if enabled {
+            Some(<H as BufferedHandle>::has_buffered_data)
+        } else {
+            None
+        };
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    H: AsFd,
+{
+    /// Returns whether priority data, such as TCP urgent data, is waiting to be read,
+    /// without blocking.
+    ///
+    /// A wrapper built with `TimeoutBuilder::priority_events` also stops waiting when
+    /// priority data arrives, and this tells the two kinds of readiness apart.
+    pub fn priority_pending(&self) -> Result<bool> {
+        utils::is_ready(&self.handle, PollFlags::POLLPRI)
+    }
+
+    /// Shuts down the read half, the write half, or both halves of the socket.
+    ///
+    /// This is `TcpStream::shutdown`, for any socket handle. It affects every clone of
+    /// the socket, and fails with the error from the operating system if the handle is
+    /// not a socket.
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        utils::shutdown_socket(&self.handle, how)
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    H: Read + AsFd,
+{
+    /// Read into `buf` only if data is already waiting, whatever the timeout.
+    ///
+    /// Returns an `io::ErrorKind::WouldBlock` error, without reading, if the handle
+    /// has nothing to read yet. End of file counts as something to read, and is
+    /// reported as `Ok(0)` as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::io::ErrorKind;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+    ///
+    /// let mut buf = [0u8; 64];
+    /// match rdr.try_read(&mut buf) {
+    ///     Ok(n) => println!("read {} bytes", n),
+    ///     Err(ref e) if e.kind() == ErrorKind::WouldBlock => println!("nothing yet"),
+    ///     Err(e) => return Err(e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !utils::is_ready(&self.handle, PollFlags::POLLIN)? {
+            return Err(utils::would_block());
+        }
+        let start = self.waiter.start();
+        let rslt = self.handle.read(buf);
+        self.waiter.record(Direction::Read, start, rslt)
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    H: Write + AsFd,
+{
+    /// Write from `buf` only if the handle can accept data right away, whatever the
+    /// timeout.
+    ///
+    /// Returns an `io::ErrorKind::WouldBlock` error, without writing, if the handle is
+    /// not writable yet. As with `write`, a handle in blocking mode can still block if
+    /// `buf` is larger than the room it has, so keep `buf` small or put the handle in
+    /// nonblocking mode to be sure.
+    pub fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !utils::is_ready(&self.handle, PollFlags::POLLOUT)? {
+            return Err(utils::would_block());
+        }
+        let start = self.waiter.start();
+        let rslt = self.handle.write(buf);
+        self.waiter.record(Direction::Write, start, rslt)
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    Timeout<H, D>: Read,
+{
+    /// Read exactly enough bytes to fill `buf`, giving up once `timeout` has elapsed
+    /// across all the reads it takes, and return how much of `timeout` was left.
+    ///
+    /// Each read still waits at most the wrapper's own timeout. The time left over can
+    /// be spent on a follow-up operation, for instance by passing it to the next
+    /// `read_exact_within`. If the wrapper is bound to a `TimeBudget` that runs out
+    /// sooner, the budget wins.
+    ///
+    /// If it times out, the error carries a `PartialTransfer` with the number of bytes
+    /// read so far, which are left at the start of `buf`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut rdr = TimeoutReader::new(stream, Duration::new(1, 0));
+    ///
+    /// let mut header = [0u8; 8];
+    /// let left = rdr.read_exact_within(&mut header, Duration::new(5, 0))?;
+    /// let mut body = vec![0u8; header[0] as usize];
+    /// rdr.read_exact_within(&mut body, left)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_exact_within(&mut self, buf: &mut [u8], timeout: Duration) -> Result<Duration> {
+        budget::run_within(self, Timeout::budget_slot, timeout, |wrapper| {
+            partial::read_exact(wrapper, buf)
+        })
+    }
+
+    /// Read exactly enough bytes to fill `buf` within `timeout`, like
+    /// `read_exact_within`, but report running out of time as a partial transfer
+    /// rather than an error.
+    ///
+    /// This suits callers that take whatever arrives in time, such as when draining
+    /// diagnostic output. On `Transfer::Partial { n }`, the data read is in the first
+    /// `n` bytes of `buf`. Errors other than timeouts are still returned as errors.
+    pub fn read_exact_partial(&mut self, buf: &mut [u8], timeout: Duration) -> Result<Transfer> {
+        partial::transfer(self.read_exact_within(buf, timeout))
+    }
+
+    /// Read all bytes until end of file onto the end of `buf`, giving up once
+    /// `timeout` has elapsed altogether, and return how many bytes were read.
+    ///
+    /// `read_to_end` only limits each read, so a source that trickles data in just
+    /// under the timeout can keep it going forever. This caps the whole of it. If time
+    /// runs out, the bytes collected so far are left in `buf`, and the error carries a
+    /// `PartialTransfer` with their number.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::process;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let child = process::Command::new("ls")
+    ///     .stdout(process::Stdio::piped())
+    ///     .spawn()?;
+    /// let mut rdr = TimeoutReader::new(child.stdout.unwrap(), Duration::new(1, 0));
+    ///
+    /// let mut output = Vec::new();
+    /// rdr.read_to_end_within(&mut output, Duration::new(10, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_to_end_within(&mut self, buf: &mut Vec<u8>, timeout: Duration) -> Result<usize> {
+        let mut n = 0;
+        budget::run_within(self, Timeout::budget_slot, timeout, |wrapper| {
+            n = partial::read_to_end(wrapper, buf)?;
+            Ok(())
+        })?;
+        Ok(n)
+    }
+
+    /// Read all bytes until end of file as UTF-8 text onto the end of `buf`, giving up
+    /// once `timeout` has elapsed altogether, and return the length of the text read.
+    ///
+    /// This works like `read_to_end_within`. If time runs out, the text read so far is
+    /// still appended to `buf`, and `tail` decides what happens to a character that
+    /// was cut off part way through, or to any invalid UTF-8.
+    pub fn read_to_string_within(
+        &mut self,
+        buf: &mut String,
+        timeout: Duration,
+        tail: Utf8Tail,
+    ) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let rslt = self.read_to_end_within(&mut bytes, timeout);
+        partial::append_utf8(buf, &bytes, rslt, tail)
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    Timeout<H, D>: Write,
+{
+    /// Write all of `buf`, giving up once `timeout` has elapsed across all the writes
+    /// it takes, and return how much of `timeout` was left.
+    ///
+    /// This is the writing counterpart of `read_exact_within`, and likewise attaches a
+    /// `PartialTransfer` to a timeout, counting the bytes written.
+    pub fn write_all_within(&mut self, buf: &[u8], timeout: Duration) -> Result<Duration> {
+        budget::run_within(self, Timeout::budget_slot, timeout, |wrapper| {
+            partial::write_all(wrapper, buf)
+        })
+    }
+
+    /// Write all of `buf` within `timeout`, like `write_all_within`, but report running
+    /// out of time as a partial transfer rather than an error.
+    pub fn write_all_partial(&mut self, buf: &[u8], timeout: Duration) -> Result<Transfer> {
+        partial::transfer(self.write_all_within(buf, timeout))
+    }
+}
+
+impl<H, D> Timeout<H, D>
+where
+    H: TryCloneHandle,
+{
+    /// Creates a new wrapper around a duplicate of the underlying handle, with the same
+    /// timeouts as this one.
+    ///
+    /// A `TimeoutStream` using `TimeoutStrategy::SocketOption` shares the socket
+    /// timeouts with its duplicate, since socket options belong to the socket rather
+    /// than to the descriptor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutReader;
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let rdr = TimeoutReader::new(stream, Duration::new(5, 0));
+    /// let other = rdr.try_clone()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_clone(&self) -> Result<Timeout<H, D>> {
+        Ok(self.with_handle(self.handle.try_clone_handle()?))
+    }
+}
+
+impl<H, D> Timeout<H, D> {
+    /// Returns a wrapper around `handle` with the same settings as this one.
+    fn with_handle(&self, handle: H) -> Timeout<H, D> {
+        Timeout {
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            flush_timeout: self.flush_timeout,
+            termios: self.termios.clone(),
+            sockopts: self.sockopts.clone(),
+            sync_user_timeout: self.sync_user_timeout,
+            waiter: self.waiter.clone(),
+            buffered: self.buffered,
+            handle,
+            direction: PhantomData,
+        }
+    }
+}
+
+impl<H, D> Clone for Timeout<H, D>
+where
+    H: Clone,
+{
+    fn clone(&self) -> Timeout<H, D> {
+        self.with_handle(self.handle.clone())
+    }
+}
+
+impl<H, D> Seek for Timeout<H, D>
+where
+    H: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.handle.seek(pos)
+    }
+}
+
+impl<H, D> AsFd for Timeout<H, D>
+where
+    H: AsFd,
+{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.handle.as_fd()
+    }
+}
+
+impl<H, D> AsRawFd for Timeout<H, D>
+where
+    H: AsFd,
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.handle.as_fd().as_raw_fd()
+    }
+}
+
+impl<H, D> IntoRawFd for Timeout<H, D>
+where
+    H: IntoRawFd,
+{
+    fn into_raw_fd(self) -> RawFd {
+        self.into_inner().into_raw_fd()
+    }
+}
+
+impl<H, D> From<Timeout<H, D>> for OwnedFd
+where
+    H: Into<OwnedFd>,
+{
+    /// Unwraps the wrapper into its descriptor, restoring any terminal settings or
+    /// socket timeouts the same way `into_inner` does.
+    fn from(wrapper: Timeout<H, D>) -> OwnedFd {
+        wrapper.into_inner().into()
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::libc::{self, c_int};
+use nix::sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices, Termios};
+use std::io::{IsTerminal, Result};
+use std::os::fd::{AsFd, OwnedFd};
+
+/// The longest timeout `VTIME` can express, in milliseconds.
+const MAX_VTIME_MS: c_int = 255 * 100;
+
+/// Remembers the terminal settings a device had before a wrapper took it over, and
+/// puts them back when dropped.
+///
+/// The guard keeps its own duplicate of the descriptor. Terminal settings belong to
+/// the device rather than to the descriptor, so restoring through the duplicate
+/// affects the handle the wrapper gave back, even after the wrapper has been
+/// unwrapped.
+///
+/// The settings are kept as a plain `libc::termios`, since nix's `Termios` is not
+/// `Sync` and would keep wrappers from moving between threads.
+pub struct SavedTermios {
+    fd: OwnedFd,
+    original: libc::termios,
+    timeout: c_int,
+}
+
+impl SavedTermios {
+    /// Switch `fd` to non-canonical mode with `VMIN` and `VTIME` configured so that a
+    /// `read` returns as soon as one byte is available, or returns nothing once
+    /// `timeout` elapses.
+    ///
+    /// Returns `None`, leaving the settings alone, if `fd` is not a terminal, there is
+    /// no timeout, or the timeout is too long for `VTIME` to express.
+    pub fn apply(fd: &impl AsFd, timeout: Option<c_int>) -> Result<Option<SavedTermios>> {
+        if !fd.as_fd().is_terminal() {
+            return Ok(None);
+        }
+
+        let ms = match timeout {
+            None => return Ok(None),
+            Some(ms) if ms > MAX_VTIME_MS => return Ok(None),
+            Some(ms) => ms,
+        };
+        let (vmin, vtime) = match ms {
+            // A zero timeout makes `read` return whatever is already there.
+            0 => (0, 0),
+            // VTIME counts tenths of a second; round up so the wait is never shorter
+            // than requested.
+            ms => (0, ((ms + 99) / 100) as u8),
+        };
+
+        let fd = fd.as_fd().try_clone_to_owned()?;
+        let original = termios::tcgetattr(&fd)?;
+
+        let mut settings = original.clone();
+        let original = libc::termios::from(original);
+        settings.local_flags.remove(LocalFlags::ICANON);
+        settings.control_chars[SpecialCharacterIndices::VMIN as usize] = vmin;
+        settings.control_chars[SpecialCharacterIndices::VTIME as usize] = vtime;
+        termios::tcsetattr(&fd, SetArg::TCSANOW, &settings)?;
+
+        Ok(Some(SavedTermios {
+            fd,
+            original,
+            timeout: ms,
+        }))
+    }
+
+    /// Returns the timeout `VTIME` was set from, in milliseconds.
+    pub fn timeout(&self) -> c_int {
+        self.timeout
+    }
+}
+
+impl Drop for SavedTermios {
+    fn drop(&mut self) {
+        let original = Termios::from(self.original);
+        let _ = termios::tcsetattr(&self.fd, SetArg::TCSANOW, &original);
+    }
+}
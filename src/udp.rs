@@ -0,0 +1,254 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use std::fmt;
+use std::io::Result;
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+use super::utils;
+
+/// The `TimeoutUdpSocket` struct adds receive and send timeouts to a `UdpSocket`.
+///
+/// Datagram sockets do not implement `Read` or `Write`, so they cannot be wrapped in
+/// a `TimeoutReader` or `TimeoutWriter`. A `TimeoutUdpSocket` instead provides the
+/// datagram operations of `UdpSocket`, each of which waits until the socket is ready,
+/// up until an optional timeout, before actually performing the operation.
+///
+/// The `recv`, `recv_from`, `send`, and `send_to` methods use the timeouts the
+/// socket was created with, while the `*_within` variants take the timeout for that
+/// single call as an argument.
+///
+/// If any operation times out, the method called will return an
+/// `io::ErrorKind::TimedOut` variant as the value of `io::Error`.
+pub struct TimeoutUdpSocket {
+    read_timeout: Option<c_int>,
+    write_timeout: Option<c_int>,
+    socket: UdpSocket,
+}
+
+impl fmt::Debug for TimeoutUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutUdpSocket")
+            .field("socket", &self.socket)
+            .field(
+                "read_timeout",
+                &self.read_timeout.map(utils::ms_to_duration),
+            )
+            .field(
+                "write_timeout",
+                &self.write_timeout.map(utils::ms_to_duration),
+            )
+            .finish()
+    }
+}
+
+impl TimeoutUdpSocket {
+    /// Create a new `TimeoutUdpSocket` with optional receive and send timeouts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutUdpSocket;
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let socket = UdpSocket::bind("127.0.0.1:34254")?;
+    /// let socket = TimeoutUdpSocket::new(socket, Duration::new(5, 0), None);
+    ///
+    /// let mut buf = [0; 1500];
+    /// let (amt, src) = socket.recv_from(&mut buf)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<R, W>(socket: UdpSocket, read_timeout: R, write_timeout: W) -> TimeoutUdpSocket
+    where
+        R: Into<Option<Duration>>,
+        W: Into<Option<Duration>>,
+    {
+        TimeoutUdpSocket {
+            read_timeout: utils::initial_timeout(read_timeout.into()),
+            write_timeout: utils::initial_timeout(write_timeout.into()),
+            socket,
+        }
+    }
+
+    /// Receives a single datagram from the connected peer. See `UdpSocket::recv`.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        utils::wait_until_ready(self.read_timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.recv(buf)
+    }
+
+    /// Receives a single datagram, returning the number of bytes read and the address
+    /// it came from. See `UdpSocket::recv_from`.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        utils::wait_until_ready(self.read_timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.recv_from(buf)
+    }
+
+    /// Receives a single datagram from the connected peer without removing it from
+    /// the queue. See `UdpSocket::peek`.
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        utils::wait_until_ready(self.read_timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.peek(buf)
+    }
+
+    /// Receives a single datagram without removing it from the queue, returning the
+    /// number of bytes read and the address it came from. See `UdpSocket::peek_from`.
+    pub fn peek_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        utils::wait_until_ready(self.read_timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.peek_from(buf)
+    }
+
+    /// Sends a datagram to the connected peer. See `UdpSocket::send`.
+    pub fn send(&self, buf: &[u8]) -> Result<usize> {
+        utils::wait_until_ready(self.write_timeout, &self.socket, PollFlags::POLLOUT)?;
+        self.socket.send(buf)
+    }
+
+    /// Sends a datagram to the given address. See `UdpSocket::send_to`.
+    pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
+        utils::wait_until_ready(self.write_timeout, &self.socket, PollFlags::POLLOUT)?;
+        self.socket.send_to(buf, addr)
+    }
+
+    /// Receives a single datagram from the connected peer, waiting at most `timeout`
+    /// for one to arrive.
+    pub fn recv_within<T: Into<Option<Duration>>>(
+        &self,
+        buf: &mut [u8],
+        timeout: T,
+    ) -> Result<usize> {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.recv(buf)
+    }
+
+    /// Receives a single datagram, waiting at most `timeout` for one to arrive.
+    pub fn recv_from_within<T: Into<Option<Duration>>>(
+        &self,
+        buf: &mut [u8],
+        timeout: T,
+    ) -> Result<(usize, SocketAddr)> {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, &self.socket, PollFlags::POLLIN)?;
+        self.socket.recv_from(buf)
+    }
+
+    /// Sends a datagram to the connected peer, waiting at most `timeout` for the socket
+    /// to become writable.
+    pub fn send_within<T: Into<Option<Duration>>>(&self, buf: &[u8], timeout: T) -> Result<usize> {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, &self.socket, PollFlags::POLLOUT)?;
+        self.socket.send(buf)
+    }
+
+    /// Sends a datagram to the given address, waiting at most `timeout` for the socket
+    /// to become writable.
+    pub fn send_to_within<A, T>(&self, buf: &[u8], addr: A, timeout: T) -> Result<usize>
+    where
+        A: ToSocketAddrs,
+        T: Into<Option<Duration>>,
+    {
+        let timeout = timeout.into().map(utils::timeout_ms);
+        utils::wait_until_ready(timeout, &self.socket, PollFlags::POLLOUT)?;
+        self.socket.send_to(buf, addr)
+    }
+
+    /// Shuts down receiving, sending, or both on the socket.
+    ///
+    /// `UdpSocket` has no `shutdown` of its own. Shutting down the receiving side
+    /// wakes a `recv` waiting in another thread, which then sees an empty datagram.
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        utils::shutdown_socket(&self.socket, how)
+    }
+
+    /// Gets a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Unwraps this `TimeoutUdpSocket`, returning the underlying socket.
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+impl AsFd for TimeoutUdpSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn datagram_round_trip() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = TimeoutUdpSocket::new(server, Duration::new(5, 0), None);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .connect(server.get_ref().local_addr().unwrap())
+            .unwrap();
+        let client = TimeoutUdpSocket::new(client, None, Duration::new(5, 0));
+
+        client.send(b"ping").unwrap();
+
+        let mut buf = [0u8; 16];
+        let (n, _) = server.peek_from(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf[..n]);
+
+        let (n, src) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf[..n]);
+        assert_eq!(client.get_ref().local_addr().unwrap(), src);
+    }
+
+    #[test]
+    fn recv_within_times_out() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = TimeoutUdpSocket::new(socket, None, None);
+
+        let mut buf = [0u8; 16];
+        let err = socket
+            .recv_from_within(&mut buf, Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn shutdown_wakes_a_waiting_recv() {
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Instant;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(socket.local_addr().unwrap()).unwrap();
+        let socket = Arc::new(TimeoutUdpSocket::new(socket, Duration::new(5, 0), None));
+
+        let start = Instant::now();
+        let receiver = {
+            let socket = Arc::clone(&socket);
+            thread::spawn(move || socket.recv(&mut [0u8; 16]))
+        };
+        thread::sleep(Duration::from_millis(50));
+        socket.shutdown(Shutdown::Read).unwrap();
+        assert_eq!(0, receiver.join().unwrap().unwrap());
+        assert!(start.elapsed() < Duration::new(1, 0));
+    }
+}
@@ -8,13 +8,20 @@
 
 use nix::libc::c_int;
 use nix::poll;
+use nix::sys::socket;
+use std::cell::Cell;
 use std::cmp;
 use std::convert::TryFrom;
+use std::env;
 use std::io::{Error, ErrorKind, Result};
-use std::os::fd::AsFd;
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd};
 use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+use super::config::TIMEOUT_SCALE_VAR;
+
 /// Convert from a duration into milliseconds as the c_int type that poll expects.
 /// If the duration exceeds the number of milliseconds that can fit into a c_int,
 /// saturate the time to the max_value of c_int.
@@ -25,8 +32,131 @@ pub fn duration_to_ms(duration: Duration) -> c_int {
     secs.saturating_mul(1_000).saturating_add(nanos / 1_000_000)
 }
 
+/// Convert a duration into milliseconds like `duration_to_ms`, rounding up, so that
+/// a wait of that long never ends before the duration has passed.
+pub fn duration_to_ms_ceil(duration: Duration) -> c_int {
+    duration_to_ms(duration.saturating_add(Duration::from_nanos(999_999)))
+}
+
+/// Convert from the milliseconds stored by the wrappers back into a duration.
+pub fn ms_to_duration(ms: c_int) -> Duration {
+    Duration::from_millis(cmp::max(ms, 0) as u64)
+}
+
+/// The bits of the `f64` scale factor, or zero if it has not been determined yet.
+static SCALE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the factor every configured timeout is multiplied by, reading it from the
+/// environment the first time.
+pub fn timeout_scale() -> f64 {
+    match SCALE.load(Ordering::Relaxed) {
+        0 => {
+            let scale = env::var(TIMEOUT_SCALE_VAR)
+                .ok()
+                .and_then(|scale| scale.trim().parse().ok())
+                .filter(|scale| valid_scale(*scale))
+                .unwrap_or(1.0);
+            // Another thread may have set the factor in the meantime; it wins.
+            let _ = SCALE.compare_exchange(
+                0,
+                f64::to_bits(scale),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+            f64::from_bits(SCALE.load(Ordering::Relaxed))
+        }
+        bits => f64::from_bits(bits),
+    }
+}
+
+/// Replace the timeout scale factor.
+pub fn set_timeout_scale(scale: f64) {
+    assert!(
+        valid_scale(scale),
+        "timeout scale must be a positive, finite number"
+    );
+    SCALE.store(f64::to_bits(scale), Ordering::Relaxed);
+}
+
+fn valid_scale(scale: f64) -> bool {
+    scale.is_finite() && scale > 0.0
+}
+
+/// Convert a timeout configured by the caller into milliseconds, multiplied by the
+/// timeout scale factor.
+pub fn timeout_ms(timeout: Duration) -> c_int {
+    scaled_ms(timeout, timeout_scale())
+}
+
+/// Convert `timeout` multiplied by `scale` into milliseconds.
+pub fn scaled_ms(timeout: Duration, scale: f64) -> c_int {
+    if scale == 1.0 || timeout == Duration::new(0, 0) {
+        return duration_to_ms(timeout);
+    }
+    let scaled =
+        Duration::try_from_secs_f64(timeout.as_secs_f64() * scale).unwrap_or(Duration::MAX);
+    // Never scale a timeout all the way down to zero, which means not to wait at all.
+    cmp::max(duration_to_ms(scaled), 1)
+}
+
+thread_local! {
+    /// The tightest timeout allowed for wrappers created on this thread, installed
+    /// by `TimeoutOverride`.
+    static OVERRIDE: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+/// Returns the timeout override for this thread.
+pub fn timeout_override() -> Option<Duration> {
+    OVERRIDE.with(Cell::get)
+}
+
+/// Replace the timeout override for this thread, returning the previous one.
+pub fn replace_timeout_override(timeout: Option<Duration>) -> Option<Duration> {
+    OVERRIDE.with(|cell| cell.replace(timeout))
+}
+
+/// Convert the timeout a wrapper is being created with, tightened to the override
+/// for this thread if there is one.
+pub fn initial_timeout(timeout: Option<Duration>) -> Option<c_int> {
+    let timeout = match (timeout, timeout_override()) {
+        (Some(timeout), Some(limit)) => Some(cmp::min(timeout, limit)),
+        (timeout, limit) => timeout.or(limit),
+    };
+    timeout.map(timeout_ms)
+}
+
+/// Validate a timeout the same way `std::net` does, rejecting a zero duration.
+pub fn checked_timeout(dur: Option<Duration>) -> Result<Option<c_int>> {
+    match dur {
+        Some(dur) if dur == Duration::new(0, 0) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        )),
+        dur => Ok(dur.map(timeout_ms)),
+    }
+}
+
+/// Shut down both directions of `fd` if it is a socket, so that the peer and anyone
+/// blocked on it see the connection end. Other descriptors are left alone.
+pub fn shutdown(fd: &impl AsFd) {
+    let _ = shutdown_socket(fd, Shutdown::Both);
+}
+
+/// Shut down the `how` direction of the socket `fd`, failing with the error from the
+/// operating system if `fd` is not a socket.
+pub fn shutdown_socket(fd: &impl AsFd, how: Shutdown) -> Result<()> {
+    let how = match how {
+        Shutdown::Read => socket::Shutdown::Read,
+        Shutdown::Write => socket::Shutdown::Write,
+        Shutdown::Both => socket::Shutdown::Both,
+    };
+    socket::shutdown(fd.as_fd().as_raw_fd(), how)?;
+    Ok(())
+}
+
 /// Wait until `to_fd` receives the poll event from `events`, up to `timeout` length
 /// of time.
+#[allow(clippy::io_other_error)]
 pub fn wait_until_ready(
     timeout: Option<c_int>,
     fd: &impl AsFd,
@@ -49,3 +179,37 @@ pub fn wait_until_ready(
     }
     Ok(())
 }
+
+/// Check, without blocking, whether `fd` has any of the poll events in `events`
+/// pending.
+pub fn is_ready(fd: &impl AsFd, events: poll::PollFlags) -> Result<bool> {
+    match wait_until_ready(Some(0), fd, events) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == ErrorKind::TimedOut => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The error for an operation that would have had to wait.
+pub fn would_block() -> Error {
+    Error::new(ErrorKind::WouldBlock, "handle is not ready")
+}
+
+/// Wait until at least one of `fds` receives one of its poll events, up to `timeout`
+/// length of time, or indefinitely if `timeout` is `None`. The events each entry
+/// received are left in its `revents`.
+pub fn wait_any(timeout: Option<c_int>, fds: &mut [poll::PollFd]) -> Result<()> {
+    let timeout = match timeout {
+        Some(timeout) => poll::PollTimeout::try_from(timeout).map_err(Error::other)?,
+        None => poll::PollTimeout::NONE,
+    };
+
+    let retval = poll::poll(fds, timeout).map_err(Error::other)?;
+    if retval == 0 {
+        return Err(Error::new(
+            ErrorKind::TimedOut,
+            "timed out waiting for fd to be ready",
+        ));
+    }
+    Ok(())
+}
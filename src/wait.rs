@@ -0,0 +1,751 @@
+// Copyright 2017 Jonathan Creekmore
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nix::errno::Errno;
+use nix::libc::c_int;
+use nix::poll::PollFlags;
+use nix::sys::socket::{self, MsgFlags};
+use nix::unistd;
+use std::cmp;
+use std::io::{Error, ErrorKind, Result};
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::budget::TimeBudget;
+use super::config::RetryPolicy;
+use super::metrics::{MetricsSink, Observation, Outcome};
+use super::observe::{Direction, WaitObserver};
+#[cfg(feature = "reactor")]
+use super::reactor;
+use super::stats::IoStats;
+use super::throughput::{StallDetector, ThroughputMeter};
+use super::utils;
+
+/// Everything besides the timeout itself that a wrapper consults when it waits for
+/// its handle to become ready.
+///
+/// Clones of a wrapper share the same state.
+#[derive(Clone, Default)]
+pub struct Waiter {
+    pub budget: Option<Arc<TimeBudget>>,
+    pub observer: Option<Arc<dyn WaitObserver>>,
+    pub name: Option<Arc<str>>,
+    pub counters: Arc<Counters>,
+    pub sink: Option<Arc<dyn MetricsSink>>,
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<Arc<Chaos>>,
+    pub watchdog: Option<Arc<Watchdog>>,
+    pub close_on_timeout: bool,
+    pub priority: bool,
+    pub read_events: Option<PollFlags>,
+    pub write_events: Option<PollFlags>,
+    pub heartbeat: Option<Arc<Heartbeat>>,
+    pub stall: Option<Arc<StallDetector>>,
+    pub meter: Option<Arc<ThroughputMeter>>,
+    pub wait_cap: Option<Duration>,
+    pub retry: RetryPolicy,
+    #[cfg(feature = "reactor")]
+    pub reactor: Option<Arc<reactor::Shared>>,
+}
+
+/// Shuts the handle down once it has gone without successful I/O for too long.
+pub struct Watchdog {
+    idle: Duration,
+    on_idle: Arc<dyn Fn() + Send + Sync>,
+    last_activity: Mutex<Instant>,
+    fired: AtomicBool,
+}
+
+impl Watchdog {
+    pub fn new(idle: Duration, on_idle: Arc<dyn Fn() + Send + Sync>) -> Watchdog {
+        Watchdog {
+            idle,
+            on_idle,
+            last_activity: Mutex::new(Instant::now()),
+            fired: AtomicBool::new(false),
+        }
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    /// Returns a watchdog with the same settings whose idle period starts now.
+    fn fresh(&self) -> Watchdog {
+        Watchdog::new(self.idle, Arc::clone(&self.on_idle))
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Returns how much longer the handle may stay idle, or `None` once it has been
+    /// idle for too long.
+    fn remaining(&self) -> Option<Duration> {
+        if self.fired.load(Ordering::Relaxed) {
+            return None;
+        }
+        let idle_for = self.last_activity.lock().unwrap().elapsed();
+        self.idle
+            .checked_sub(idle_for)
+            .filter(|d| *d > Duration::new(0, 0))
+    }
+
+    /// Call the callback and shut `fd` down, the first time the handle is found to
+    /// have been idle for too long.
+    fn fire(&self, fd: &impl AsFd) -> Error {
+        if !self.fired.swap(true, Ordering::Relaxed) {
+            (self.on_idle)();
+            utils::shutdown(fd);
+        }
+        Error::new(ErrorKind::TimedOut, "idle for too long")
+    }
+}
+
+/// Keepalive bytes written to the handle at a fixed interval while a read waits.
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: c_int,
+    message: Box<[u8]>,
+    /// Held by writes through the wrapper and its clones, so that a beat never lands
+    /// in the middle of one.
+    writing: Mutex<()>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: c_int, message: &[u8]) -> Heartbeat {
+        Heartbeat {
+            interval,
+            message: message.into(),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Keep beats from being sent until the guard is dropped.
+    pub fn hold(&self) -> MutexGuard<'_, ()> {
+        self.writing.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn interval(&self) -> Duration {
+        utils::ms_to_duration(self.interval)
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Wait with `poll` up to `timeout`, or indefinitely if it is `None`, sending the
+    /// message to `fd` each time an interval passes without the wait ending.
+    fn wait<F>(&self, timeout: Option<c_int>, fd: &impl AsFd, mut poll: F) -> Result<()>
+    where
+        F: FnMut(Option<c_int>) -> Result<()>,
+    {
+        let deadline = timeout.map(|ms| Instant::now() + utils::ms_to_duration(ms));
+        loop {
+            let left = deadline.map(|deadline| {
+                utils::duration_to_ms(deadline.saturating_duration_since(Instant::now()))
+            });
+            let slice = left.map_or(self.interval, |left| left.min(self.interval));
+            match poll(Some(slice)) {
+                Err(ref e)
+                    if e.kind() == ErrorKind::TimedOut && left.is_none_or(|left| left > slice) =>
+                {
+                    self.send(fd)?
+                }
+                rslt => return rslt,
+            }
+        }
+    }
+
+    /// Write the message straight to `fd` with a single call, unless a write through
+    /// the wrapper is in progress or `fd` cannot take it right now, in which case this
+    /// beat is skipped.
+    ///
+    /// Sockets are sent to without blocking. Other handles are written to once they
+    /// poll writable, which only blocks if the message is larger than the room they
+    /// guarantee, such as `PIPE_BUF` for pipes.
+    fn send(&self, fd: &impl AsFd) -> Result<()> {
+        let _writing = match self.writing.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(()),
+        };
+        if !utils::is_ready(fd, PollFlags::POLLOUT)? {
+            return Ok(());
+        }
+        let rslt = loop {
+            let rslt = match socket::send(
+                fd.as_fd().as_raw_fd(),
+                &self.message,
+                MsgFlags::MSG_DONTWAIT,
+            ) {
+                Err(Errno::ENOTSOCK) => unistd::write(fd, &self.message),
+                rslt => rslt,
+            };
+            if rslt != Err(Errno::EINTR) {
+                break rslt;
+            }
+        };
+        match rslt {
+            Ok(n) if n == self.message.len() => Ok(()),
+            Ok(_) => Err(Error::other("heartbeat was only partly written")),
+            Err(Errno::EAGAIN) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Spurious timeouts injected at random, for soak testing.
+#[cfg(feature = "chaos")]
+#[derive(Debug)]
+pub struct Chaos {
+    threshold: u64,
+    state: AtomicU64,
+}
+
+#[cfg(feature = "chaos")]
+impl Chaos {
+    pub fn new(probability: f64) -> Chaos {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "chaos probability must be between 0 and 1"
+        );
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Chaos {
+            threshold: (probability * u64::MAX as f64) as u64,
+            // Xorshift gets stuck at zero.
+            state: AtomicU64::new(seed | 1),
+        }
+    }
+
+    /// Fail with a spurious timeout if this operation is one of the unlucky ones.
+    fn strike(&self) -> Result<()> {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let prev = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap_or_else(|x| x);
+        // The generator never produces zero, so a threshold of zero never strikes.
+        if step(prev) <= self.threshold {
+            Err(Error::new(ErrorKind::TimedOut, "injected timeout"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The running totals behind `IoStats`.
+#[derive(Debug, Default)]
+pub struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    timeouts: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+impl Counters {
+    pub fn snapshot(&self) -> IoStats {
+        IoStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Waiter {
+    /// Returns a copy of this state for a newly created wrapper, which keeps its own
+    /// statistics.
+    pub fn for_new_wrapper(&self) -> Waiter {
+        Waiter {
+            counters: Arc::default(),
+            watchdog: self.watchdog.as_ref().map(|w| Arc::new(w.fresh())),
+            stall: self.stall.as_ref().map(|s| Arc::new(s.fresh())),
+            meter: self.meter.as_ref().map(|m| Arc::new(m.fresh())),
+            ..self.clone()
+        }
+    }
+
+    /// Note the start of a `read` or `write`, if anything needs to know how long it
+    /// takes.
+    pub fn start(&self) -> Option<Instant> {
+        self.sink.as_ref().map(|_| Instant::now())
+    }
+
+    /// Wait until `handle` is ready for `direction`, up to `timeout`, then perform
+    /// `op` on it and account for the operation.
+    ///
+    /// This is the whole of a `read` or `write` for wrappers that wait with `poll`.
+    pub fn perform<T, F>(
+        &self,
+        direction: Direction,
+        timeout: Option<c_int>,
+        handle: &mut T,
+        op: F,
+    ) -> Result<usize>
+    where
+        T: AsFd,
+        F: FnOnce(&mut T) -> Result<usize>,
+    {
+        let start = self.start();
+        let rslt = self
+            .wait_until_ready(timeout, handle, events(direction))
+            .and_then(|_| op(handle));
+        self.record(direction, start, rslt)
+    }
+
+    /// Wait until `handle` is writable, up to `timeout`, then flush it with `op` and
+    /// account for the flush.
+    pub fn perform_flush<T, F>(&self, timeout: Option<c_int>, handle: &mut T, op: F) -> Result<()>
+    where
+        T: AsFd,
+        F: FnOnce(&mut T) -> Result<()>,
+    {
+        let rslt = self
+            .wait_until_ready(timeout, handle, PollFlags::POLLOUT)
+            .and_then(|_| op(handle));
+        self.record_flush(rslt)
+    }
+
+    /// Account for a finished `read` or `write` that began at `start`, passing its
+    /// result through.
+    pub fn record(
+        &self,
+        direction: Direction,
+        start: Option<Instant>,
+        rslt: Result<usize>,
+    ) -> Result<usize> {
+        if let (Some(sink), Some(start)) = (self.sink.as_ref(), start) {
+            sink.observe(&Observation {
+                name: self.name.as_deref(),
+                direction,
+                latency: start.elapsed(),
+                outcome: match rslt {
+                    Ok(_) => Outcome::Success,
+                    Err(ref e) if e.kind() == ErrorKind::TimedOut => Outcome::TimedOut,
+                    Err(ref e) => Outcome::Failed(e.kind()),
+                },
+                bytes: *rslt.as_ref().unwrap_or(&0),
+            });
+        }
+
+        let (ops, bytes) = match direction {
+            Direction::Read => (&self.counters.reads, &self.counters.bytes_read),
+            Direction::Write => (&self.counters.writes, &self.counters.bytes_written),
+        };
+        ops.fetch_add(1, Ordering::Relaxed);
+        match rslt {
+            Ok(n) => {
+                bytes.fetch_add(n as u64, Ordering::Relaxed);
+                if let Some(ref stall) = self.stall {
+                    stall.record(n);
+                }
+                if let Some(ref meter) = self.meter {
+                    meter.record(n);
+                }
+                self.touch();
+            }
+            Err(ref e) => self.record_error(e),
+        }
+        rslt
+    }
+
+    /// Account for a finished `flush`, passing its result through.
+    pub fn record_flush(&self, rslt: Result<()>) -> Result<()> {
+        match rslt {
+            Ok(()) => self.touch(),
+            Err(ref e) => self.record_error(e),
+        }
+        rslt
+    }
+
+    /// Note successful I/O for the watchdog.
+    fn touch(&self) {
+        if let Some(ref watchdog) = self.watchdog {
+            watchdog.touch();
+        }
+    }
+
+    fn record_error(&self, e: &Error) {
+        if e.kind() == ErrorKind::TimedOut {
+            self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Keep heartbeats from being sent, if there are any, until the guard is dropped.
+    pub fn hold_heartbeats(&self) -> Option<MutexGuard<'_, ()>> {
+        self.heartbeat.as_ref().map(|heartbeat| heartbeat.hold())
+    }
+
+    /// Wait until `fd` receives one of `events`, up to `timeout` or whatever is left
+    /// of the budget, whichever is shorter.
+    pub fn wait_until_ready(
+        &self,
+        timeout: Option<c_int>,
+        fd: &impl AsFd,
+        events: PollFlags,
+    ) -> Result<()> {
+        let rslt = self.wait_limited(timeout, fd, events);
+        self.close_if_timed_out(fd, rslt)
+    }
+
+    fn wait_limited(
+        &self,
+        timeout: Option<c_int>,
+        fd: &impl AsFd,
+        events: PollFlags,
+    ) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        self.inject_chaos()?;
+        let mut retries = self.retry.retries;
+        loop {
+            let budget = self.remaining_budget()?;
+            let idle = self.remaining_idle(fd)?;
+            let capped = self.remaining_wait_cap()?;
+            let direction = direction(events);
+            let events = self.wait_events(direction, events);
+            // A stalled transfer may still use data that is already there, which is how
+            // its rate picks up again; only a wait that would block fails.
+            let stall = match self.remaining_before_stall() {
+                Ok(stall) => stall,
+                Err(_) if utils::is_ready(fd, events)? => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let limited = [timeout, budget, idle, stall, capped]
+                .iter()
+                .flatten()
+                .min()
+                .cloned();
+            let heartbeat = match self.heartbeat {
+                Some(ref heartbeat) if direction == Direction::Read => Some(heartbeat),
+                _ => None,
+            };
+
+            // Without a timeout there is nothing to wait for, unless heartbeats are due.
+            if limited.is_none() && heartbeat.is_none() {
+                return Ok(());
+            }
+
+            let expected = limited.map_or(Duration::MAX, utils::ms_to_duration);
+            self.wait_started(fd, direction, expected);
+            let start = Instant::now();
+            let rslt = match heartbeat {
+                Some(heartbeat) => {
+                    heartbeat.wait(limited, fd, |slice| self.poll(slice, fd, events))
+                }
+                None => self.poll(limited, fd, events),
+            };
+            self.wait_finished(fd, direction, start.elapsed(), &rslt);
+
+            match rslt {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut && limited != timeout => {
+                    // Cut short by the watchdog, the stall detector, the wait cap, or the
+                    // budget.
+                    self.remaining_idle(fd)?;
+                    self.remaining_before_stall()?;
+                    self.remaining_wait_cap()?;
+                    return Err(budget_exhausted());
+                }
+                // Only the wrapper's own timeout is worth another try.
+                Err(ref e) if e.kind() == ErrorKind::TimedOut && retries > 0 => {
+                    retries -= 1;
+                    self.pause_before_retry();
+                }
+                rslt => return rslt,
+            }
+        }
+    }
+
+    /// Sleep for the retry policy's backoff, or until the budget runs out if that is
+    /// sooner.
+    fn pause_before_retry(&self) {
+        let pause = match self.budget {
+            Some(ref budget) => cmp::min(self.retry.backoff, budget.remaining()),
+            None => self.retry.backoff,
+        };
+        if !pause.is_zero() {
+            thread::sleep(pause);
+        }
+    }
+
+    /// The events to wait for in place of `events` when waiting for `direction`,
+    /// taking the configured event masks and priority data into account.
+    fn wait_events(&self, direction: Direction, events: PollFlags) -> PollFlags {
+        match direction {
+            Direction::Read => {
+                let events = self.read_events.unwrap_or(events);
+                if self.priority {
+                    events | PollFlags::POLLPRI
+                } else {
+                    events
+                }
+            }
+            Direction::Write => self.write_events.unwrap_or(events),
+        }
+    }
+
+    /// Wait for `fd` itself, through the reactor if there is one.
+    fn poll(&self, timeout: Option<c_int>, fd: &impl AsFd, events: PollFlags) -> Result<()> {
+        #[cfg(feature = "reactor")]
+        if let Some(ref reactor) = self.reactor {
+            return reactor.wait_until_ready(timeout, fd, events);
+        }
+        utils::wait_until_ready(timeout, fd, events)
+    }
+
+    fn wait_started(&self, fd: &impl AsFd, direction: Direction, timeout: Duration) {
+        if let Some(ref observer) = self.observer {
+            observer.on_wait_start(direction, timeout);
+        }
+        #[cfg(feature = "log")]
+        debug!(
+            "{}: waiting up to {:?} to {}",
+            self.describe(fd),
+            timeout,
+            verb(direction)
+        );
+        #[cfg(not(feature = "log"))]
+        let _ = fd;
+    }
+
+    fn wait_finished(
+        &self,
+        fd: &impl AsFd,
+        direction: Direction,
+        elapsed: Duration,
+        rslt: &Result<()>,
+    ) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.counters.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
+
+        let timed_out = match *rslt {
+            Err(ref e) => e.kind() == ErrorKind::TimedOut,
+            Ok(()) => false,
+        };
+        if let Some(ref observer) = self.observer {
+            if timed_out {
+                observer.on_timeout(direction, elapsed);
+            } else {
+                observer.on_wait_end(direction, elapsed);
+            }
+        }
+        #[cfg(feature = "log")]
+        match *rslt {
+            Ok(()) => debug!(
+                "{}: ready to {} after {:?}",
+                self.describe(fd),
+                verb(direction),
+                elapsed
+            ),
+            Err(_) if timed_out => debug!(
+                "{}: timed out after {:?} waiting to {}",
+                self.describe(fd),
+                elapsed,
+                verb(direction)
+            ),
+            Err(ref e) => debug!(
+                "{}: waiting to {} failed after {:?}: {}",
+                self.describe(fd),
+                verb(direction),
+                elapsed,
+                e
+            ),
+        }
+        #[cfg(not(feature = "log"))]
+        let _ = fd;
+    }
+
+    /// Identify the wrapper in log records by its name, or else its descriptor.
+    #[cfg(feature = "log")]
+    fn describe(&self, fd: &impl AsFd) -> String {
+        use std::os::fd::AsRawFd;
+
+        match self.name {
+            Some(ref name) => name.to_string(),
+            None => format!("fd {}", fd.as_fd().as_raw_fd()),
+        }
+    }
+
+    /// Fail if the budget has run out or `fd` has been idle for too long, for
+    /// operations that do not wait with `poll`.
+    pub fn check_limits(&self, fd: &impl AsFd) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        let rslt = self.inject_chaos();
+        #[cfg(not(feature = "chaos"))]
+        let rslt = Ok(());
+        let rslt = rslt
+            .and_then(|_| self.remaining_budget())
+            .and_then(|_| self.remaining_idle(fd));
+        self.close_if_timed_out(fd, rslt.map(|_| ()))
+    }
+
+    /// Shut `fd` down if `rslt` is a timeout and the wrapper was asked to close on
+    /// timeouts, passing `rslt` through.
+    pub fn close_if_timed_out<T>(&self, fd: &impl AsFd, rslt: Result<T>) -> Result<T> {
+        match rslt {
+            Err(ref e) if self.close_on_timeout && e.kind() == ErrorKind::TimedOut => {
+                utils::shutdown(fd)
+            }
+            _ => {}
+        }
+        rslt
+    }
+
+    #[cfg(feature = "chaos")]
+    fn inject_chaos(&self) -> Result<()> {
+        match self.chaos {
+            Some(ref chaos) => chaos.strike(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the time left in the budget in milliseconds, or an error if it has
+    /// run out.
+    fn remaining_budget(&self) -> Result<Option<c_int>> {
+        match self.budget {
+            Some(ref budget) if budget.is_exhausted() => Err(budget_exhausted()),
+            // Round up, so that a wait cut short by the budget ends after it has run
+            // out rather than just before.
+            Some(ref budget) => Ok(Some(utils::duration_to_ms_ceil(budget.remaining()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns how much longer the wrapper may spend waiting altogether in
+    /// milliseconds, or an error once it has used up its cap.
+    fn remaining_wait_cap(&self) -> Result<Option<c_int>> {
+        let cap = match self.wait_cap {
+            Some(cap) => cap,
+            None => return Ok(None),
+        };
+        let waited = Duration::from_nanos(self.counters.wait_nanos.load(Ordering::Relaxed));
+        match cap.checked_sub(waited).filter(|left| !left.is_zero()) {
+            // Round up, like the budget.
+            Some(left) => Ok(Some(utils::duration_to_ms_ceil(left))),
+            None => Err(Error::new(ErrorKind::TimedOut, "total wait time exhausted")),
+        }
+    }
+
+    /// Returns how much longer the transfer may go without data before it counts as
+    /// stalled in milliseconds, or an error once it has.
+    fn remaining_before_stall(&self) -> Result<Option<c_int>> {
+        match self.stall {
+            // Round up, like the budget.
+            Some(ref stall) => Ok(Some(utils::duration_to_ms_ceil(stall.remaining()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns how much longer `fd` may stay idle in milliseconds, or an error once
+    /// the watchdog has shut it down.
+    fn remaining_idle(&self, fd: &impl AsFd) -> Result<Option<c_int>> {
+        match self.watchdog {
+            Some(ref watchdog) => match watchdog.remaining() {
+                // Round up, like the budget.
+                Some(remaining) => Ok(Some(utils::duration_to_ms_ceil(remaining))),
+                None => Err(watchdog.fire(fd)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+fn events(direction: Direction) -> PollFlags {
+    match direction {
+        Direction::Read => PollFlags::POLLIN,
+        Direction::Write => PollFlags::POLLOUT,
+    }
+}
+
+fn direction(events: PollFlags) -> Direction {
+    if events.contains(PollFlags::POLLOUT) {
+        Direction::Write
+    } else {
+        Direction::Read
+    }
+}
+
+#[cfg(feature = "log")]
+fn verb(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Read => "read",
+        Direction::Write => "write",
+    }
+}
+
+fn budget_exhausted() -> Error {
+    Error::new(ErrorKind::TimedOut, "time budget exhausted")
+}
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use log::{self, Level, LevelFilter, Log, Metadata, Record};
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::super::TimeoutBuilder;
+
+    struct Capture(Mutex<Vec<String>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Debug
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    lazy_static! {
+        static ref CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+    }
+
+    #[test]
+    fn log_records_for_timed_out_wait() {
+        log::set_logger(&*CAPTURE).unwrap();
+        log::set_max_level(LevelFilter::Debug);
+
+        let (left, _right) = UnixStream::pair().unwrap();
+        let mut rdr = TimeoutBuilder::new()
+            .name("upstream")
+            .read_timeout(Duration::from_millis(50))
+            .reader(left);
+        let mut buf = [0u8; 1];
+        assert_eq!(ErrorKind::TimedOut, rdr.read(&mut buf).unwrap_err().kind());
+
+        let records = CAPTURE.0.lock().unwrap();
+        let records: Vec<_> = records
+            .iter()
+            .filter(|r| r.starts_with("upstream: "))
+            .collect();
+        assert_eq!(2, records.len());
+        assert_eq!("upstream: waiting up to 50ms to read", records[0]);
+        assert!(records[1].starts_with("upstream: timed out after "));
+    }
+}
@@ -6,16 +6,19 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use nix::libc::c_int;
-use nix::poll::PollFlags;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
 use std::io::Result;
-use std::io::Seek;
-use std::io::SeekFrom;
 use std::io::Write;
 use std::os::fd::AsFd;
-use std::os::fd::BorrowedFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::OwnedFd;
 use std::time::Duration;
 
+use super::config;
+use super::observe::Direction;
+use super::timeout::{Timeout, Writing};
 use super::utils;
 
 /// The `TimeoutWriter` struct adds write timeouts to any writer.
@@ -28,56 +31,81 @@ use super::utils;
 /// an `io::ErrorKind::TimedOut` variant as the value of `io::Error`. All other
 /// error values that would normally be produced by the underlying implementation
 /// of the `Write` trait could also be produced by the `TimeoutWriter`.
-pub struct TimeoutWriter<H>
-where
-    H: Write + AsFd,
-{
-    timeout: Option<c_int>,
-    handle: H,
-}
+///
+/// This is the generic `Timeout` wrapper for the `Writing` direction, which is where
+/// the methods it shares with `TimeoutReader` and `TimeoutStream` are documented.
+pub type TimeoutWriter<H> = Timeout<H, Writing>;
 
 impl<H> Write for TimeoutWriter<H>
 where
     H: Write + AsFd,
 {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        utils::wait_until_ready(self.timeout, &self.handle, PollFlags::POLLOUT)?;
-        self.handle.write(buf)
+        self.waiter.perform(
+            Direction::Write,
+            self.write_timeout,
+            &mut self.handle,
+            |h| h.write(buf),
+        )
     }
 
     fn flush(&mut self) -> Result<()> {
-        utils::wait_until_ready(self.timeout, &self.handle, PollFlags::POLLOUT)?;
-        self.handle.flush()
+        let timeout = self.flush_timeout.or(self.write_timeout);
+        self.waiter
+            .perform_flush(timeout, &mut self.handle, |h| h.flush())
     }
 }
 
-impl<H> Seek for TimeoutWriter<H>
+/// Shared references can be written to whenever the handle allows it, so a writer
+/// can be used from several places at once just like a `&TcpStream`.
+impl<H> Write for &TimeoutWriter<H>
 where
-    H: Write + AsFd + Seek,
+    H: Write + AsFd,
+    for<'b> &'b H: Write,
 {
-    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.handle.seek(pos)
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut handle = &self.handle;
+        self.waiter
+            .perform(Direction::Write, self.write_timeout, &mut handle, |h| {
+                h.write(buf)
+            })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut handle = &self.handle;
+        let timeout = self.flush_timeout.or(self.write_timeout);
+        self.waiter
+            .perform_flush(timeout, &mut handle, |h| h.flush())
     }
 }
 
-impl<H> AsFd for TimeoutWriter<H>
+/// Handles that can also be read from, such as sockets, stay readable through the
+/// writer. Reads wait for the handle up to the read timeout, which is unset unless
+/// `set_read_timeout` is called.
+impl<H> Read for TimeoutWriter<H>
 where
-    H: Write + AsFd,
+    H: Read + Write + AsFd,
 {
-    fn as_fd(&self) -> BorrowedFd<'_> {
-        self.handle.as_fd()
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.waiter
+            .perform(Direction::Read, self.read_timeout, &mut self.handle, |h| {
+                h.read(buf)
+            })
     }
 }
 
-impl<H> Clone for TimeoutWriter<H>
+/// Shows the descriptor rather than the handle, so that every `TimeoutWriter` can be
+/// debugged, whatever it wraps.
+impl<H> fmt::Debug for TimeoutWriter<H>
 where
-    H: Write + AsFd + Clone,
+    H: Write + AsFd,
 {
-    fn clone(&self) -> TimeoutWriter<H> {
-        TimeoutWriter {
-            handle: self.handle.clone(),
-            ..*self
-        }
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutWriter")
+            .field("fd", &self.as_raw_fd())
+            .field("timeout", &self.timeout())
+            .field("flush_timeout", &self.flush_timeout())
+            .finish()
     }
 }
 
@@ -118,10 +146,190 @@ where
     /// ```
     pub fn new<T: Into<Option<Duration>>>(handle: H, timeout: T) -> TimeoutWriter<H> {
         TimeoutWriter {
-            timeout: timeout.into().map(utils::duration_to_ms),
-            handle,
+            write_timeout: utils::initial_timeout(timeout.into()),
+            ..Timeout::bare(handle)
         }
     }
+
+    /// Create a new `TimeoutWriter`, falling back to the default timeout from the
+    /// `TIMEOUT_READWRITE_DEFAULT_MS` environment variable when `timeout` is `None`.
+    ///
+    /// This lets operators tune timeouts without a rebuild. If the variable is not
+    /// set, or does not hold a whole number of milliseconds, the `TimeoutWriter` has no
+    /// timeout, just as with `TimeoutWriter::new`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutWriter;
+    /// use std::fs::File;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut f = File::open("file.txt")?;
+    /// let mut wtr = TimeoutWriter::new_with_default(f, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_default<T: Into<Option<Duration>>>(handle: H, timeout: T) -> TimeoutWriter<H> {
+        let timeout = timeout.into().or_else(config::env_default_timeout);
+        TimeoutWriter::new(handle, timeout)
+    }
+
+    /// Returns the timeout of this writer.
+    ///
+    /// If the timeout is `None`, then `write` calls will block indefinitely. The
+    /// timeout is kept with millisecond precision, so the value returned may be
+    /// truncated from the one that was set.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.write_timeout.map(utils::ms_to_duration)
+    }
+
+    /// Sets the timeout of this writer.
+    ///
+    /// If the value specified is `None`, then `write` calls will block indefinitely.
+    /// An `Err` is returned if the zero `Duration` is passed to this method.
+    pub fn set_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.write_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Sets a separate timeout for `flush`, or `None` to use the write timeout.
+    ///
+    /// Flushing can take far longer than any single write, for instance when a pipe
+    /// has to drain into a slow consumer, so it often deserves a larger allowance.
+    /// An `Err` is returned if the zero `Duration` is passed to this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutWriter;
+    /// use std::fs::File;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut wtr = TimeoutWriter::new(File::create("file.txt")?, Duration::new(1, 0));
+    /// wtr.set_flush_timeout(Some(Duration::new(30, 0)))?;
+    /// assert_eq!(Some(Duration::new(30, 0)), wtr.flush_timeout());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_flush_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.flush_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Returns the timeout that `flush` uses, which is the write timeout unless a
+    /// separate flush timeout was set.
+    pub fn flush_timeout(&self) -> Option<Duration> {
+        self.flush_timeout
+            .or(self.write_timeout)
+            .map(utils::ms_to_duration)
+    }
+
+    /// Sets the timeout for reading through this writer, for handles that can also be
+    /// read from.
+    ///
+    /// If the value specified is `None`, which is the default, `read` calls will
+    /// block indefinitely. An `Err` is returned if the zero `Duration` is passed to
+    /// this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutWriter;
+    /// use std::io::{Read, Write};
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:8080")?;
+    /// let mut wtr = TimeoutWriter::new(stream, Duration::new(1, 0));
+    /// wtr.set_read_timeout(Some(Duration::new(5, 0)))?;
+    ///
+    /// wtr.write_all(b"ping")?;
+    /// let mut reply = [0u8; 4];
+    /// wtr.read_exact(&mut reply)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> Result<()> {
+        self.read_timeout = utils::checked_timeout(dur)?;
+        Ok(())
+    }
+
+    /// Returns the timeout for reading through this writer.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.map(utils::ms_to_duration)
+    }
+
+    /// Sets the flush timeout the way constructors set timeouts, for
+    /// `TimeoutBuilder` and `TimeoutConfig`.
+    pub(crate) fn set_initial_flush_timeout(&mut self, timeout: Option<Duration>) {
+        self.flush_timeout = timeout.and_then(|t| utils::initial_timeout(Some(t)));
+    }
+}
+
+impl TimeoutWriter<File> {
+    /// Create a new `TimeoutWriter` with an optional timeout that takes ownership of a
+    /// bare file descriptor, such as one received from another process.
+    ///
+    /// The descriptor is written through a `File`, which works for any kind of
+    /// descriptor, not just regular files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutWriter;
+    /// use std::os::fd::OwnedFd;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo(fd: OwnedFd) -> std::io::Result<()> {
+    /// let mut wtr = TimeoutWriter::from_owned_fd(fd, Duration::new(5, 0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_owned_fd<T: Into<Option<Duration>>>(
+        fd: OwnedFd,
+        timeout: T,
+    ) -> TimeoutWriter<File> {
+        TimeoutWriter::new(File::from(fd), timeout)
+    }
+
+    /// Create a new `TimeoutWriter` with an optional timeout around a duplicate of the
+    /// descriptor behind `handle`, leaving `handle` itself with the caller.
+    ///
+    /// The `TimeoutWriter` owns the duplicate, so it can be moved to another thread or
+    /// outlive `handle`, and its close-on-exec flag is its own. Note that a duplicate
+    /// still shares the file offset and file status flags such as `O_NONBLOCK` with
+    /// `handle`, since those belong to the open file rather than to the descriptor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timeout_readwrite::TimeoutWriter;
+    /// use std::io;
+    /// use std::time::Duration;
+    ///
+    /// # fn foo() -> std::io::Result<()> {
+    /// let mut wtr = TimeoutWriter::new_dup(&io::stdout(), Duration::new(5, 0))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_dup<F, T>(handle: &F, timeout: T) -> Result<TimeoutWriter<File>>
+    where
+        F: AsFd,
+        T: Into<Option<Duration>>,
+    {
+        let fd = handle.as_fd().try_clone_to_owned()?;
+        Ok(TimeoutWriter::from_owned_fd(fd, timeout))
+    }
+}
+
+impl From<OwnedFd> for TimeoutWriter<File> {
+    /// Take ownership of a bare file descriptor, without a timeout.
+    fn from(fd: OwnedFd) -> TimeoutWriter<File> {
+        TimeoutWriter::from_owned_fd(fd, None)
+    }
 }
 
 pub trait TimeoutWriteExt<H>
@@ -139,3 +347,69 @@ where
         TimeoutWriter::new(self, timeout)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::super::testing;
+    use super::*;
+
+    #[test]
+    fn try_write_never_waits() {
+        let (mock, mut control) = testing::mock_pollable().unwrap();
+        let mut wtr = TimeoutWriter::new(mock, Duration::new(5, 0));
+
+        control.block_writes().unwrap();
+        let err = wtr.try_write(b"data").unwrap_err();
+        assert_eq!(ErrorKind::WouldBlock, err.kind());
+
+        control.unblock_writes().unwrap();
+        assert_eq!(4, wtr.try_write(b"data").unwrap());
+        assert_eq!(b"data", &control.take_written().unwrap()[..]);
+        assert_eq!(1, wtr.stats().writes);
+    }
+
+    #[test]
+    fn reads_pass_through_with_read_timeout() {
+        let (mock, control) = testing::mock_pollable().unwrap();
+        let mut wtr = TimeoutWriter::new(mock, Duration::new(5, 0));
+        wtr.set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(ErrorKind::TimedOut, wtr.read(&mut buf).unwrap_err().kind());
+
+        wtr.write_all(b"ping").unwrap();
+        assert_eq!(b"ping", &control.take_written().unwrap()[..]);
+        control.make_readable(b"pong").unwrap();
+        wtr.read_exact(&mut buf).unwrap();
+        assert_eq!(b"pong", &buf);
+    }
+
+    #[test]
+    fn shutdown_ends_the_peer_read() {
+        use std::fs::File;
+        use std::net::Shutdown;
+        use std::os::unix::net::UnixStream;
+        use TimeoutReader;
+
+        let (left, right) = UnixStream::pair().unwrap();
+        let mut wtr = TimeoutWriter::new(left, Duration::new(5, 0));
+        let mut rdr = TimeoutReader::new(right, Duration::new(5, 0));
+
+        wtr.write_all(b"last").unwrap();
+        wtr.shutdown(Shutdown::Write).unwrap();
+        let mut buf = Vec::new();
+        rdr.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"last", &buf[..]);
+        assert_eq!(ErrorKind::BrokenPipe, wtr.write(b"x").unwrap_err().kind());
+
+        rdr.shutdown(Shutdown::Read).unwrap();
+        assert_eq!(0, rdr.read(&mut [0u8; 4]).unwrap());
+
+        let wtr = TimeoutWriter::new(File::create("/dev/null").unwrap(), None);
+        assert!(wtr.shutdown(Shutdown::Both).is_err());
+    }
+}